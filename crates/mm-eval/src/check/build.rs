@@ -1,9 +1,14 @@
+use std::collections::HashMap;
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+
 use super::equation::Variable;
 use super::Checker;
 use crate::check::equation::{Sum, Term};
 use crate::implicit::Melody;
 use crate::note::Note;
-use crate::{melody, Allocator, Factor, Length};
+use crate::{melody, Allocator, Factor, Length, Name};
 
 impl<N, Id, A> Checker<'_, N, Id, A>
 where
@@ -11,14 +16,26 @@ where
     A: Allocator<melody::Melody<N, Id, A>>,
     A: Allocator<Melody<N, Id, A>>,
 {
-    pub fn build_equation(&self, melody: &Melody<N, Id, A>) -> Vec<Sum> {
-        self.build(&Factor::one(), melody)
+    /// Build the sums of an equation for the length of `melody`, where `params`
+    /// binds the parameters of its definition to length variables.
+    pub fn build_equation(
+        &self,
+        params: &HashMap<Name, Variable>,
+        melody: &Melody<N, Id, A>,
+    ) -> Vec<Sum> {
+        self.build(params, &Factor::one(), melody)
     }
 
-    fn build(&self, factor: &Factor, melody: &Melody<N, Id, A>) -> Vec<Sum> {
+    fn build(
+        &self,
+        params: &HashMap<Name, Variable>,
+        factor: &Factor,
+        melody: &Melody<N, Id, A>,
+    ) -> Vec<Sum> {
         match melody {
             Melody::Pause(_) => Self::constant(factor * &Length::one()),
             Melody::Note(_, _) => Self::constant(factor * &Length::one()),
+            Melody::Degree(_, _) => Self::constant(factor * &Length::one()),
 
             Melody::Name(_, name) => Self::variable(
                 factor.clone(),
@@ -28,24 +45,84 @@ where
                     .expect("all names are bound to a var before use"),
             ),
 
+            Melody::Parameter(_, name) => Self::variable(
+                factor.clone(),
+                *params
+                    .get(name)
+                    .expect("all parameters are bound to a var before use"),
+            ),
+
+            Melody::Call(_, name, args) => {
+                let var = *self
+                    .context
+                    .get(name)
+                    .expect("all names are bound to a var before use");
+
+                // Calls within the same component have no template yet, but
+                // pass their parameters along unchanged.
+                let Some(template) = self.templates.get(&var) else {
+                    return Self::variable(factor.clone(), var);
+                };
+
+                let args: HashMap<_, _> = template
+                    .params
+                    .iter()
+                    .zip(A::as_slice(args))
+                    .map(|(param, arg)| (*param, self.build(params, &Factor::one(), arg)))
+                    .collect();
+
+                Self::max(template.sums.iter().map(|sum| {
+                    Self::sum(sum.terms.iter().map(|term| match term {
+                        Term::Constant(length) => Self::constant(factor * length),
+                        Term::Variable(by, param) => Self::scale(
+                            &(factor * by),
+                            args.get(param).expect("all parameters are given arguments"),
+                        ),
+                    }))
+                }))
+            }
+
             Melody::Scale(_, scale, melody) => {
                 let factor = factor * scale;
-                self.build(&factor, A::as_ref(melody))
+                self.build(params, &factor, A::as_ref(melody))
             }
 
-            Melody::Sharp(_, _, melody) => self.build(factor, A::as_ref(melody)),
-            Melody::Offset(_, _, melody) => self.build(factor, A::as_ref(melody)),
+            Melody::Sharp(_, _, melody) => self.build(params, factor, A::as_ref(melody)),
+            Melody::Offset(_, _, melody) => self.build(params, factor, A::as_ref(melody)),
+            Melody::Dynamic(_, _, melody) => self.build(params, factor, A::as_ref(melody)),
+
+            Melody::Repeat(_, Some(0), _) => Self::constant(Length::zero()),
+            Melody::Repeat(_, Some(count), melody) => {
+                let factor = factor * &Factor(BigRational::from_integer(BigInt::from(*count)));
+                self.build(params, &factor, A::as_ref(melody))
+            }
+
+            Melody::Repeat(_, None, _) => Self::constant(Length::Unbounded),
+            Melody::Reverse(_, melody) => self.build(params, factor, A::as_ref(melody)),
+            Melody::Invert(_, _, melody) => self.build(params, factor, A::as_ref(melody)),
+            Melody::Tempo(_, _, _, melody) => self.build(params, factor, A::as_ref(melody)),
+            Melody::Voice(_, _, melody) => self.build(params, factor, A::as_ref(melody)),
+            Melody::Slice(_, from, to, _) => {
+                Self::constant(factor * &Length::Bounded(&to.0 - &from.0))
+            }
+            Melody::Fit(_, length, _) => {
+                Self::constant(factor * &Length::Bounded(length.0.clone()))
+            }
+            Melody::Key(_, _, melody) => self.build(params, factor, A::as_ref(melody)),
+            Melody::Steps(_, _, melody) => self.build(params, factor, A::as_ref(melody)),
 
             Melody::Sequence(melodies) => Self::sum(
                 A::as_slice(melodies)
                     .iter()
-                    .map(|melody| self.build(factor, melody)),
+                    .map(|melody| self.build(params, factor, melody)),
             ),
 
-            Melody::Stack(melodies) => Self::max(
+            // A choice lasts as long as its longest melody, whichever is
+            // played, just like a stack.
+            Melody::Stack(melodies) | Melody::Choice(_, _, melodies) => Self::max(
                 A::as_slice(melodies)
                     .iter()
-                    .map(|melody| self.build(factor, melody)),
+                    .map(|melody| self.build(params, factor, melody)),
             ),
         }
     }
@@ -62,6 +139,21 @@ where
         }]
     }
 
+    fn scale(factor: &Factor, sums: &[Sum]) -> Vec<Sum> {
+        sums.iter()
+            .map(|sum| Sum {
+                terms: sum
+                    .terms
+                    .iter()
+                    .map(|term| match term {
+                        Term::Constant(length) => Term::Constant(factor * length),
+                        Term::Variable(by, var) => Term::Variable(factor * by, *var),
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
     fn max(parts: impl IntoIterator<Item = Vec<Sum>>) -> Vec<Sum> {
         parts.into_iter().flatten().collect()
     }
@@ -1,4 +1,4 @@
-use crate::{melody, Allocator, Factor, Length};
+use crate::{implicit, melody, Allocator, Factor, Length};
 
 use super::Checker;
 
@@ -23,7 +23,47 @@ pub enum Term {
     Variable(Factor, Variable),
 }
 
-impl<N, Id, A: Allocator<melody::Melody<N, Id, A>>> Checker<'_, N, Id, A> {
+/// The length of a parameterized definition, as the maximum value of one or
+/// more [`Sum`s](Sum) over the lengths of its parameters.
+pub struct Template {
+    pub params: Vec<Variable>,
+    pub sums: Vec<Sum>,
+}
+
+impl Template {
+    /// Compute the length of this template given the lengths of each of its
+    /// parameters.
+    pub fn apply(&self, lengths: &[Length]) -> Length {
+        debug_assert!(self.params.len() == lengths.len());
+
+        self.sums
+            .iter()
+            .map(|sum| {
+                sum.terms
+                    .iter()
+                    .map(|term| match term {
+                        Term::Constant(length) => length.clone(),
+                        Term::Variable(factor, var) => {
+                            let index = self
+                                .params
+                                .iter()
+                                .position(|param| param == var)
+                                .expect("templates only refer to their parameters");
+                            factor * &lengths[index]
+                        }
+                    })
+                    .fold(Length::zero(), |a, b| &a + &b)
+            })
+            .max()
+            .unwrap_or_else(Length::zero)
+    }
+}
+
+impl<N, Id, A> Checker<'_, N, Id, A>
+where
+    A: Allocator<implicit::Melody<N, Id, A>>,
+    A: Allocator<melody::Melody<N, Id, A>>,
+{
     /// Create a fresh and unique length variable.
     pub fn fresh(&mut self) -> Variable {
         let var = Variable(self.counter);
@@ -1,7 +1,11 @@
 use std::collections::HashSet;
 
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::Zero;
+
 use crate::note::Note;
-use crate::{implicit, melody, Allocator, Length, Name};
+use crate::{implicit, melody, Allocator, Factor, Length, Name};
 
 use super::{Checker, Error};
 
@@ -12,9 +16,12 @@ where
     A: Allocator<implicit::Melody<N, Id, A>>,
     A: Allocator<melody::Melody<N, Id, A>>,
 {
+    /// Lower `melody` into an explicit melody, where `arguments` gives the
+    /// lengths of the parameters of the definition it is part of.
     pub fn lower(
         &mut self,
-        component: &HashSet<&Name>,
+        component: &HashSet<Name>,
+        arguments: &[(Name, Length)],
         melody: &implicit::Melody<N, Id, A>,
     ) -> melody::Melody<N, Id, A> {
         let span = melody.span();
@@ -22,27 +29,58 @@ where
         let (node, length) = match melody {
             implicit::Melody::Pause(_) => (melody::Node::Pause, Length::one()),
             implicit::Melody::Note(_, note) => (melody::Node::Note(note.clone()), Length::one()),
+            implicit::Melody::Degree(_, degree) => (melody::Node::Degree(*degree), Length::one()),
 
             implicit::Melody::Name(_, name) => {
-                let var = self
-                    .context
-                    .get(name)
-                    .expect("all names are given variables");
-
-                let length = self
-                    .lengths
-                    .get(var)
-                    .expect("unknown names are reported before checking");
+                let length = self.length_of(name, &[]);
 
                 if component.contains(name) && !length.is_unbounded() {
-                    (melody::Node::Recur(*name), length.clone())
+                    (melody::Node::Recur(*name), length)
+                } else {
+                    (melody::Node::Name(*name), length)
+                }
+            }
+
+            implicit::Melody::Parameter(_, name) => {
+                let (index, (_, length)) = arguments
+                    .iter()
+                    .enumerate()
+                    .find(|(_, (param, _))| param == name)
+                    .expect("parameters are bound to their definition");
+
+                (melody::Node::Parameter(index), length.clone())
+            }
+
+            implicit::Melody::Call(_, name, _) if component.contains(name) => {
+                // Recursive calls pass their arguments along unchanged, so they
+                // can keep referring to the arguments of the caller.
+                let lengths: Vec<_> = arguments.iter().map(|(_, length)| length.clone()).collect();
+                let length = self.length_of(name, &lengths);
+                let instance = self.instantiate(*name, lengths);
+
+                if length.is_unbounded() {
+                    (melody::Node::Name(instance), length)
                 } else {
-                    (melody::Node::Name(*name), length.clone())
+                    (melody::Node::Recur(instance), length)
                 }
             }
 
+            implicit::Melody::Call(_, name, args) => {
+                let args: Vec<_> = A::as_slice(args)
+                    .iter()
+                    .map(|arg| self.lower(component, arguments, arg))
+                    .collect();
+
+                let lengths: Vec<_> = args.iter().map(|arg| arg.length.clone()).collect();
+                let length = self.length_of(name, &lengths);
+                let instance = self.instantiate(*name, lengths);
+                let args = self.alloc.pack_many(args);
+
+                (melody::Node::Call(instance, args), length)
+            }
+
             implicit::Melody::Scale(_, by, melody) => {
-                let melody = self.lower(component, A::as_ref(melody));
+                let melody = self.lower(component, arguments, A::as_ref(melody));
                 let length = by * &melody.length;
                 let melody = self.alloc.pack(melody);
 
@@ -50,7 +88,7 @@ where
             }
 
             implicit::Melody::Sharp(_, by, melody) => {
-                let melody = self.lower(component, A::as_ref(melody));
+                let melody = self.lower(component, arguments, A::as_ref(melody));
                 let length = melody.length.clone();
                 let melody = self.alloc.pack(melody);
 
@@ -58,17 +96,149 @@ where
             }
 
             implicit::Melody::Offset(_, by, melody) => {
-                let melody = self.lower(component, A::as_ref(melody));
+                let melody = self.lower(component, arguments, A::as_ref(melody));
                 let length = melody.length.clone();
                 let melody = self.alloc.pack(melody);
 
                 (melody::Node::Offset(*by, melody), length)
             }
 
+            implicit::Melody::Dynamic(_, by, melody) => {
+                let melody = self.lower(component, arguments, A::as_ref(melody));
+                let length = melody.length.clone();
+                let melody = self.alloc.pack(melody);
+
+                (melody::Node::Dynamic(by.clone(), melody), length)
+            }
+
+            implicit::Melody::Repeat(_, count, melody) => {
+                let melody = self.lower(component, arguments, A::as_ref(melody));
+
+                // Repeating an unbounded melody is like having it in the middle
+                // of a sequence.
+                if melody.length.is_unbounded() && count.is_none_or(|count| count > 1) {
+                    self.errors
+                        .push(Error::UnboundedNotLast(melody.span.clone()));
+                }
+
+                // Playing a melody no times takes no time, even if the melody
+                // itself never ends.
+                let length = match count {
+                    Some(0) => Length::zero(),
+                    Some(count) => {
+                        let count = Factor(BigRational::from_integer(BigInt::from(*count)));
+                        &count * &melody.length
+                    }
+
+                    None => Length::Unbounded,
+                };
+
+                let melody = self.alloc.pack(melody);
+                (melody::Node::Repeat(*count, melody), length)
+            }
+
+            implicit::Melody::Reverse(_, melody) => {
+                let melody = self.lower(component, arguments, A::as_ref(melody));
+                let length = melody.length.clone();
+
+                if length.is_unbounded() {
+                    self.errors
+                        .push(Error::UnboundedReverse(melody.span.clone()));
+                }
+
+                let melody = self.alloc.pack(melody);
+                (melody::Node::Reverse(melody), length)
+            }
+
+            implicit::Melody::Invert(_, axis, melody) => {
+                let melody = self.lower(component, arguments, A::as_ref(melody));
+                let length = melody.length.clone();
+                let melody = self.alloc.pack(melody);
+
+                (melody::Node::Invert(axis.clone(), melody), length)
+            }
+
+            implicit::Melody::Key(_, key, melody) => {
+                let melody = self.lower(component, arguments, A::as_ref(melody));
+                let length = melody.length.clone();
+                let melody = self.alloc.pack(melody);
+
+                (melody::Node::Key(key.clone(), melody), length)
+            }
+
+            implicit::Melody::Steps(_, by, melody) => {
+                let melody = self.lower(component, arguments, A::as_ref(melody));
+                let length = melody.length.clone();
+                let melody = self.alloc.pack(melody);
+
+                (melody::Node::Steps(*by, melody), length)
+            }
+
+            implicit::Melody::Tempo(_, from, to, melody) => {
+                let melody = self.lower(component, arguments, A::as_ref(melody));
+                let length = melody.length.clone();
+
+                // A gradual change of tempo needs an end to arrive at.
+                if length.is_unbounded() && from.as_ref() != Some(to) {
+                    self.errors
+                        .push(Error::UnboundedTempoChange(melody.span.clone()));
+                }
+
+                let melody = self.alloc.pack(melody);
+                (
+                    melody::Node::Tempo(from.clone(), to.clone(), melody),
+                    length,
+                )
+            }
+
+            implicit::Melody::Voice(_, voice, melody) => {
+                let melody = self.lower(component, arguments, A::as_ref(melody));
+                let length = melody.length.clone();
+                let melody = self.alloc.pack(melody);
+
+                (melody::Node::Voice(*voice, melody), length)
+            }
+
+            implicit::Melody::Slice(_, from, to, melody) => {
+                // The part of the melody outside of the slice is never played,
+                // so the melody itself may well be unbounded.
+                let melody = self.lower(component, arguments, A::as_ref(melody));
+                let melody = self.alloc.pack(melody);
+
+                (
+                    melody::Node::Slice(from.clone(), to.clone(), melody),
+                    Length::Bounded(&to.0 - &from.0),
+                )
+            }
+
+            implicit::Melody::Fit(_, length, melody) => {
+                let melody = self.lower(component, arguments, A::as_ref(melody));
+
+                let by = match &melody.length {
+                    Length::Bounded(inner) if inner.is_zero() => {
+                        self.errors.push(Error::EmptyFit(melody.span.clone()));
+                        Factor::one()
+                    }
+
+                    Length::Bounded(inner) => Factor(&length.0 / inner),
+
+                    Length::Unbounded => {
+                        self.errors.push(Error::UnboundedFit(melody.span.clone()));
+                        Factor::one()
+                    }
+                };
+
+                let melody = self.alloc.pack(melody);
+                (
+                    melody::Node::Scale(by, melody),
+                    Length::Bounded(length.0.clone()),
+                )
+            }
+
             implicit::Melody::Sequence(melodies) => {
                 let melodies: Vec<_> = A::as_slice(melodies)
                     .iter()
-                    .map(|melody| self.lower(component, melody))
+                    .map(|melody| self.lower(component, arguments, melody))
                     .collect();
 
                 for melody in melodies.iter().rev().skip(1) {
@@ -91,7 +261,7 @@ where
             implicit::Melody::Stack(melodies) => {
                 let melodies: Vec<_> = A::as_slice(melodies)
                     .iter()
-                    .map(|melody| self.lower(component, melody))
+                    .map(|melody| self.lower(component, arguments, melody))
                     .collect();
 
                 let length = melodies
@@ -104,6 +274,23 @@ where
 
                 (melody::Node::Stack(melodies), length)
             }
+
+            implicit::Melody::Choice(_, weights, melodies) => {
+                let melodies: Vec<_> = A::as_slice(melodies)
+                    .iter()
+                    .map(|melody| self.lower(component, arguments, melody))
+                    .collect();
+
+                let length = melodies
+                    .iter()
+                    .map(|melody| melody.length.clone())
+                    .max()
+                    .unwrap_or_else(Length::zero);
+
+                let melodies = self.alloc.pack_many(melodies);
+
+                (melody::Node::Choice(weights.clone(), melodies), length)
+            }
         };
 
         melody::Melody { node, span, length }
@@ -2,6 +2,7 @@ mod build;
 mod equation;
 mod lower;
 mod matrix;
+mod recursion;
 mod solve;
 
 #[cfg(test)]
@@ -9,23 +10,68 @@ mod tests;
 
 use std::collections::{HashMap, HashSet};
 
+use crate::chord::Chord;
 use crate::dependency::dependencies;
 use crate::note::Note;
 use crate::span::Span;
-use crate::{implicit, melody, topology, Allocator, Length, Name};
+use crate::{implicit, melody, topology, Allocator, Length, Name, Names};
 
-use self::equation::{Equation, Variable};
+use self::equation::{Equation, Template, Variable};
+use self::recursion::check_recursion;
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum Error<Id> {
     NoPublicNames(Span<Id>),
     UnknownName(Span<Id>, Name),
+    /// An unknown name which starts like a chord, at its quality.
+    UnknownChordQuality(Span<Id>),
+    ArgumentCount {
+        at: Span<Id>,
+        expected: usize,
+        actual: usize,
+    },
+    RecursiveArguments(Span<Id>),
     UnboundedNotLast(Span<Id>),
+    UnboundedReverse(Span<Id>),
+    UnboundedFit(Span<Id>),
+    EmptyFit(Span<Id>),
+    UnboundedTempoChange(Span<Id>),
     UnfoundedRecursion(Span<Id>),
 }
 
+/// Report an unknown name which starts like a chord as an unknown quality of
+/// that chord instead.
+fn misspelled_chord<N: Note, Id: Clone>(names: &Names, error: Error<Id>) -> Error<Id> {
+    let Error::UnknownName(span, name) = error else {
+        return error;
+    };
+
+    match Chord::<N>::unknown_quality(names.get(&name)) {
+        Some(quality) => Error::UnknownChordQuality(Span::new(
+            span.source.clone(),
+            span.start + quality.start..span.start + quality.end,
+        )),
+
+        None => Error::UnknownName(span, name),
+    }
+}
+
+/// Something suspicious about a program which does not stop it from being
+/// played.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Warning<Id> {
+    /// A definition annotated to last some number of bars has another length.
+    BarCount {
+        at: Span<Id>,
+        bars: usize,
+        expected: Length,
+        actual: Length,
+    },
+}
+
 pub fn check<N, Id, A>(
     alloc: &mut A,
+    names: &mut Names,
     program: implicit::Program<N, Id, A>,
 ) -> Result<melody::Program<N, Id, A>, Vec<Error<Id>>>
 where
@@ -34,44 +80,125 @@ where
     A: Allocator<implicit::Melody<N, Id, A>>,
     A: Allocator<melody::Melody<N, Id, A>>,
 {
-    let graph = dependencies::<N, Id, A>(&program.defs)?;
-    let mut checker = Checker::new(alloc);
+    let graph = dependencies::<N, Id, A>(&program.defs, &program.params).map_err(|errors| {
+        errors
+            .into_iter()
+            .map(|error| misspelled_chord::<N, Id>(names, error))
+            .collect::<Vec<_>>()
+    })?;
+    let components = topology::order(&graph);
 
-    for names in topology::order(&graph) {
-        let mut span = None;
-        for name in names.iter() {
-            let name_span = program.spans.get(name).expect("all names have a span");
-            if let Some(span) = &mut span {
-                *span += name_span.clone();
-            } else {
-                span = Some(name_span.clone());
-            }
-        }
+    let errors: Vec<_> = components
+        .iter()
+        .flat_map(|names| check_recursion::<N, Id, A>(&program.defs, &program.params, names))
+        .collect();
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut checker = Checker::new(alloc, names, &program);
 
-        let span = span.expect("components have at least one name");
-        checker.check_component(&program.defs, names, span);
+    for names in components {
+        let spans: Vec<_> = names
+            .iter()
+            .map(|name| program.spans.get(name).expect("all names have a span"))
+            .collect();
+
+        // Components may span several sources, in which case the span covers
+        // only the names in one of them, preferring the entry source.
+        let source = spans
+            .iter()
+            .find(|span| span.source == program.source.source)
+            .or(spans.first())
+            .map(|span| span.source.clone())
+            .expect("components have at least one name");
+
+        let span = spans
+            .into_iter()
+            .filter(|span| span.source == source)
+            .cloned()
+            .reduce(|a, b| a + b)
+            .expect("components have at least one name");
+        checker.check_component(names, span);
     }
 
     if program.public.is_empty() {
-        checker.errors.push(Error::NoPublicNames(program.source));
+        checker
+            .errors
+            .push(Error::NoPublicNames(program.source.clone()));
     }
 
+    // Parameterized definitions have a length for every set of arguments, so
+    // only definitions without parameters are compared to their annotation.
+    let mut warnings = Vec::new();
     if checker.errors.is_empty() {
+        for (name, bars) in program.bars.iter() {
+            if program.params.contains_key(name) {
+                continue;
+            }
+
+            let actual = checker.length_of(name, &[]);
+            if actual != bars.length {
+                warnings.push(Warning::BarCount {
+                    at: bars.span.clone(),
+                    bars: bars.count,
+                    expected: bars.length.clone(),
+                    actual,
+                });
+            }
+        }
+    }
+
+    warnings.sort_by_key(|Warning::BarCount { at, .. }| at.start);
+
+    let Checker {
+        defs,
+        spans,
+        errors,
+        ..
+    } = checker;
+
+    // Public definitions with parameters can be imported by other sources, but
+    // they cannot be played on their own.
+    let public = program
+        .public
+        .into_iter()
+        .filter(|name| !program.params.contains_key(name))
+        .collect();
+
+    if errors.is_empty() {
         Ok(melody::Program {
-            defs: checker.defs,
-            spans: program.spans,
-            public: program.public,
+            defs,
+            spans,
+            public,
+            tempo: program.tempo,
+            time: program.time,
+            metadata: program.metadata,
+            docs: program.docs,
+            warnings,
         })
     } else {
-        Err(checker.errors)
+        Err(errors)
     }
 }
 
-struct Checker<'a, N, Id, A: Allocator<melody::Melody<N, Id, A>>> {
+struct Checker<'a, N, Id, A>
+where
+    A: Allocator<implicit::Melody<N, Id, A>>,
+    A: Allocator<melody::Melody<N, Id, A>>,
+{
     alloc: &'a mut A,
-    defs: HashMap<Name, A::Holder>,
+    names: &'a mut Names,
+    program: &'a implicit::Program<N, Id, A>,
+
+    defs: HashMap<Name, <A as Allocator<melody::Melody<N, Id, A>>>::Holder>,
+    spans: HashMap<Name, Span<Id>>,
     context: HashMap<Name, Variable>,
     lengths: HashMap<Variable, Length>,
+    templates: HashMap<Variable, Template>,
+    components: HashMap<Name, HashSet<Name>>,
+    instances: HashMap<(Name, Vec<Length>), Name>,
     counter: usize,
 
     errors: Vec<Error<Id>>,
@@ -84,29 +211,45 @@ where
     A: Allocator<implicit::Melody<N, Id, A>>,
     A: Allocator<melody::Melody<N, Id, A>>,
 {
-    pub fn new(alloc: &'a mut A) -> Self {
+    pub fn new(
+        alloc: &'a mut A,
+        names: &'a mut Names,
+        program: &'a implicit::Program<N, Id, A>,
+    ) -> Self {
         Self {
             alloc,
+            names,
+            program,
+
             defs: HashMap::new(),
+            spans: program.spans.clone(),
             context: HashMap::new(),
             lengths: HashMap::new(),
+            templates: HashMap::new(),
+            components: HashMap::new(),
+            instances: HashMap::new(),
             counter: 0,
 
             errors: Vec::new(),
         }
     }
 
-    pub fn check_component(
-        &mut self,
-        program: &HashMap<Name, <A as Allocator<implicit::Melody<N, Id, A>>>::Holder>,
-        names: HashSet<&Name>,
-        span: Span<Id>,
-    ) {
+    pub fn check_component(&mut self, names: HashSet<&Name>, span: Span<Id>) {
         for name in names.iter() {
             let var = self.fresh();
             self.context.insert(Name::clone(name), var);
         }
 
+        // Every definition in a component passes its parameters along
+        // unchanged, so they all share the same parameter lengths.
+        let arity = names
+            .iter()
+            .find_map(|name| self.program.params.get(name))
+            .map(Vec::len)
+            .unwrap_or(0);
+
+        let params: Vec<_> = (0..arity).map(|_| self.fresh()).collect();
+
         let mut equations = Vec::with_capacity(names.len());
         for name in names.iter() {
             let var = *self
@@ -114,24 +257,113 @@ where
                 .get(name)
                 .expect("all names are bound a variable");
 
-            let Some(melody) = program.get(name) else {
+            let Some(melody) = self.program.defs.get(name) else {
                 continue;
             };
 
-            let sums = self.build_equation(A::as_ref(melody));
+            let bound = self
+                .program
+                .params
+                .get(name)
+                .into_iter()
+                .flatten()
+                .copied()
+                .zip(params.iter().copied())
+                .collect();
+
+            let sums = self.build_equation(&bound, A::as_ref(melody));
 
             equations.push(Equation { var, sums });
         }
 
-        self.solve(equations, span);
+        self.solve(equations, &params, span);
+
+        let component: HashSet<Name> = names.iter().map(|name| **name).collect();
+
+        if arity > 0 {
+            // Parameterized definitions are lowered once for every distinct
+            // set of argument lengths they are called with.
+            for name in names {
+                self.components.insert(*name, component.clone());
+            }
+
+            return;
+        }
 
         for name in names.iter() {
-            let Some(melody) = program.get(name) else { continue; };
+            let Some(melody) = self.program.defs.get(name) else { continue; };
 
-            let melody = self.lower(&names, A::as_ref(melody));
+            let melody = self.lower(&component, &[], A::as_ref(melody));
             let melody = self.alloc.pack(melody);
             let prev = self.defs.insert(**name, melody);
             debug_assert!(prev.is_none());
         }
     }
+
+    /// Get the name of the instance of the parameterized definition `name`
+    /// with the given parameter lengths, lowering it if it does not yet exist.
+    fn instantiate(&mut self, name: Name, lengths: Vec<Length>) -> Name {
+        let key = (name, lengths);
+        if let Some(instance) = self.instances.get(&key) {
+            return *instance;
+        }
+
+        let (name, lengths) = key;
+        let instance = {
+            let lengths: Vec<_> = lengths.iter().map(ToString::to_string).collect();
+            let instance = format!("{}({})", self.names.get(&name), lengths.join(", "));
+            self.names.fresh(instance)
+        };
+
+        self.instances.insert((name, lengths.clone()), instance);
+
+        let span = self
+            .program
+            .spans
+            .get(&name)
+            .expect("all names have a span");
+        self.spans.insert(instance, span.clone());
+
+        let component = self
+            .components
+            .get(&name)
+            .expect("parameterized definitions are checked before use")
+            .clone();
+
+        let program = self.program;
+        let melody = program.defs.get(&name).expect("all names are defined");
+        let arguments: Vec<_> = program
+            .params
+            .get(&name)
+            .expect("instances are of parameterized definitions")
+            .iter()
+            .copied()
+            .zip(lengths)
+            .collect();
+
+        let melody = self.lower(&component, &arguments, A::as_ref(melody));
+        let melody = self.alloc.pack(melody);
+        let prev = self.defs.insert(instance, melody);
+        debug_assert!(prev.is_none());
+
+        instance
+    }
+
+    /// Get the length of the definition `name` given the lengths of its
+    /// parameters.
+    fn length_of(&self, name: &Name, lengths: &[Length]) -> Length {
+        let var = self
+            .context
+            .get(name)
+            .expect("all names are given variables");
+
+        if let Some(template) = self.templates.get(var) {
+            template.apply(lengths)
+        } else {
+            self.lengths
+                .get(var)
+                .expect("unknown names are reported before checking")
+                .clone()
+        }
+    }
 }
@@ -0,0 +1,85 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::implicit::Melody;
+use crate::{Allocator, Name};
+
+use super::Error;
+
+/// Check that every recursive reference within a component passes along the
+/// parameters of the definition it occurs in unchanged. This ensures that
+/// every definition in the component has the same parameters, such that their
+/// lengths can be solved for together.
+pub fn check_recursion<N, Id: Clone, A: Allocator<Melody<N, Id, A>>>(
+    program: &HashMap<Name, A::Holder>,
+    params: &HashMap<Name, Vec<Name>>,
+    component: &HashSet<&Name>,
+) -> Vec<Error<Id>> {
+    let mut errors = Vec::new();
+
+    for name in component.iter() {
+        let Some(melody) = program.get(name) else {
+            continue;
+        };
+
+        let own = params.get(name).map(Vec::as_slice).unwrap_or_default();
+        check(component, own, A::as_ref(melody), &mut errors);
+    }
+
+    errors
+}
+
+fn check<N, Id: Clone, A: Allocator<Melody<N, Id, A>>>(
+    component: &HashSet<&Name>,
+    own: &[Name],
+    melody: &Melody<N, Id, A>,
+    errors: &mut Vec<Error<Id>>,
+) {
+    match melody {
+        Melody::Pause(_) | Melody::Note(..) | Melody::Degree(..) | Melody::Parameter(..) => {}
+
+        Melody::Name(span, name) => {
+            if component.contains(name) && !own.is_empty() {
+                errors.push(Error::RecursiveArguments(span.clone()));
+            }
+        }
+
+        Melody::Call(span, name, args) => {
+            let args = A::as_slice(args);
+
+            if component.contains(name) {
+                let passed = args.len() == own.len()
+                    && args.iter().zip(own).all(
+                        |(arg, param)| matches!(arg, Melody::Parameter(_, name) if name == param),
+                    );
+
+                if !passed {
+                    errors.push(Error::RecursiveArguments(span.clone()));
+                }
+            }
+
+            for melody in args {
+                check(component, own, melody, errors);
+            }
+        }
+
+        Melody::Scale(_, _, melody)
+        | Melody::Sharp(_, _, melody)
+        | Melody::Offset(_, _, melody)
+        | Melody::Dynamic(_, _, melody)
+        | Melody::Repeat(_, _, melody)
+        | Melody::Reverse(_, melody)
+        | Melody::Invert(_, _, melody)
+        | Melody::Tempo(_, _, _, melody)
+        | Melody::Voice(_, _, melody)
+        | Melody::Slice(_, _, _, melody)
+        | Melody::Fit(_, _, melody)
+        | Melody::Key(_, _, melody)
+        | Melody::Steps(_, _, melody) => check(component, own, A::as_ref(melody), errors),
+
+        Melody::Sequence(melodies) | Melody::Stack(melodies) | Melody::Choice(_, _, melodies) => {
+            for melody in A::as_slice(melodies) {
+                check(component, own, melody, errors);
+            }
+        }
+    }
+}
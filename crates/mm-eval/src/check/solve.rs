@@ -5,46 +5,107 @@ use num_bigint::BigInt;
 use num_rational::BigRational;
 
 use crate::span::Span;
-use crate::{melody, Allocator, Length};
+use crate::{implicit, melody, Allocator, Factor, Length};
 
-use super::equation::{Equation, Term, Variable};
+use super::equation::{Equation, Sum, Template, Term, Variable};
 use super::matrix::{self, solve, Row, System};
 use super::{Checker, Error};
 
 enum Solution {
-    Solved(Vec<(Variable, BigRational)>),
+    /// Every variable is the maximum of one or more linear sums, each given as
+    /// a constant followed by one coefficient per parameter.
+    Solved(Vec<(Variable, Vec<Vec<BigRational>>)>),
     Unbounded(Vec<Variable>),
     Unfounded(Vec<Variable>),
 }
 
-impl<N, Id, A: Allocator<melody::Melody<N, Id, A>>> Checker<'_, N, Id, A> {
-    pub fn solve(&mut self, equations: Vec<Equation>, span: Span<Id>) {
-        match self.solve_equations(equations) {
+impl<N, Id, A> Checker<'_, N, Id, A>
+where
+    A: Allocator<implicit::Melody<N, Id, A>>,
+    A: Allocator<melody::Melody<N, Id, A>>,
+{
+    /// Solve the given equations for the lengths of their variables. If the
+    /// equations refer to any `params`, the lengths are solved as templates
+    /// over those parameters.
+    pub fn solve(&mut self, equations: Vec<Equation>, params: &[Variable], span: Span<Id>) {
+        match self.solve_equations(equations, params) {
             Solution::Solved(lengths) => {
-                for (var, length) in lengths {
-                    let prev = self.lengths.insert(var, Length::Bounded(length));
-                    debug_assert!(prev.is_none());
+                for (var, solutions) in lengths {
+                    if params.is_empty() {
+                        let zero = BigRational::from_integer(BigInt::from(0));
+                        let length = solutions
+                            .into_iter()
+                            .map(|mut solution| solution.swap_remove(0))
+                            .fold(zero, |a, b| a.max(b));
+
+                        let prev = self.lengths.insert(var, Length::Bounded(length));
+                        debug_assert!(prev.is_none());
+                    } else {
+                        let sums = solutions
+                            .into_iter()
+                            .unique()
+                            .map(|solution| Self::make_sum(params, solution))
+                            .collect();
+
+                        self.set_template(var, params, sums);
+                    }
                 }
             }
 
             Solution::Unbounded(vars) => {
                 for var in vars {
-                    let prev = self.lengths.insert(var, Length::Unbounded);
-                    debug_assert!(prev.is_none());
+                    self.set_constant(var, params, Length::Unbounded);
                 }
             }
 
             Solution::Unfounded(vars) => {
                 self.errors.push(Error::UnfoundedRecursion(span));
                 for var in vars {
-                    let prev = self.lengths.insert(var, Length::zero());
-                    debug_assert!(prev.is_none());
+                    self.set_constant(var, params, Length::zero());
                 }
             }
         }
     }
 
-    fn solve_equations(&mut self, equations: Vec<Equation>) -> Solution {
+    fn set_constant(&mut self, var: Variable, params: &[Variable], length: Length) {
+        if params.is_empty() {
+            let prev = self.lengths.insert(var, length);
+            debug_assert!(prev.is_none());
+        } else {
+            let sums = vec![Sum {
+                terms: vec![Term::Constant(length)],
+            }];
+
+            self.set_template(var, params, sums);
+        }
+    }
+
+    fn set_template(&mut self, var: Variable, params: &[Variable], sums: Vec<Sum>) {
+        let template = Template {
+            params: params.to_vec(),
+            sums,
+        };
+
+        let prev = self.templates.insert(var, template);
+        debug_assert!(prev.is_none());
+    }
+
+    fn make_sum(params: &[Variable], solution: Vec<BigRational>) -> Sum {
+        let zero = BigRational::from_integer(BigInt::from(0));
+        let mut solution = solution.into_iter();
+        let constant = solution.next().expect("solutions have a constant");
+
+        let mut terms = vec![Term::Constant(Length::Bounded(constant))];
+        for (param, coeff) in params.iter().zip(solution) {
+            if coeff != zero {
+                terms.push(Term::Variable(Factor(coeff), *param));
+            }
+        }
+
+        Sum { terms }
+    }
+
+    fn solve_equations(&mut self, equations: Vec<Equation>, params: &[Variable]) -> Solution {
         let vars: Vec<_> = equations.iter().map(|eq| eq.var).collect();
         let var_positions: HashMap<_, _> = vars
             .iter()
@@ -52,9 +113,15 @@ impl<N, Id, A: Allocator<melody::Melody<N, Id, A>>> Checker<'_, N, Id, A> {
             .map(|(index, var)| (*var, index))
             .collect();
 
+        let param_positions: HashMap<_, _> = params
+            .iter()
+            .enumerate()
+            .map(|(index, var)| (*var, index))
+            .collect();
+
         let mut possible_rows = Vec::with_capacity(equations.len());
         for equation in equations {
-            match self.make_row(&var_positions, equation) {
+            match self.make_row(&var_positions, &param_positions, equation) {
                 Some(rows) => possible_rows.push(rows),
                 None => return Solution::Unbounded(vars),
             }
@@ -63,16 +130,22 @@ impl<N, Id, A: Allocator<melody::Melody<N, Id, A>>> Checker<'_, N, Id, A> {
         // Variables are in the same order as the equation list
         let possible_rows = possible_rows.into_iter().multi_cartesian_product();
 
-        let mut result = vec![BigRational::from_integer(BigInt::from(0)); vars.len()];
+        let mut result = vec![Vec::new(); vars.len()];
 
         for rows in possible_rows {
-            let system = System::new(rows, vars.clone());
-            match solve(system) {
-                Ok(solution) => {
-                    for (total, (_, mut new)) in result.iter_mut().zip(solution) {
-                        *total = total.max(&mut new).clone();
-                    }
-                }
+            // Parameter lengths are assumed to be positive, such that a
+            // recursion which adds some parameter is unbounded rather than
+            // unfounded.
+            let combined = rows
+                .iter()
+                .map(|(row, coeffs)| Row {
+                    coeffs: row.coeffs.clone(),
+                    constant: coeffs.iter().fold(row.constant.clone(), |a, b| a + b),
+                })
+                .collect();
+
+            let solution = match solve(System::new(combined, vars.clone())) {
+                Ok(solution) => solution,
 
                 Err(matrix::Error::Contradiction) => {
                     return Solution::Unbounded(vars);
@@ -82,6 +155,39 @@ impl<N, Id, A: Allocator<melody::Melody<N, Id, A>>> Checker<'_, N, Id, A> {
                     return Solution::Unfounded(vars);
                 }
             };
+
+            if params.is_empty() {
+                for (total, (_, new)) in result.iter_mut().zip(solution) {
+                    total.push(vec![new]);
+                }
+
+                continue;
+            }
+
+            // Solve for the constant and each parameter separately, such that
+            // the solution is a linear sum over the parameters.
+            let mut parts = Vec::with_capacity(params.len() + 1);
+            for index in 0..=params.len() {
+                let system = rows
+                    .iter()
+                    .map(|(row, coeffs)| Row {
+                        coeffs: row.coeffs.clone(),
+                        constant: match index {
+                            0 => row.constant.clone(),
+                            index => coeffs[index - 1].clone(),
+                        },
+                    })
+                    .collect();
+
+                let part = solve(System::new(system, vars.clone()))
+                    .expect("the combined system has a solution");
+
+                parts.push(part);
+            }
+
+            for (position, total) in result.iter_mut().enumerate() {
+                total.push(parts.iter().map(|part| part[position].1.clone()).collect());
+            }
         }
 
         Solution::Solved(vars.into_iter().zip(result).collect())
@@ -90,8 +196,9 @@ impl<N, Id, A: Allocator<melody::Melody<N, Id, A>>> Checker<'_, N, Id, A> {
     fn make_row(
         &self,
         var_positions: &HashMap<Variable, usize>,
+        param_positions: &HashMap<Variable, usize>,
         equation: Equation,
-    ) -> Option<Vec<Row>> {
+    ) -> Option<Vec<(Row, Vec<BigRational>)>> {
         let index = *var_positions
             .get(&equation.var)
             .expect("equation variable is part of the equation");
@@ -100,6 +207,8 @@ impl<N, Id, A: Allocator<melody::Melody<N, Id, A>>> Checker<'_, N, Id, A> {
         for sum in equation.sums {
             let mut constant = BigRational::from_integer(BigInt::from(0));
             let mut coeffs = vec![BigRational::from_integer(BigInt::from(0)); var_positions.len()];
+            let mut params =
+                vec![BigRational::from_integer(BigInt::from(0)); param_positions.len()];
 
             coeffs[index] = BigRational::from_integer(BigInt::from(1));
 
@@ -111,6 +220,8 @@ impl<N, Id, A: Allocator<melody::Melody<N, Id, A>>> Checker<'_, N, Id, A> {
                     Term::Variable(factor, var) => {
                         if let Some(pos) = var_positions.get(&var) {
                             coeffs[*pos] -= factor.0;
+                        } else if let Some(pos) = param_positions.get(&var) {
+                            params[*pos] += factor.0;
                         } else {
                             let length = self
                                 .lengths
@@ -124,7 +235,7 @@ impl<N, Id, A: Allocator<melody::Melody<N, Id, A>>> Checker<'_, N, Id, A> {
                 }
             }
 
-            rows.push(Row { coeffs, constant });
+            rows.push((Row { coeffs, constant }, params));
         }
 
         Some(rows)
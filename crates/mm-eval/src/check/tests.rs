@@ -1,12 +1,15 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use num_bigint::BigInt;
 use num_rational::BigRational;
 
-use super::Error;
+use super::recursion::check_recursion;
+use super::{Error, Warning};
 use crate::names::names;
 use crate::span::span;
-use crate::{implicit, melody, Allocator, Factor, Heap, Length, Name};
+use crate::{
+    implicit, melody, Allocator, Factor, Heap, Length, Metadata, Name, Names, Tempo, Time,
+};
 
 fn r(n: i128, d: i128) -> BigRational {
     BigRational::new(BigInt::from(n), BigInt::from(d))
@@ -15,16 +18,34 @@ fn r(n: i128, d: i128) -> BigRational {
 fn check_ok(
     expected: HashMap<Name, <Heap as Allocator<melody::Melody<char, &'static str, Heap>>>::Holder>,
     program: HashMap<Name, <Heap as Allocator<implicit::Melody<char, &'static str, Heap>>>::Holder>,
+) {
+    check_params_ok(Names::new(), expected, program, HashMap::new());
+}
+
+fn check_params_ok(
+    mut names: Names,
+    expected: HashMap<Name, <Heap as Allocator<melody::Melody<char, &'static str, Heap>>>::Holder>,
+    program: HashMap<Name, <Heap as Allocator<implicit::Melody<char, &'static str, Heap>>>::Holder>,
+    params: HashMap<Name, Vec<Name>>,
 ) {
     let mut alloc = Heap;
     let spans = program.keys().map(|name| (*name, span())).collect();
+    let public = vec![names.make("it")];
 
     let actual = super::check(
         &mut alloc,
+        &mut names,
         implicit::Program {
             defs: program,
+            params,
             spans,
-            public: vec![names()("it")],
+            public,
+            imports: Vec::new(),
+            tempo: None,
+            time: None,
+            bars: HashMap::new(),
+            metadata: Metadata::default(),
+            docs: HashMap::new(),
             source: span(),
         },
     )
@@ -41,10 +62,18 @@ fn check_err(
 
     let actual = super::check(
         &mut Heap,
+        &mut Names::new(),
         implicit::Program {
             defs: program,
+            params: HashMap::new(),
             spans,
             public: vec![names()("it")],
+            imports: Vec::new(),
+            tempo: None,
+            time: None,
+            bars: HashMap::new(),
+            metadata: Metadata::default(),
+            docs: HashMap::new(),
             source: span(),
         },
     );
@@ -110,6 +139,266 @@ fn scaled_note() {
     check_ok(expected, program);
 }
 
+#[test]
+fn choice() {
+    let mut name = names();
+
+    let a = implicit::Melody::Note(span(), 'a');
+    let b = implicit::Melody::Note(span(), 'b');
+    let b = implicit::Melody::Scale(span(), Factor(r(2, 1)), Box::new(b));
+    let melody = implicit::Melody::Choice(span(), vec![1, 2], vec![a, b]);
+    let program = HashMap::from([(name("x"), Box::new(melody))]);
+
+    let a = melody::Melody {
+        node: melody::Node::Note('a'),
+        span: span(),
+        length: Length::one(),
+    };
+
+    let b = melody::Melody {
+        node: melody::Node::Note('b'),
+        span: span(),
+        length: Length::one(),
+    };
+
+    let b = melody::Melody {
+        node: melody::Node::Scale(Factor(r(2, 1)), Box::new(b)),
+        span: span(),
+        length: Length::Bounded(r(2, 1)),
+    };
+
+    // A choice lasts as long as its longest branch.
+    let melody = melody::Melody {
+        node: melody::Node::Choice(vec![1, 2], vec![a, b]),
+        span: span(),
+        length: Length::Bounded(r(2, 1)),
+    };
+
+    let expected = HashMap::from([(name("x"), Box::new(melody))]);
+    check_ok(expected, program);
+}
+
+#[test]
+fn repeated_note() {
+    let mut name = names();
+
+    let melody = implicit::Melody::Note(span(), 'a');
+    let melody = implicit::Melody::Repeat(span(), Some(3), Box::new(melody));
+    let program = HashMap::from([(name("x"), Box::new(melody))]);
+
+    let melody = melody::Melody {
+        node: melody::Node::Note('a'),
+        span: span(),
+        length: Length::one(),
+    };
+
+    let melody = melody::Melody {
+        node: melody::Node::Repeat(Some(3), Box::new(melody)),
+        span: span(),
+        length: Length::Bounded(r(3, 1)),
+    };
+
+    let expected = HashMap::from([(name("x"), Box::new(melody))]);
+    check_ok(expected, program);
+}
+
+#[test]
+fn repeated_forever_not_last() {
+    let mut name = names();
+
+    let a = implicit::Melody::Note(span(), 'a');
+    let a = implicit::Melody::Repeat(span(), None, Box::new(a));
+    let b = implicit::Melody::Note(span(), 'b');
+    let melody = implicit::Melody::Sequence(vec![a, b]);
+    let program = HashMap::from([(name("x"), Box::new(melody))]);
+
+    let expected = vec![Error::UnboundedNotLast(span())];
+    check_err(expected, program);
+}
+
+#[test]
+fn repeated_forever_no_times() {
+    let mut name = names();
+
+    let a = implicit::Melody::Note(span(), 'a');
+    let a = implicit::Melody::Repeat(span(), None, Box::new(a));
+    let a = implicit::Melody::Repeat(span(), Some(0), Box::new(a));
+    let b = implicit::Melody::Note(span(), 'b');
+    let melody = implicit::Melody::Sequence(vec![a, b]);
+    let program = HashMap::from([(name("x"), Box::new(melody))]);
+
+    let a = melody::Melody {
+        node: melody::Node::Note('a'),
+        span: span(),
+        length: Length::one(),
+    };
+
+    let a = melody::Melody {
+        node: melody::Node::Repeat(None, Box::new(a)),
+        span: span(),
+        length: Length::Unbounded,
+    };
+
+    let a = melody::Melody {
+        node: melody::Node::Repeat(Some(0), Box::new(a)),
+        span: span(),
+        length: Length::zero(),
+    };
+
+    let b = melody::Melody {
+        node: melody::Node::Note('b'),
+        span: span(),
+        length: Length::one(),
+    };
+
+    let melody = melody::Melody {
+        node: melody::Node::Sequence(vec![a, b]),
+        span: span(),
+        length: Length::one(),
+    };
+
+    let expected = HashMap::from([(name("x"), Box::new(melody))]);
+    check_ok(expected, program);
+}
+
+#[test]
+fn sliced_forever_not_last() {
+    let mut name = names();
+
+    let a = implicit::Melody::Note(span(), 'a');
+    let a = implicit::Melody::Repeat(span(), None, Box::new(a));
+    let a = implicit::Melody::Slice(span(), Time(r(0, 1)), Time(r(3, 1)), Box::new(a));
+    let b = implicit::Melody::Note(span(), 'b');
+    let melody = implicit::Melody::Sequence(vec![a, b]);
+    let program = HashMap::from([(name("x"), Box::new(melody))]);
+
+    let a = melody::Melody {
+        node: melody::Node::Note('a'),
+        span: span(),
+        length: Length::one(),
+    };
+
+    let a = melody::Melody {
+        node: melody::Node::Repeat(None, Box::new(a)),
+        span: span(),
+        length: Length::Unbounded,
+    };
+
+    let a = melody::Melody {
+        node: melody::Node::Slice(Time(r(0, 1)), Time(r(3, 1)), Box::new(a)),
+        span: span(),
+        length: Length::Bounded(r(3, 1)),
+    };
+
+    let b = melody::Melody {
+        node: melody::Node::Note('b'),
+        span: span(),
+        length: Length::one(),
+    };
+
+    let melody = melody::Melody {
+        node: melody::Node::Sequence(vec![a, b]),
+        span: span(),
+        length: Length::Bounded(r(4, 1)),
+    };
+
+    let expected = HashMap::from([(name("x"), Box::new(melody))]);
+    check_ok(expected, program);
+}
+
+#[test]
+fn reversed_unbounded() {
+    let mut name = names();
+
+    let a = implicit::Melody::Note(span(), 'a');
+    let a = implicit::Melody::Repeat(span(), None, Box::new(a));
+    let melody = implicit::Melody::Reverse(span(), Box::new(a));
+    let program = HashMap::from([(name("x"), Box::new(melody))]);
+
+    let expected = vec![Error::UnboundedReverse(span())];
+    check_err(expected, program);
+}
+
+#[test]
+fn fit_sequence() {
+    let mut name = names();
+
+    let a = implicit::Melody::Note(span(), 'a');
+    let b = implicit::Melody::Note(span(), 'b');
+    let sequence = implicit::Melody::Sequence(vec![a, b]);
+    let fit = implicit::Melody::Fit(span(), Time(r(3, 1)), Box::new(sequence));
+    let c = implicit::Melody::Note(span(), 'c');
+    let melody = implicit::Melody::Sequence(vec![fit, c]);
+    let program = HashMap::from([(name("x"), Box::new(melody))]);
+
+    let note = |c| melody::Melody {
+        node: melody::Node::Note(c),
+        span: span(),
+        length: Length::one(),
+    };
+
+    let sequence = melody::Melody {
+        node: melody::Node::Sequence(vec![note('a'), note('b')]),
+        span: span(),
+        length: Length::Bounded(r(2, 1)),
+    };
+
+    let fit = melody::Melody {
+        node: melody::Node::Scale(Factor(r(3, 2)), Box::new(sequence)),
+        span: span(),
+        length: Length::Bounded(r(3, 1)),
+    };
+
+    let melody = melody::Melody {
+        node: melody::Node::Sequence(vec![fit, note('c')]),
+        span: span(),
+        length: Length::Bounded(r(4, 1)),
+    };
+
+    let expected = HashMap::from([(name("x"), Box::new(melody))]);
+    check_ok(expected, program);
+}
+
+#[test]
+fn fit_unbounded() {
+    let mut name = names();
+
+    let a = implicit::Melody::Note(span(), 'a');
+    let a = implicit::Melody::Repeat(span(), None, Box::new(a));
+    let melody = implicit::Melody::Fit(span(), Time(r(3, 1)), Box::new(a));
+    let program = HashMap::from([(name("x"), Box::new(melody))]);
+
+    let expected = vec![Error::UnboundedFit(span())];
+    check_err(expected, program);
+}
+
+#[test]
+fn fit_empty() {
+    let mut name = names();
+
+    let a = implicit::Melody::Note(span(), 'a');
+    let a = implicit::Melody::Repeat(span(), Some(0), Box::new(a));
+    let melody = implicit::Melody::Fit(span(), Time(r(3, 1)), Box::new(a));
+    let program = HashMap::from([(name("x"), Box::new(melody))]);
+
+    let expected = vec![Error::EmptyFit(span())];
+    check_err(expected, program);
+}
+
+#[test]
+fn gradual_tempo_unbounded() {
+    let mut name = names();
+    let tempo = Tempo::from_bpm(BigInt::from(60)).unwrap();
+
+    let a = implicit::Melody::Note(span(), 'a');
+    let a = implicit::Melody::Repeat(span(), None, Box::new(a));
+    let melody = implicit::Melody::Tempo(span(), None, tempo, Box::new(a));
+    let program = HashMap::from([(name("x"), Box::new(melody))]);
+
+    let expected = vec![Error::UnboundedTempoChange(span())];
+    check_err(expected, program);
+}
+
 #[test]
 fn pause_note_sequence() {
     let mut name = names();
@@ -445,3 +734,184 @@ fn recursive_stack() {
     let expected = vec![Error::UnfoundedRecursion(span())];
     check_err(expected, program);
 }
+
+#[test]
+fn parameterized() {
+    // it = f(a)
+    // f(x) = x, 1/2 f(x)
+    let mut names = Names::new();
+    let it_name = names.make("it");
+    let f_name = names.make("f");
+    let x_name = names.make("x");
+    let instance = names.clone().fresh("f(1)");
+
+    let a = implicit::Melody::Note(span(), 'a');
+    let it = implicit::Melody::Call(span(), f_name, vec![a]);
+
+    let x = implicit::Melody::Parameter(span(), x_name);
+    let to_x = implicit::Melody::Parameter(span(), x_name);
+    let to_f = implicit::Melody::Call(span(), f_name, vec![to_x]);
+    let half = implicit::Melody::Scale(span(), Factor(r(1, 2)), Box::new(to_f));
+    let f = implicit::Melody::Sequence(vec![x, half]);
+
+    let program = HashMap::from([(it_name, Box::new(it)), (f_name, Box::new(f))]);
+    let params = HashMap::from([(f_name, vec![x_name])]);
+
+    let a = melody::Melody {
+        node: melody::Node::Note('a'),
+        span: span(),
+        length: Length::one(),
+    };
+
+    let it = melody::Melody {
+        node: melody::Node::Call(instance, vec![a]),
+        span: span(),
+        length: Length::Bounded(r(2, 1)),
+    };
+
+    let x = melody::Melody {
+        node: melody::Node::Parameter(0),
+        span: span(),
+        length: Length::one(),
+    };
+
+    let to_f = melody::Melody {
+        node: melody::Node::Recur(instance),
+        span: span(),
+        length: Length::Bounded(r(2, 1)),
+    };
+
+    let half = melody::Melody {
+        node: melody::Node::Scale(Factor(r(1, 2)), Box::new(to_f)),
+        span: span(),
+        length: Length::one(),
+    };
+
+    let f = melody::Melody {
+        node: melody::Node::Sequence(vec![x, half]),
+        span: span(),
+        length: Length::Bounded(r(2, 1)),
+    };
+
+    let expected = HashMap::from([(it_name, Box::new(it)), (instance, Box::new(f))]);
+    check_params_ok(names, expected, program, params);
+}
+
+#[test]
+fn changed_recursive_arguments() {
+    let mut name = names();
+
+    let a = implicit::Melody::Note(span(), 'a');
+    let to_f = implicit::Melody::Call(span(), name("f"), vec![a]);
+    let x = implicit::Melody::Parameter(span(), name("x"));
+    let f = implicit::Melody::Sequence(vec![x, to_f]);
+
+    let program = HashMap::from([(name("f"), Box::new(f))]);
+    let params = HashMap::from([(name("f"), vec![name("x")])]);
+
+    let spans = program.keys().map(|name| (*name, span())).collect();
+    let actual = super::check(
+        &mut Heap,
+        &mut Names::new(),
+        implicit::Program {
+            defs: program,
+            params,
+            spans,
+            public: vec![],
+            imports: Vec::new(),
+            tempo: None,
+            time: None,
+            bars: HashMap::new(),
+            metadata: Metadata::default(),
+            docs: HashMap::new(),
+            source: span(),
+        },
+    );
+
+    assert_eq!(Err(vec![Error::RecursiveArguments(span())]), actual);
+}
+
+#[test]
+fn alternating_recursive_arguments() {
+    let mut name = names();
+    let (f, g, x, y) = (name("f"), name("g"), name("x"), name("y"));
+
+    let param = |name| implicit::Melody::Parameter(span(), name);
+    let call =
+        |to, first, second| implicit::Melody::Call(span(), to, vec![param(first), param(second)]);
+
+    // f(x, y) = x, g(x, y); g(x, y) = y, f(x, y)
+    let program: HashMap<_, Box<implicit::Melody<char, _, Heap>>> = HashMap::from([
+        (
+            f,
+            Box::new(implicit::Melody::Sequence(vec![param(x), call(g, x, y)])),
+        ),
+        (
+            g,
+            Box::new(implicit::Melody::Sequence(vec![param(y), call(f, x, y)])),
+        ),
+    ]);
+
+    let params = HashMap::from([(f, vec![x, y]), (g, vec![x, y])]);
+    let component = HashSet::from([&f, &g]);
+    assert!(check_recursion::<_, _, Heap>(&program, &params, &component).is_empty());
+
+    // f(x, y) = x, f(y, x)
+    let program: HashMap<_, Box<implicit::Melody<char, _, Heap>>> = HashMap::from([(
+        f,
+        Box::new(implicit::Melody::Sequence(vec![param(x), call(f, y, x)])),
+    )]);
+
+    let component = HashSet::from([&f]);
+    assert_eq!(
+        vec![Error::RecursiveArguments(span())],
+        check_recursion::<_, _, Heap>(&program, &params, &component)
+    );
+}
+
+#[test]
+fn bar_count() {
+    let mut name = names();
+
+    let a = implicit::Melody::Note(span(), 'a');
+    let b = implicit::Melody::Note(span(), 'b');
+    let it = implicit::Melody::Sequence(vec![a, b]);
+    let whole = implicit::Melody::Note(span(), 'c');
+    let xs = implicit::Melody::Scale(span(), Factor(r(4, 1)), Box::new(whole));
+
+    let program = HashMap::from([(name("it"), Box::new(it)), (name("xs"), Box::new(xs))]);
+    let bar = |count: usize| implicit::Bars {
+        count,
+        length: Length::Bounded(r(4 * count as i128, 1)),
+        span: span(),
+    };
+
+    let spans = program.keys().map(|name| (*name, span())).collect();
+    let actual = super::check(
+        &mut Heap,
+        &mut Names::new(),
+        implicit::Program {
+            defs: program,
+            params: HashMap::new(),
+            spans,
+            public: vec![name("it")],
+            imports: Vec::new(),
+            tempo: None,
+            time: None,
+            bars: HashMap::from([(name("it"), bar(1)), (name("xs"), bar(1))]),
+            metadata: Metadata::default(),
+            docs: HashMap::new(),
+            source: span(),
+        },
+    )
+    .map(|program| program.warnings);
+
+    let expected = vec![Warning::BarCount {
+        at: span(),
+        bars: 1,
+        expected: Length::Bounded(r(4, 1)),
+        actual: Length::Bounded(r(2, 1)),
+    }];
+
+    assert_eq!(Ok(expected), actual);
+}
@@ -0,0 +1,265 @@
+use std::ops::Range;
+
+use crate::note::Note;
+
+/// The ways a known chord quality can start.
+const QUALITIES: [&str; 10] = [
+    "maj", "min", "mM", "m", "M", "dim", "aug", "dom", "sus", "add",
+];
+
+/// A chord built on a root, given as the semitones above the root of each of
+/// its notes, from lowest to highest.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Chord<N> {
+    pub root: N,
+    pub intervals: Vec<isize>,
+}
+
+impl<N: Note> Chord<N> {
+    /// Parse a chord symbol like `Cmaj7`, `F#m` or `G7b9`. A chord symbol is a
+    /// letter from `A` to `G`, followed by any number of sharps (`#`) and flats
+    /// (`b`), followed by a quality. The root is in octave 4.
+    ///
+    /// Gives `None` unless the whole quality is known, such that names like
+    /// `Emily` or `Cmotif` are left as names. Symbols like `C6`, `C9` and `C13`
+    /// are chords here, but are read as notes with an octave wherever notes
+    /// may have one, so those chords are written `Cadd6`, `Cdom9` and `Cdom13`.
+    pub fn parse(symbol: &str) -> Option<Self> {
+        let letter = symbol
+            .get(..1)
+            .filter(|letter| ("A".."H").contains(letter))?;
+        let rest = &symbol[1..];
+        let quality = rest.trim_start_matches(['#', 'b']);
+
+        let accidentals = &rest[..rest.len() - quality.len()];
+        let sharps = accidentals
+            .chars()
+            .map(|c| if c == '#' { 1 } else { -1 })
+            .sum();
+
+        if quality.is_empty() {
+            return None;
+        }
+
+        let root = N::parse(letter)?.add_sharp(sharps)?;
+        let intervals = intervals(quality)?;
+        Some(Self { root, intervals })
+    }
+
+    /// Get where the quality of `symbol` is, if it is not a chord but starts
+    /// like one, such as `Cmaj8` or `Dsus5`. Undefined names like these are
+    /// most likely misspelled chords.
+    pub fn unknown_quality(symbol: &str) -> Option<Range<usize>> {
+        let rest = symbol
+            .strip_prefix(|c: char| ('A'..='G').contains(&c))?
+            .trim_start_matches(['#', 'b']);
+
+        let start = symbol.len() - rest.len();
+        let known = QUALITIES.iter().any(|quality| rest.starts_with(quality));
+        (known && Self::parse(symbol).is_none()).then_some(start..symbol.len())
+    }
+
+    /// Get the notes of this chord in close position above its root, or `None`
+    /// if any of them cannot be represented.
+    pub fn notes(&self) -> Option<Vec<N>> {
+        self.intervals
+            .iter()
+            .map(|by| self.root.add_sharp(*by))
+            .collect()
+    }
+}
+
+/// Get the semitones above the root of the notes in a chord with the given
+/// quality, or `None` if the quality is unknown.
+fn intervals(quality: &str) -> Option<Vec<isize>> {
+    let mut rest = quality;
+
+    let mut third = 4;
+    let mut fifth = 7;
+    let mut seventh = 10;
+    let mut has_seventh = false;
+    let mut needs_number = false;
+
+    if eat(&mut rest, "mmaj") || eat(&mut rest, "mM") {
+        third = 3;
+        seventh = 11;
+        needs_number = true;
+    } else if eat(&mut rest, "maj") || eat(&mut rest, "M") {
+        seventh = 11;
+    } else if eat(&mut rest, "min") || eat(&mut rest, "m") {
+        third = 3;
+    } else if eat(&mut rest, "dim") {
+        third = 3;
+        fifth = 6;
+        seventh = 9;
+    } else if eat(&mut rest, "aug") {
+        fifth = 8;
+    } else if eat(&mut rest, "ø") {
+        third = 3;
+        fifth = 6;
+        has_seventh = true;
+    } else if eat(&mut rest, "dom") {
+        needs_number = true;
+    }
+
+    let mut upper = Vec::new();
+    match number(&mut rest, &[13, 11, 9, 7, 6]) {
+        Some(6) => upper.push(9),
+        Some(7) => has_seventh = true,
+        Some(9) => upper.push(14),
+        Some(11) => upper.extend([14, 17]),
+        Some(13) => upper.extend([14, 21]),
+        _ if needs_number => return None,
+        _ => {}
+    }
+
+    has_seventh |= upper.iter().any(|by| *by > 12);
+
+    if eat(&mut rest, "sus2") {
+        third = 2;
+    } else if eat(&mut rest, "sus4") || eat(&mut rest, "sus") {
+        third = 5;
+    }
+
+    loop {
+        if eat(&mut rest, "add") {
+            let by = match number(&mut rest, &[13, 11, 9, 6, 4, 2])? {
+                2 => 2,
+                4 => 5,
+                6 => 9,
+                degree => natural(degree),
+            };
+
+            upper.push(by);
+        } else if let Some(sign) = sign(&mut rest) {
+            match number(&mut rest, &[13, 11, 9, 5])? {
+                5 => fifth += sign,
+                degree => {
+                    let by = natural(degree);
+                    upper.retain(|other| *other != by);
+                    upper.push(by + sign);
+                }
+            }
+        } else {
+            break;
+        }
+    }
+
+    if !rest.is_empty() {
+        return None;
+    }
+
+    let mut intervals = vec![0, third, fifth];
+    if has_seventh {
+        intervals.push(seventh);
+    }
+
+    intervals.extend(upper);
+    intervals.sort();
+    intervals.dedup();
+
+    Some(intervals)
+}
+
+/// Get the semitones above the root of an unaltered ninth, eleventh or
+/// thirteenth.
+fn natural(degree: usize) -> isize {
+    match degree {
+        9 => 14,
+        11 => 17,
+        13 => 21,
+        _ => unreachable!(),
+    }
+}
+
+fn eat(rest: &mut &str, prefix: &str) -> bool {
+    match rest.strip_prefix(prefix) {
+        Some(stripped) => {
+            *rest = stripped;
+            true
+        }
+
+        None => false,
+    }
+}
+
+fn number(rest: &mut &str, numbers: &[usize]) -> Option<usize> {
+    numbers
+        .iter()
+        .copied()
+        .find(|number| eat(rest, &number.to_string()))
+}
+
+fn sign(rest: &mut &str) -> Option<isize> {
+    if eat(rest, "b") {
+        Some(-1)
+    } else if eat(rest, "#") {
+        Some(1)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Chord;
+
+    fn intervals(symbol: &str) -> Vec<isize> {
+        Chord::<char>::parse(symbol).unwrap().intervals
+    }
+
+    #[test]
+    fn qualities() {
+        assert_eq!(vec![0, 4, 7], intervals("CM"));
+        assert_eq!(vec![0, 3, 7], intervals("Cm"));
+        assert_eq!(vec![0, 4, 7, 11], intervals("Cmaj7"));
+        assert_eq!(vec![0, 4, 7, 10], intervals("Cdom7"));
+        assert_eq!(vec![0, 3, 7, 10], intervals("Cm7"));
+        assert_eq!(vec![0, 3, 7, 11], intervals("CmM7"));
+        assert_eq!(vec![0, 3, 6, 9], intervals("Cdim7"));
+        assert_eq!(vec![0, 3, 6, 10], intervals("Cm7b5"));
+        assert_eq!(vec![0, 3, 6, 10], intervals("Cø"));
+        assert_eq!(vec![0, 4, 8], intervals("Caug"));
+        assert_eq!(vec![0, 5, 7, 10], intervals("C7sus4"));
+        assert_eq!(vec![0, 4, 7, 10, 13], intervals("G7b9"));
+        assert_eq!(vec![0, 4, 7, 10, 15, 18], intervals("C7#9#11"));
+        assert_eq!(vec![0, 4, 7, 10, 14, 21], intervals("C13"));
+        assert_eq!(vec![0, 3, 7, 14], intervals("Cmadd9"));
+        assert_eq!(vec![0, 4, 7, 9], intervals("C6"));
+    }
+
+    #[test]
+    fn not_chords() {
+        assert_eq!(None, Chord::<char>::parse("Chorus"));
+        assert_eq!(None, Chord::<char>::parse("Bass"));
+        assert_eq!(None, Chord::<char>::parse("cm7"));
+        assert_eq!(None, Chord::<char>::parse("Eb"));
+    }
+
+    #[test]
+    fn unknown_qualities() {
+        assert_eq!(None, Chord::<char>::parse("Cmaj8"));
+        assert_eq!(None, Chord::<char>::parse("F#dom"));
+        assert_eq!(None, Chord::<char>::parse("Emily"));
+        assert_eq!(None, Chord::<char>::parse("Cmotif"));
+        assert_eq!(None, Chord::<char>::parse("Amelody"));
+        assert_eq!(None, Chord::<char>::parse("Dsustain"));
+    }
+
+    #[test]
+    fn misspelled() {
+        assert_eq!(Some(1..5), Chord::<char>::unknown_quality("Cmaj8"));
+        assert_eq!(Some(2..5), Chord::<char>::unknown_quality("F#dom"));
+        assert_eq!(Some(1..6), Chord::<char>::unknown_quality("Cmotif"));
+        assert_eq!(None, Chord::<char>::unknown_quality("Cmaj7"));
+        assert_eq!(None, Chord::<char>::unknown_quality("Bass"));
+        assert_eq!(None, Chord::<char>::unknown_quality("Chorus"));
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(vec![0, 4, 7, 9], intervals("Cadd6"));
+        assert_eq!(vec![0, 4, 7, 10, 14], intervals("Cdom9"));
+        assert_eq!(vec![0, 4, 7, 10, 14, 21], intervals("Cdom13"));
+    }
+}
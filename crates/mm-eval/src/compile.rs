@@ -1,7 +1,11 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
 use crate::note::Note;
 use crate::parse::Parser;
-use crate::{check, implicit, melody, Allocator, Error, Names};
+use crate::{check, implicit, link, melody, Allocator, Error, Names};
 
+/// Compile a single source which does not import any other sources.
 pub fn compile<N, Id, A>(
     alloc: &mut A,
     names: &mut Names,
@@ -10,14 +14,38 @@ pub fn compile<N, Id, A>(
 ) -> Result<melody::Program<N, Id, A>, Vec<Error<Id>>>
 where
     N: Note,
-    Id: Clone + Eq,
+    Id: Clone + Eq + Hash,
     A: Allocator<implicit::Melody<N, Id, A>>,
     A: Allocator<melody::Melody<N, Id, A>>,
 {
-    let parsed = match Parser::parse(alloc, names, name, source) {
+    let parsed = match Parser::parse(alloc, names, name.clone(), source) {
         Ok(parsed) => parsed,
         Err(es) => return Err(es.into_iter().map(Into::into).collect()),
     };
 
-    check::check(alloc, parsed).map_err(|err| err.into_iter().map(Into::into).collect())
+    let modules = HashMap::from([(name.clone(), parsed)]);
+    compile_linked(alloc, names, &name, &modules, |_, _| None)
+}
+
+/// Link the already parsed source `entry` with the sources it imports and check
+/// the result. See [`link::link`] for how imports are resolved.
+pub fn compile_linked<N, Id, A>(
+    alloc: &mut A,
+    names: &mut Names,
+    entry: &Id,
+    modules: &HashMap<Id, implicit::Program<N, Id, A>>,
+    resolve: impl Fn(&Id, &str) -> Option<Id>,
+) -> Result<melody::Program<N, Id, A>, Vec<Error<Id>>>
+where
+    N: Note,
+    Id: Clone + Eq + Hash,
+    A: Allocator<implicit::Melody<N, Id, A>>,
+    A: Allocator<melody::Melody<N, Id, A>>,
+{
+    let linked = match link::link(alloc, names, entry, modules, resolve) {
+        Ok(linked) => linked,
+        Err(es) => return Err(es.into_iter().map(Into::into).collect()),
+    };
+
+    check::check(alloc, names, linked).map_err(|err| err.into_iter().map(Into::into).collect())
 }
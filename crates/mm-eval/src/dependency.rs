@@ -1,20 +1,23 @@
 use std::collections::{HashMap, HashSet};
 
 use crate::implicit::Melody;
+use crate::span::Span;
 use crate::{check, Allocator, Name};
 
 /// Compute the dependency graph of the given program. Each returned entry
 /// contains "outgoing" edges: `a` is in the set of names referred to by `b` if
-/// the definition of `b` refers to `a` at any place.
+/// the definition of `b` refers to `a` at any place. Every reference is also
+/// checked to give its definition the right number of arguments.
 pub fn dependencies<N, Id: Clone, A: Allocator<Melody<N, Id, A>>>(
     program: &HashMap<Name, A::Holder>,
+    params: &HashMap<Name, Vec<Name>>,
 ) -> Result<HashMap<Name, HashSet<Name>>, Vec<check::Error<Id>>> {
     let mut errs = Vec::new();
     let program = program
         .iter()
         .map(|(name, melody)| {
             let mut refers = HashSet::new();
-            if let Err(es) = compute(program, &mut refers, A::as_ref(melody)) {
+            if let Err(es) = compute(program, params, &mut refers, A::as_ref(melody)) {
                 errs.extend(es);
             }
             (*name, refers)
@@ -27,11 +30,12 @@ pub fn dependencies<N, Id: Clone, A: Allocator<Melody<N, Id, A>>>(
 /// Add the names referred to by `melody` to `within`.
 fn compute<N, Id: Clone, A: Allocator<Melody<N, Id, A>>>(
     program: &HashMap<Name, A::Holder>,
+    params: &HashMap<Name, Vec<Name>>,
     within: &mut HashSet<Name>,
     melody: &Melody<N, Id, A>,
 ) -> Result<(), Vec<check::Error<Id>>> {
     match melody {
-        Melody::Pause(_) | Melody::Note(..) => Ok(()),
+        Melody::Pause(_) | Melody::Note(..) | Melody::Degree(..) | Melody::Parameter(..) => Ok(()),
 
         Melody::Name(span, name) => {
             within.insert(*name);
@@ -39,18 +43,47 @@ fn compute<N, Id: Clone, A: Allocator<Melody<N, Id, A>>>(
             if !program.contains_key(name) {
                 Err(vec![check::Error::UnknownName(span.clone(), *name)])
             } else {
-                Ok(())
+                arity(params, span, name, 0)
             }
         }
 
-        Melody::Scale(_, _, melody) => compute(program, within, A::as_ref(melody)),
-        Melody::Sharp(_, _, melody) => compute(program, within, A::as_ref(melody)),
-        Melody::Offset(_, _, melody) => compute(program, within, A::as_ref(melody)),
+        Melody::Call(span, name, args) => {
+            within.insert(*name);
 
-        Melody::Sequence(melodies) | Melody::Stack(melodies) => {
+            let mut errs = vec![];
+            if !program.contains_key(name) {
+                errs.push(check::Error::UnknownName(span.clone(), *name));
+            } else if let Err(es) = arity(params, span, name, A::as_slice(args).len()) {
+                errs.extend(es);
+            }
+
+            for melody in A::as_slice(args) {
+                if let Err(es) = compute(program, params, within, melody) {
+                    errs.extend(es);
+                }
+            }
+
+            errs.is_empty().then_some(()).ok_or(errs)
+        }
+
+        Melody::Scale(_, _, melody) => compute(program, params, within, A::as_ref(melody)),
+        Melody::Sharp(_, _, melody) => compute(program, params, within, A::as_ref(melody)),
+        Melody::Offset(_, _, melody) => compute(program, params, within, A::as_ref(melody)),
+        Melody::Dynamic(_, _, melody) => compute(program, params, within, A::as_ref(melody)),
+        Melody::Repeat(_, _, melody) => compute(program, params, within, A::as_ref(melody)),
+        Melody::Reverse(_, melody) => compute(program, params, within, A::as_ref(melody)),
+        Melody::Invert(_, _, melody) => compute(program, params, within, A::as_ref(melody)),
+        Melody::Tempo(_, _, _, melody) => compute(program, params, within, A::as_ref(melody)),
+        Melody::Voice(_, _, melody) => compute(program, params, within, A::as_ref(melody)),
+        Melody::Slice(_, _, _, melody) => compute(program, params, within, A::as_ref(melody)),
+        Melody::Fit(_, _, melody) => compute(program, params, within, A::as_ref(melody)),
+        Melody::Key(_, _, melody) => compute(program, params, within, A::as_ref(melody)),
+        Melody::Steps(_, _, melody) => compute(program, params, within, A::as_ref(melody)),
+
+        Melody::Sequence(melodies) | Melody::Stack(melodies) | Melody::Choice(_, _, melodies) => {
             let mut errs = vec![];
             for melody in A::as_slice(melodies) {
-                if let Err(es) = compute(program, within, melody) {
+                if let Err(es) = compute(program, params, within, melody) {
                     errs.extend(es);
                 }
             }
@@ -60,6 +93,27 @@ fn compute<N, Id: Clone, A: Allocator<Melody<N, Id, A>>>(
     }
 }
 
+/// Check that a reference to `name` gives it as many arguments as it has
+/// parameters.
+fn arity<Id: Clone>(
+    params: &HashMap<Name, Vec<Name>>,
+    span: &Span<Id>,
+    name: &Name,
+    actual: usize,
+) -> Result<(), Vec<check::Error<Id>>> {
+    let expected = params.get(name).map(Vec::len).unwrap_or(0);
+
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(vec![check::Error::ArgumentCount {
+            at: span.clone(),
+            expected,
+            actual,
+        }])
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::{HashMap, HashSet};
@@ -67,7 +121,7 @@ mod tests {
     use crate::implicit::Melody;
     use crate::names::names;
     use crate::span::span;
-    use crate::Heap;
+    use crate::{check, Heap};
 
     use super::dependencies;
 
@@ -84,7 +138,7 @@ mod tests {
         let program = HashMap::from([(name("a"), Box::new(a)), (name("b"), Box::new(seq))]);
         let expected = HashMap::from([(name("a"), HashSet::new()), (name("b"), HashSet::new())]);
 
-        let actual = dependencies::<char, &str, Heap>(&program);
+        let actual = dependencies::<char, &str, Heap>(&program, &HashMap::new());
 
         assert_eq!(Ok(expected), actual);
     }
@@ -109,7 +163,7 @@ mod tests {
             (name("c"), HashSet::from([name("b")])),
         ]);
 
-        let actual = dependencies::<char, &str, Heap>(&program);
+        let actual = dependencies::<char, &str, Heap>(&program, &HashMap::new());
 
         assert_eq!(Ok(expected), actual);
     }
@@ -140,7 +194,7 @@ mod tests {
             (name("d"), HashSet::from([name("b"), name("c")])),
         ]);
 
-        let actual = dependencies::<char, &str, Heap>(&program);
+        let actual = dependencies::<char, &str, Heap>(&program, &HashMap::new());
 
         assert_eq!(Ok(expected), actual);
     }
@@ -165,8 +219,50 @@ mod tests {
             (name("c"), HashSet::from([name("b")])),
         ]);
 
-        let actual = dependencies::<char, &str, Heap>(&program);
+        let actual = dependencies::<char, &str, Heap>(&program, &HashMap::new());
+
+        assert_eq!(Ok(expected), actual);
+    }
+
+    #[test]
+    fn calls() {
+        let mut name = names();
+
+        let x = Melody::Parameter(span(), name("x"));
+        let a = Melody::Note(span(), 'a');
+        let call = Melody::Call(span(), name("f"), vec![a]);
+
+        let program = HashMap::from([(name("f"), Box::new(x)), (name("g"), Box::new(call))]);
+        let params = HashMap::from([(name("f"), vec![name("x")])]);
+
+        let expected = HashMap::from([
+            (name("f"), HashSet::new()),
+            (name("g"), HashSet::from([name("f")])),
+        ]);
+
+        let actual = dependencies::<char, &str, Heap>(&program, &params);
 
         assert_eq!(Ok(expected), actual);
     }
+
+    #[test]
+    fn wrong_arity() {
+        let mut name = names();
+
+        let x = Melody::Parameter(span(), name("x"));
+        let to_f = Melody::Name(span(), name("f"));
+
+        let program = HashMap::from([(name("f"), Box::new(x)), (name("g"), Box::new(to_f))]);
+        let params = HashMap::from([(name("f"), vec![name("x")])]);
+
+        let expected = vec![check::Error::ArgumentCount {
+            at: span(),
+            expected: 1,
+            actual: 0,
+        }];
+
+        let actual = dependencies::<char, &str, Heap>(&program, &params);
+
+        assert_eq!(Err(expected), actual);
+    }
 }
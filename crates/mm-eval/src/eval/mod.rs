@@ -1,22 +1,31 @@
 #[cfg(test)]
 mod tests;
 
+mod random;
+
 use std::cmp::Ordering;
 use std::collections::{BinaryHeap, HashMap};
+use std::rc::Rc;
 
 use num_bigint::BigInt;
 use num_rational::BigRational;
+use num_traits::Zero;
 
+use self::random::Random;
+use crate::key::{Key, Mode};
 use crate::melody::{Melody, Node};
 use crate::note::Note;
 use crate::span::Span;
-use crate::{Allocator, Factor, Length, Name, Time};
+use crate::{Allocator, Factor, Length, Name, Tempo, Time, Velocity, Voice};
 
 pub const DEFAULT_MAX_DEPTH: usize = 10;
 
 pub struct Evaluator<'a, N, Id, A: Allocator<Melody<N, Id, A>>> {
     program: &'a HashMap<Name, A::Holder>,
     entry: Name,
+    key: Option<Key<N>>,
+    tempo: Tempo,
+    seed: u64,
     max_depth: usize,
     min_length: Length,
 }
@@ -28,9 +37,17 @@ where
     A: Allocator<Melody<N, Id, A>>,
 {
     pub fn new(program: &'a HashMap<Name, A::Holder>, entry: Name) -> Self {
+        let key = N::parse("C").map(|tonic| Key {
+            tonic,
+            mode: Mode::Ionian,
+        });
+
         Self {
             program,
             entry,
+            key,
+            tempo: Tempo::default(),
+            seed: 0,
             max_depth: DEFAULT_MAX_DEPTH,
             min_length: Length::Bounded(BigRational::new(BigInt::from(1), BigInt::from(512))),
         }
@@ -40,11 +57,42 @@ where
         Self { max_depth, ..self }
     }
 
+    /// Set the key which scale degrees outside of any explicit key are played
+    /// in. Defaults to C major.
+    pub fn with_key(self, key: Key<N>) -> Self {
+        Self {
+            key: Some(key),
+            ..self
+        }
+    }
+
+    /// Set the tempo the melody starts at. Defaults to 120 beats per minute.
+    pub fn with_tempo(self, tempo: Tempo) -> Self {
+        Self { tempo, ..self }
+    }
+
+    /// Set the seed which choices between melodies are made with. Evaluating
+    /// the same melody with the same seed always makes the same choices.
+    pub fn with_seed(self, seed: u64) -> Self {
+        Self { seed, ..self }
+    }
+
     pub fn with_min_length(self, min_length: Length) -> Self {
         Self { min_length, ..self }
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = (N, Span<Id>, Time, Length)> + '_ {
+    /// Iterate over the notes of the melody, ignoring changes of tempo and
+    /// overflows.
+    pub fn iter(&self) -> impl Iterator<Item = Played<N, Id>> + '_ {
+        self.events().filter_map(|event| match event {
+            Event::Note(played) => Some(played),
+            Event::Tempo { .. } | Event::Overflow(_) => None,
+        })
+    }
+
+    /// Iterate over the notes and changes of tempo of the melody, in order of
+    /// when they start. The tempo the melody starts at comes first.
+    pub fn events(&self) -> impl Iterator<Item = Event<N, Id>> + '_ {
         let melody = self.program.get(&self.entry).expect("entry exists");
         let melody = A::as_ref(melody);
         let start = Time::zero();
@@ -52,6 +100,12 @@ where
 
         Iter {
             evaluator: self,
+            initial: Some(self.tempo.clone()),
+            restores: BinaryHeap::new(),
+            restored: 0,
+            played: 0,
+            random: Random::new(self.seed),
+            buffered: BinaryHeap::new(),
             queue: BinaryHeap::from([NextMelody {
                 melody,
                 depth: 0,
@@ -60,11 +114,51 @@ where
                 factor,
                 offset: 0,
                 sharps: 0,
+                velocity: Velocity::one(),
+                arguments: None,
+                repetition: 0,
+                before: 0,
+                silent: 0,
+                reversed: false,
+                inversions: None,
+                key: self.key.as_ref(),
+                steps: 0,
+                tempo: &self.tempo,
+                voice: Voice::default(),
+                window: None,
             }]),
         }
     }
 }
 
+/// Something which happens while a melody is played.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Event<N, Id> {
+    Note(Played<N, Id>),
+    /// A change of tempo, which happens gradually over the given length if
+    /// the tempos differ.
+    Tempo {
+        start: Time,
+        length: Length,
+        from: Tempo,
+        to: Tempo,
+    },
+    /// A transposition which adds up to more than can be played, such that
+    /// nothing within the melody at the given span is played.
+    Overflow(Span<Id>),
+}
+
+/// A note as it is played, along with where in the source it comes from.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Played<N, Id> {
+    pub note: N,
+    pub span: Span<Id>,
+    pub start: Time,
+    pub length: Length,
+    pub velocity: Velocity,
+    pub voice: Voice,
+}
+
 struct NextMelody<'a, N, Id, A: Allocator<Melody<N, Id, A>>> {
     melody: &'a Melody<N, Id, A>,
     depth: usize,
@@ -72,13 +166,155 @@ struct NextMelody<'a, N, Id, A: Allocator<Melody<N, Id, A>>> {
 
     factor: Factor,
     offset: isize,
-    sharps: usize,
+    sharps: isize,
+    velocity: Velocity,
+    arguments: Option<Rc<Arguments<'a, N, Id, A>>>,
+    /// How many times this melody has already been played, if it repeats.
+    repetition: usize,
+    /// How many notes were played before the previous repetition started.
+    before: usize,
+    /// How many repetitions in a row played no notes.
+    silent: usize,
+    /// Whether this melody is played backwards.
+    reversed: bool,
+    inversions: Option<Rc<Inversion<'a, N>>>,
+    key: Option<&'a Key<N>>,
+    steps: isize,
+    tempo: &'a Tempo,
+    voice: Voice,
+    /// The times outside of which nothing is played, if this melody is part
+    /// of a slice.
+    window: Option<Rc<Window>>,
+}
+
+/// A return to the surrounding tempo once a melody with its own tempo ends.
+/// Restores at the same time happen in the opposite order of being scheduled,
+/// such that the outermost tempo comes last.
+struct Restore {
+    at: Time,
+    tempo: Tempo,
+    order: usize,
+}
+
+impl Eq for Restore {}
+
+impl PartialEq for Restore {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl PartialOrd for Restore {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Restore {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let Time(this) = &self.at;
+        let Time(that) = &other.at;
+        this.cmp(that).reverse().then(self.order.cmp(&other.order))
+    }
+}
+
+/// The part of the timeline a slice of a melody is played in.
+struct Window {
+    start: BigRational,
+    end: BigRational,
+}
+
+impl Window {
+    /// Get the part of this window which also lies within an outer window.
+    fn within(self, outer: &Self) -> Self {
+        Self {
+            start: self.start.max(outer.start.clone()),
+            end: self.end.min(outer.end.clone()),
+        }
+    }
+
+    /// Whether a melody starting at `start` and lasting `length` lies entirely
+    /// outside of this window. Melodies without any length are only outside
+    /// of it if they start outside of it.
+    fn excludes(&self, start: &Time, length: &Length) -> bool {
+        if start.0 >= self.end {
+            return true;
+        }
+
+        match length {
+            Length::Bounded(length) => start.0 < self.start && &start.0 + length <= self.start,
+            Length::Unbounded => false,
+        }
+    }
+}
+
+/// A note which is already played, ordered such that the note which starts
+/// first is the greatest.
+struct Buffered<N, Id>(Played<N, Id>);
+
+impl<N, Id> Eq for Buffered<N, Id> {}
+
+impl<N, Id> PartialEq for Buffered<N, Id> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
 }
 
-impl<N, Id, A: Allocator<Melody<N, Id, A>>> Eq for NextMelody<'_, N, Id, A> {
-    fn assert_receiver_is_total_eq(&self) {}
+impl<N, Id> PartialOrd for Buffered<N, Id> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
+impl<N, Id> Ord for Buffered<N, Id> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.start.0.cmp(&other.0.start.0).reverse()
+    }
+}
+
+/// An inversion which applies to a melody, along with the transposition which
+/// applies after it, and any further inversions outside of that.
+struct Inversion<'a, N> {
+    axis: &'a N,
+    offset: isize,
+    sharps: isize,
+    outer: Option<Rc<Inversion<'a, N>>>,
+}
+
+/// The arguments given to a call, along with the arguments of the caller,
+/// which the arguments themselves may refer to.
+struct Arguments<'a, N, Id, A: Allocator<Melody<N, Id, A>>> {
+    melodies: &'a [Melody<N, Id, A>],
+    outer: Option<Rc<Arguments<'a, N, Id, A>>>,
+}
+
+impl<N, Id, A: Allocator<Melody<N, Id, A>>> Clone for NextMelody<'_, N, Id, A> {
+    fn clone(&self) -> Self {
+        Self {
+            melody: self.melody,
+            depth: self.depth,
+            start: self.start.clone(),
+            factor: self.factor.clone(),
+            offset: self.offset,
+            sharps: self.sharps,
+            velocity: self.velocity.clone(),
+            arguments: self.arguments.clone(),
+            repetition: self.repetition,
+            before: self.before,
+            silent: self.silent,
+            reversed: self.reversed,
+            inversions: self.inversions.clone(),
+            key: self.key,
+            steps: self.steps,
+            tempo: self.tempo,
+            voice: self.voice,
+            window: self.window.clone(),
+        }
+    }
+}
+
+impl<N, Id, A: Allocator<Melody<N, Id, A>>> Eq for NextMelody<'_, N, Id, A> {}
+
 impl<N, Id, A: Allocator<Melody<N, Id, A>>> PartialEq for NextMelody<'_, N, Id, A> {
     fn eq(&self, other: &Self) -> bool {
         self.cmp(other).is_eq()
@@ -101,6 +337,14 @@ impl<N, Id, A: Allocator<Melody<N, Id, A>>> Ord for NextMelody<'_, N, Id, A> {
 
 struct Iter<'a, N, Id, A: Allocator<Melody<N, Id, A>>> {
     evaluator: &'a Evaluator<'a, N, Id, A>,
+    initial: Option<Tempo>,
+    restores: BinaryHeap<Restore>,
+    restored: usize,
+    /// How many notes have been returned so far.
+    played: usize,
+    random: Random,
+    /// Notes which are already played, waiting for their time to come.
+    buffered: BinaryHeap<Buffered<N, Id>>,
     queue: BinaryHeap<NextMelody<'a, N, Id, A>>,
 }
 
@@ -110,27 +354,126 @@ where
     Id: Clone,
     A: Allocator<Melody<N, Id, A>>,
 {
-    type Item = (N, Span<Id>, Time, Length);
+    type Item = Event<N, Id>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while let Some(next) = self.queue.pop() {
-            let start = next.start;
-            let depth = next.depth;
-            let factor = next.factor;
-            let offset = next.offset;
-            let sharps = next.sharps;
+        if let Some(tempo) = self.initial.take() {
+            return Some(Event::Tempo {
+                start: Time::zero(),
+                length: Length::zero(),
+                from: tempo.clone(),
+                to: tempo,
+            });
+        }
+
+        loop {
+            if let Some(restore) = self.restores.peek() {
+                let due = self
+                    .queue
+                    .peek()
+                    .is_none_or(|next| restore.at.0 <= next.start.0)
+                    && self
+                        .buffered
+                        .peek()
+                        .is_none_or(|buffered| restore.at.0 <= buffered.0.start.0);
+
+                if due {
+                    let Restore { at, tempo, .. } = self.restores.pop().expect("peeked");
+                    return Some(Event::Tempo {
+                        start: at,
+                        length: Length::zero(),
+                        from: tempo.clone(),
+                        to: tempo,
+                    });
+                }
+            }
+
+            if let Some(buffered) = self.buffered.peek() {
+                let due = self
+                    .queue
+                    .peek()
+                    .is_none_or(|next| buffered.0.start.0 <= next.start.0);
+
+                if due {
+                    let Buffered(played) = self.buffered.pop().expect("peeked");
+                    self.played += 1;
+                    return Some(Event::Note(played));
+                }
+            }
+
+            let next = self.queue.pop()?;
             let melody = next.melody;
-            let length = &melody.length * &factor;
+            let length = &melody.length * &next.factor;
 
-            if depth >= self.evaluator.max_depth || length < self.evaluator.min_length {
+            if next.depth >= self.evaluator.max_depth || length < self.evaluator.min_length {
+                continue;
+            }
+
+            if next
+                .window
+                .as_ref()
+                .is_some_and(|window| window.excludes(&next.start, &length))
+            {
                 continue;
             }
 
             match &melody.node {
                 Node::Pause => {}
                 Node::Note(note) => {
-                    let note = note.add_octave(offset).add_sharp(sharps);
-                    return Some((note, melody.span.clone(), start, length));
+                    let inversions = next.inversions.as_deref();
+                    let Some(note) = transform(note, next.offset, next.sharps, inversions) else {
+                        return Some(Event::Overflow(melody.span.clone()));
+                    };
+
+                    let Some((start, length)) = clip(next.window.as_deref(), &next.start, &length)
+                    else {
+                        continue;
+                    };
+
+                    self.played += 1;
+                    return Some(Event::Note(Played {
+                        note,
+                        span: melody.span.clone(),
+                        start,
+                        length,
+                        velocity: next.velocity,
+                        voice: next.voice,
+                    }));
+                }
+
+                Node::Degree(degree) => {
+                    // Degrees outside of any key are played as pauses if
+                    // there is no default key for this kind of note.
+                    let Some(key) = next.key else {
+                        continue;
+                    };
+
+                    let Some(degree) = degree.checked_add(next.steps) else {
+                        return Some(Event::Overflow(melody.span.clone()));
+                    };
+
+                    let inversions = next.inversions.as_deref();
+                    let Some(note) = key
+                        .degree(degree)
+                        .and_then(|note| transform(&note, next.offset, next.sharps, inversions))
+                    else {
+                        return Some(Event::Overflow(melody.span.clone()));
+                    };
+
+                    let Some((start, length)) = clip(next.window.as_deref(), &next.start, &length)
+                    else {
+                        continue;
+                    };
+
+                    self.played += 1;
+                    return Some(Event::Note(Played {
+                        note,
+                        span: melody.span.clone(),
+                        start,
+                        length,
+                        velocity: next.velocity,
+                        voice: next.voice,
+                    }));
                 }
 
                 Node::Recur(name) => {
@@ -140,14 +483,11 @@ where
                         .get(name)
                         .expect("all names are defined");
 
-                    let melody = A::as_ref(melody);
                     self.queue.push(NextMelody {
-                        melody,
-                        depth: depth + 1,
-                        start,
-                        factor,
-                        offset,
-                        sharps,
+                        melody: A::as_ref(melody),
+                        depth: next.depth + 1,
+                        repetition: 0,
+                        ..next
                     });
                 }
 
@@ -158,92 +498,458 @@ where
                         .get(name)
                         .expect("all names are defined");
 
-                    let melody = A::as_ref(melody);
                     self.queue.push(NextMelody {
-                        melody,
-                        depth,
-                        start,
-                        factor,
-                        offset,
-                        sharps,
+                        melody: A::as_ref(melody),
+                        repetition: 0,
+                        ..next
                     });
                 }
 
-                Node::Scale(scale, melody) => {
-                    let factor = Factor(factor.0 * scale.0.clone());
-                    let melody = A::as_ref(melody);
+                Node::Parameter(index) => {
+                    let arguments = next
+                        .arguments
+                        .as_ref()
+                        .expect("parameters only occur within calls");
+
+                    let melody = &arguments.melodies[*index];
+                    let outer = arguments.outer.clone();
                     self.queue.push(NextMelody {
                         melody,
-                        depth,
-                        start,
-                        factor,
-                        offset,
-                        sharps,
+                        arguments: outer,
+                        repetition: 0,
+                        ..next
                     });
                 }
 
-                Node::Sharp(by, melody) => {
-                    let sharps = sharps + *by;
-                    let melody = A::as_ref(melody);
+                Node::Call(name, args) => {
+                    let melody = self
+                        .evaluator
+                        .program
+                        .get(name)
+                        .expect("all names are defined");
+
+                    let arguments = Arguments {
+                        melodies: A::as_slice(args),
+                        outer: next.arguments.clone(),
+                    };
+
                     self.queue.push(NextMelody {
-                        melody,
-                        depth,
-                        start,
+                        melody: A::as_ref(melody),
+                        arguments: Some(Rc::new(arguments)),
+                        repetition: 0,
+                        ..next
+                    });
+                }
+
+                Node::Scale(scale, inner) => {
+                    let factor = Factor(&next.factor.0 * &scale.0);
+                    self.queue.push(NextMelody {
+                        melody: A::as_ref(inner),
                         factor,
-                        offset,
+                        repetition: 0,
+                        ..next
+                    });
+                }
+
+                Node::Sharp(by, inner) => {
+                    let Some(sharps) = next.sharps.checked_add(*by) else {
+                        return Some(Event::Overflow(melody.span.clone()));
+                    };
+
+                    self.queue.push(NextMelody {
+                        melody: A::as_ref(inner),
                         sharps,
+                        repetition: 0,
+                        ..next
                     });
                 }
 
-                Node::Offset(by, melody) => {
-                    let offset = offset + *by;
-                    let melody = A::as_ref(melody);
+                Node::Offset(by, inner) => {
+                    let Some(offset) = next.offset.checked_add(*by) else {
+                        return Some(Event::Overflow(melody.span.clone()));
+                    };
+
                     self.queue.push(NextMelody {
-                        melody,
-                        depth,
-                        start,
-                        factor,
+                        melody: A::as_ref(inner),
                         offset,
-                        sharps,
+                        repetition: 0,
+                        ..next
+                    });
+                }
+
+                Node::Dynamic(by, inner) => {
+                    let velocity = &next.velocity * by;
+                    self.queue.push(NextMelody {
+                        melody: A::as_ref(inner),
+                        velocity,
+                        repetition: 0,
+                        ..next
+                    });
+                }
+
+                Node::Repeat(count, inner) => {
+                    if count.is_some_and(|count| next.repetition >= count) {
+                        continue;
+                    }
+
+                    // An unending repetition of something which plays no
+                    // notes would never end, so it stops once enough
+                    // repetitions in a row played nothing.
+                    let silent = if next.repetition > 0 && self.played == next.before {
+                        next.silent + 1
+                    } else {
+                        0
+                    };
+
+                    if count.is_none() && silent >= self.evaluator.max_depth {
+                        continue;
+                    }
+
+                    let inner = A::as_ref(inner);
+                    self.queue.push(NextMelody {
+                        melody: inner,
+                        repetition: 0,
+                        ..next.clone()
+                    });
+
+                    // Repetitions are queued one at a time, such that even
+                    // unending repetitions are expanded lazily.
+                    if inner.length > Length::zero() && !inner.length.is_unbounded() {
+                        self.queue.push(NextMelody {
+                            start: &next.start + &(&next.factor * &inner.length),
+                            repetition: next.repetition + 1,
+                            before: self.played,
+                            silent,
+                            ..next
+                        });
+                    }
+                }
+
+                Node::Invert(axis, inner) => {
+                    // Transpositions within the inversion apply before it, so
+                    // they start over from nothing.
+                    let inversion = Inversion {
+                        axis,
+                        offset: next.offset,
+                        sharps: next.sharps,
+                        outer: next.inversions.clone(),
+                    };
+
+                    self.queue.push(NextMelody {
+                        melody: A::as_ref(inner),
+                        offset: 0,
+                        sharps: 0,
+                        repetition: 0,
+                        inversions: Some(Rc::new(inversion)),
+                        ..next
+                    });
+                }
+
+                Node::Key(new, inner) => {
+                    // Scale steps are relative to the key they are applied
+                    // in, so they do not carry over into a new key.
+                    self.queue.push(NextMelody {
+                        melody: A::as_ref(inner),
+                        repetition: 0,
+                        key: Some(new),
+                        steps: 0,
+                        ..next
+                    });
+                }
+
+                Node::Steps(by, inner) => {
+                    let Some(steps) = next.steps.checked_add(*by) else {
+                        return Some(Event::Overflow(melody.span.clone()));
+                    };
+
+                    self.queue.push(NextMelody {
+                        melody: A::as_ref(inner),
+                        repetition: 0,
+                        steps,
+                        ..next
                     });
                 }
 
+                Node::Tempo(from, to, inner) => {
+                    let Some((changed, changing)) =
+                        clip(next.window.as_deref(), &next.start, &length)
+                    else {
+                        continue;
+                    };
+
+                    // Once the melody ends, the surrounding tempo resumes.
+                    if let Length::Bounded(_) = &changing {
+                        self.restores.push(Restore {
+                            at: &changed + &changing,
+                            tempo: next.tempo.clone(),
+                            order: self.restored,
+                        });
+
+                        self.restored += 1;
+                    }
+
+                    // A reversed gradual change goes the other way, such that
+                    // an accelerando becomes a ritardando.
+                    let from = from.as_ref().unwrap_or(next.tempo);
+                    let (first, last) = if next.reversed {
+                        (to, from)
+                    } else {
+                        (from, to)
+                    };
+
+                    // Only the part of a gradual change within a slice is
+                    // played, at the tempos it would have there.
+                    let (from, until) = match (&length, &changing) {
+                        (Length::Bounded(length), Length::Bounded(changing))
+                            if first != last && *length > BigRational::zero() =>
+                        {
+                            let skipped = (&changed.0 - &next.start.0) / length;
+                            let played = (&changed.0 + changing - &next.start.0) / length;
+                            (first.towards(last, &skipped), first.towards(last, &played))
+                        }
+
+                        _ => (first.clone(), last.clone()),
+                    };
+
+                    self.queue.push(NextMelody {
+                        melody: A::as_ref(inner),
+                        repetition: 0,
+                        tempo: to,
+                        ..next
+                    });
+
+                    return Some(Event::Tempo {
+                        start: changed,
+                        length: changing,
+                        from,
+                        to: until,
+                    });
+                }
+
+                Node::Voice(own, inner) => {
+                    let voice = own.within(next.voice);
+                    self.queue.push(NextMelody {
+                        melody: A::as_ref(inner),
+                        repetition: 0,
+                        voice,
+                        ..next
+                    });
+                }
+
+                Node::Slice(from, to, inner) => {
+                    let melody = A::as_ref(inner);
+                    let inner_start = Time(&next.start.0 - &next.factor.0 * &from.0);
+                    let own = Window {
+                        start: next.start.0.clone(),
+                        end: &next.start.0 + &next.factor.0 * (&to.0 - &from.0),
+                    };
+
+                    if !next.reversed {
+                        let window = match &next.window {
+                            Some(outer) => own.within(outer),
+                            None => own,
+                        };
+
+                        self.queue.push(NextMelody {
+                            melody,
+                            start: inner_start,
+                            repetition: 0,
+                            window: Some(Rc::new(window)),
+                            ..next
+                        });
+
+                        continue;
+                    }
+
+                    // A slice cannot be reversed in place, as the melody it is
+                    // taken from may be unbounded. Instead, the notes within
+                    // the slice are played forwards and then mirrored, leaving
+                    // out any changes of tempo.
+                    let mirror = &own.start + &own.end;
+                    let window = next.window.clone();
+                    let nested = Iter {
+                        evaluator: self.evaluator,
+                        initial: None,
+                        restores: BinaryHeap::new(),
+                        restored: 0,
+                        played: 0,
+                        random: Random::new(self.random.next_u64()),
+                        buffered: BinaryHeap::new(),
+                        queue: BinaryHeap::from([NextMelody {
+                            melody,
+                            start: inner_start,
+                            repetition: 0,
+                            reversed: false,
+                            window: Some(Rc::new(own)),
+                            ..next
+                        }]),
+                    };
+
+                    for event in nested {
+                        let Event::Note(mut played) = event else {
+                            continue;
+                        };
+
+                        let Length::Bounded(length) = &played.length else {
+                            unreachable!("individual notes are bounded");
+                        };
+
+                        let mirrored = Time(&mirror - &played.start.0 - length);
+                        let Some((start, length)) =
+                            clip(window.as_deref(), &mirrored, &played.length)
+                        else {
+                            continue;
+                        };
+
+                        played.start = start;
+                        played.length = length;
+                        self.buffered.push(Buffered(played));
+                    }
+                }
+
+                Node::Reverse(inner) => {
+                    self.queue.push(NextMelody {
+                        melody: A::as_ref(inner),
+                        repetition: 0,
+                        reversed: !next.reversed,
+                        ..next
+                    });
+                }
+
+                Node::Sequence(melodies) if next.reversed => {
+                    // Reversed melodies are bounded, so every part of the
+                    // sequence can be placed relative to its end.
+                    let Length::Bounded(mut remaining) = melody.length.clone() else {
+                        unreachable!("reversed melodies are bounded");
+                    };
+
+                    for melody in A::as_slice(melodies) {
+                        let Length::Bounded(length) = &melody.length else {
+                            unreachable!("reversed melodies are bounded");
+                        };
+
+                        remaining -= length;
+                        let start =
+                            &next.start + &(&next.factor * &Length::Bounded(remaining.clone()));
+
+                        self.queue.push(NextMelody {
+                            melody,
+                            start,
+                            repetition: 0,
+                            ..next.clone()
+                        });
+                    }
+                }
+
                 Node::Sequence(melodies) => {
-                    let mut start = start;
+                    let mut start = next.start.clone();
                     for melody in A::as_slice(melodies) {
                         let length = &melody.length;
                         self.queue.push(NextMelody {
                             melody,
-                            depth,
                             start: start.clone(),
-                            factor: factor.clone(),
-                            offset,
-                            sharps,
+                            repetition: 0,
+                            ..next.clone()
                         });
 
                         if matches!(length, Length::Unbounded) {
                             break;
                         }
 
-                        start = &start + &(&factor * length);
+                        start = &start + &(&next.factor * length);
                     }
                 }
 
+                Node::Choice(weights, melodies) => {
+                    let Some(index) = self.random.pick(weights) else {
+                        continue;
+                    };
+
+                    let chosen = &A::as_slice(melodies)[index];
+                    let start = if next.reversed {
+                        mirror(&next.start, &next.factor, &melody.length, &chosen.length)
+                    } else {
+                        next.start.clone()
+                    };
+
+                    self.queue.push(NextMelody {
+                        melody: chosen,
+                        start,
+                        repetition: 0,
+                        ..next
+                    });
+                }
+
                 Node::Stack(melodies) => {
-                    for melody in A::as_slice(melodies) {
+                    for part in A::as_slice(melodies) {
+                        let start = if next.reversed {
+                            mirror(&next.start, &next.factor, &melody.length, &part.length)
+                        } else {
+                            next.start.clone()
+                        };
+
                         self.queue.push(NextMelody {
-                            melody,
-                            depth,
-                            start: start.clone(),
-                            factor: factor.clone(),
-                            offset,
-                            sharps,
+                            melody: part,
+                            start,
+                            repetition: 0,
+                            ..next.clone()
                         });
                     }
                 }
             }
         }
+    }
+}
+
+/// Get where a part of a reversed melody starts, such that it ends where the
+/// whole melody ends.
+fn mirror(start: &Time, factor: &Factor, whole: &Length, part: &Length) -> Time {
+    let (Length::Bounded(whole), Length::Bounded(part)) = (whole, part) else {
+        unreachable!("reversed melodies are bounded");
+    };
 
-        None
+    start + &(factor * &Length::Bounded(whole - part))
+}
+
+/// Apply a transposition and any inversions to a note, or give `None` if the
+/// note ends up out of range.
+fn transform<N: Note>(
+    note: &N,
+    offset: isize,
+    sharps: isize,
+    inversions: Option<&Inversion<N>>,
+) -> Option<N> {
+    let mut note = note.add_octave(offset)?.add_sharp(sharps)?;
+    let mut inversion = inversions;
+
+    while let Some(Inversion {
+        axis,
+        offset,
+        sharps,
+        outer,
+    }) = inversion
+    {
+        note = note.invert(axis)?.add_octave(*offset)?.add_sharp(*sharps)?;
+        inversion = outer.as_deref();
     }
+
+    Some(note)
+}
+
+/// Get the part of a melody starting at `start` and lasting `length` which
+/// lies within a window, if any of it does.
+fn clip(window: Option<&Window>, start: &Time, length: &Length) -> Option<(Time, Length)> {
+    let Some(window) = window else {
+        return Some((start.clone(), length.clone()));
+    };
+
+    let end = match length {
+        Length::Bounded(length) => (&start.0 + length).min(window.end.clone()),
+        Length::Unbounded => window.end.clone(),
+    };
+
+    let start = start.0.clone().max(window.start.clone());
+    let empty = end < start || (end == start && *length != Length::zero());
+
+    (!empty).then(|| (Time(start.clone()), Length::Bounded(end - start)))
 }
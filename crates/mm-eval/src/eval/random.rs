@@ -0,0 +1,85 @@
+/// A small pseudorandom number generator (SplitMix64), such that melodies with
+/// choices play the same way every time they are evaluated with the same seed.
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Pick an index into `weights`, where every index is picked with a chance
+    /// proportional to its weight. Gives `None` if all weights are zero.
+    pub fn pick(&mut self, weights: &[usize]) -> Option<usize> {
+        let total: u128 = weights.iter().map(|weight| *weight as u128).sum();
+        if total == 0 {
+            return None;
+        }
+
+        // The total may exceed any single draw, so two are joined together.
+        // Any bias from the remainder is too small to hear.
+        let draw = u128::from(self.next_u64()) << 64 | u128::from(self.next_u64());
+        let mut at = draw % total;
+        for (index, weight) in weights.iter().enumerate() {
+            let weight = *weight as u128;
+            if at < weight {
+                return Some(index);
+            }
+
+            at -= weight;
+        }
+
+        unreachable!("the picked point lies below the total weight")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Random;
+
+    #[test]
+    fn reproducible() {
+        let mut a = Random::new(7);
+        let mut b = Random::new(7);
+
+        for _ in 0..10 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+    }
+
+    #[test]
+    fn weights() {
+        let mut random = Random::new(1);
+        let mut counts = [0; 3];
+
+        for _ in 0..1000 {
+            counts[random.pick(&[1, 0, 3]).unwrap()] += 1;
+        }
+
+        assert_eq!(0, counts[1]);
+        assert!(counts[2] > 2 * counts[0]);
+        assert_eq!(None, random.pick(&[0, 0]));
+    }
+
+    #[test]
+    fn large_weights() {
+        let mut random = Random::new(1);
+        let mut counts = [0; 2];
+
+        for _ in 0..1000 {
+            counts[random.pick(&[usize::MAX, usize::MAX]).unwrap()] += 1;
+        }
+
+        assert!(counts[0] > 0 && counts[1] > 0);
+    }
+}
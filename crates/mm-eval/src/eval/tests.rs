@@ -3,24 +3,36 @@ use std::collections::HashMap;
 use num_bigint::BigInt;
 use num_rational::BigRational;
 
+use crate::key::{Key, Mode};
 use crate::melody::{Melody, Node};
 use crate::names::names;
+use crate::note::Note;
 use crate::span::{span, Span};
-use crate::{Allocator, Factor, Heap, Length, Name, Time};
+use crate::{Allocator, Factor, Heap, Length, Name, Tempo, Time, Velocity, Voice};
 
-use super::Evaluator;
+use super::{Evaluator, Event, Played};
 
 fn r(n: i128, d: i128) -> BigRational {
     BigRational::new(BigInt::from(n), BigInt::from(d))
 }
 
+fn tuple<N>(played: Played<N, &str>) -> (N, Span<&str>, Time, Length, Velocity) {
+    (
+        played.note,
+        played.span,
+        played.start,
+        played.length,
+        played.velocity,
+    )
+}
+
 fn check(
-    expected: Vec<(char, Span<&str>, Time, Length)>,
+    expected: Vec<(char, Span<&str>, Time, Length, Velocity)>,
     program: HashMap<Name, <Heap as Allocator<Melody<char, &str, Heap>>>::Holder>,
     entry: Name,
 ) {
     let eval: Evaluator<_, _, Heap> = Evaluator::new(&program, entry);
-    let actual: Vec<_> = eval.iter().collect();
+    let actual: Vec<_> = eval.iter().map(tuple).collect();
     assert_eq!(expected, actual);
 }
 
@@ -56,8 +68,8 @@ fn simple_sequence() {
     let program = HashMap::from([(name("it"), Box::new(melody))]);
 
     let expected = vec![
-        ('a', span, Time(r(0, 1)), Length::one()),
-        ('c', span, Time(r(2, 1)), Length::one()),
+        ('a', span, Time(r(0, 1)), Length::one(), Velocity::one()),
+        ('c', span, Time(r(2, 1)), Length::one(), Velocity::one()),
     ];
 
     check(expected, program, name("it"));
@@ -95,8 +107,8 @@ fn simple_stack() {
     let program = HashMap::from([(name("it"), Box::new(melody))]);
 
     let expected = vec![
-        ('a', span, Time::zero(), Length::one()),
-        ('c', span, Time::zero(), Length::one()),
+        ('a', span, Time::zero(), Length::one(), Velocity::one()),
+        ('c', span, Time::zero(), Length::one(), Velocity::one()),
     ];
 
     check(expected, program, name("it"));
@@ -183,21 +195,21 @@ fn unending_stack() {
         Evaluator::new(&program, name("stack")).with_max_depth(5);
 
     let expected = vec![
-        ('a', span, Time(r(0, 1)), Length::one()),
-        ('c', span, Time(r(0, 1)), Length::one()),
-        ('b', span, Time(r(1, 1)), Length::one()),
-        ('d', span, Time(r(1, 1)), Length::one()),
-        ('a', span, Time(r(2, 1)), Length::one()),
-        ('c', span, Time(r(2, 1)), Length::one()),
-        ('b', span, Time(r(3, 1)), Length::one()),
-        ('d', span, Time(r(3, 1)), Length::one()),
-        ('a', span, Time(r(4, 1)), Length::one()),
-        ('c', span, Time(r(4, 1)), Length::one()),
-        ('b', span, Time(r(5, 1)), Length::one()),
-        ('d', span, Time(r(5, 1)), Length::one()),
+        ('a', span, Time(r(0, 1)), Length::one(), Velocity::one()),
+        ('c', span, Time(r(0, 1)), Length::one(), Velocity::one()),
+        ('b', span, Time(r(1, 1)), Length::one(), Velocity::one()),
+        ('d', span, Time(r(1, 1)), Length::one(), Velocity::one()),
+        ('a', span, Time(r(2, 1)), Length::one(), Velocity::one()),
+        ('c', span, Time(r(2, 1)), Length::one(), Velocity::one()),
+        ('b', span, Time(r(3, 1)), Length::one(), Velocity::one()),
+        ('d', span, Time(r(3, 1)), Length::one(), Velocity::one()),
+        ('a', span, Time(r(4, 1)), Length::one(), Velocity::one()),
+        ('c', span, Time(r(4, 1)), Length::one(), Velocity::one()),
+        ('b', span, Time(r(5, 1)), Length::one(), Velocity::one()),
+        ('d', span, Time(r(5, 1)), Length::one(), Velocity::one()),
     ];
 
-    let actual: Vec<_> = evaluator.iter().take(12).collect();
+    let actual: Vec<_> = evaluator.iter().take(12).map(tuple).collect();
 
     assert_eq!(expected, actual);
 }
@@ -237,14 +249,44 @@ fn fractal() {
         Evaluator::new(&program, name("fractal")).with_max_depth(5);
 
     let expected = vec![
-        ('a', span, Time(r(0, 1)), Length::Bounded(r(1, 1))),
-        ('a', span, Time(r(1, 1)), Length::Bounded(r(1, 2))),
-        ('a', span, Time(r(3, 2)), Length::Bounded(r(1, 4))),
-        ('a', span, Time(r(7, 4)), Length::Bounded(r(1, 8))),
-        ('a', span, Time(r(15, 8)), Length::Bounded(r(1, 16))),
+        (
+            'a',
+            span,
+            Time(r(0, 1)),
+            Length::Bounded(r(1, 1)),
+            Velocity::one(),
+        ),
+        (
+            'a',
+            span,
+            Time(r(1, 1)),
+            Length::Bounded(r(1, 2)),
+            Velocity::one(),
+        ),
+        (
+            'a',
+            span,
+            Time(r(3, 2)),
+            Length::Bounded(r(1, 4)),
+            Velocity::one(),
+        ),
+        (
+            'a',
+            span,
+            Time(r(7, 4)),
+            Length::Bounded(r(1, 8)),
+            Velocity::one(),
+        ),
+        (
+            'a',
+            span,
+            Time(r(15, 8)),
+            Length::Bounded(r(1, 16)),
+            Velocity::one(),
+        ),
     ];
 
-    let actual: Vec<_> = evaluator.iter().take(100).collect();
+    let actual: Vec<_> = evaluator.iter().take(100).map(tuple).collect();
 
     assert_eq!(expected, actual);
 }
@@ -262,3 +304,884 @@ fn empty_recursive() {
     let program = HashMap::from([(name("x"), Box::new(x))]);
     check(vec![], program, name("x"))
 }
+
+#[test]
+fn nested_dynamics() {
+    let mut name = names();
+    let span = span();
+
+    let quiet = Velocity(r(1, 2));
+    let loud = Velocity(r(3, 2));
+
+    let a = Melody {
+        node: Node::Note('a'),
+        length: Length::one(),
+        span,
+    };
+
+    let b = Melody {
+        node: Node::Note('b'),
+        length: Length::one(),
+        span,
+    };
+
+    let b = Melody {
+        node: Node::Dynamic(loud, Box::new(b)),
+        length: Length::one(),
+        span,
+    };
+
+    let sequence = Melody {
+        node: Node::Sequence(vec![a, b]),
+        length: Length::Bounded(r(2, 1)),
+        span,
+    };
+
+    let melody = Melody {
+        node: Node::Dynamic(quiet, Box::new(sequence)),
+        length: Length::Bounded(r(2, 1)),
+        span,
+    };
+
+    let program = HashMap::from([(name("it"), Box::new(melody))]);
+
+    let expected = vec![
+        ('a', span, Time(r(0, 1)), Length::one(), Velocity(r(1, 2))),
+        ('b', span, Time(r(1, 1)), Length::one(), Velocity(r(3, 4))),
+    ];
+
+    check(expected, program, name("it"));
+}
+
+#[test]
+fn arguments() {
+    let mut name = names();
+    let span = span();
+
+    let a = Melody {
+        node: Node::Note('a'),
+        length: Length::one(),
+        span,
+    };
+
+    let b = Melody {
+        node: Node::Note('b'),
+        length: Length::one(),
+        span,
+    };
+
+    let x1 = Melody {
+        node: Node::Parameter(0),
+        length: Length::one(),
+        span,
+    };
+
+    let y = Melody {
+        node: Node::Parameter(1),
+        length: Length::one(),
+        span,
+    };
+
+    let x2 = Melody {
+        node: Node::Parameter(0),
+        length: Length::one(),
+        span,
+    };
+
+    let f = Melody {
+        node: Node::Sequence(vec![x1, y, x2]),
+        length: Length::Bounded(r(3, 1)),
+        span,
+    };
+
+    let it = Melody {
+        node: Node::Call(name("f"), vec![a, b]),
+        length: Length::Bounded(r(3, 1)),
+        span,
+    };
+
+    let program = HashMap::from([(name("it"), Box::new(it)), (name("f"), Box::new(f))]);
+
+    let expected = vec![
+        ('a', span, Time(r(0, 1)), Length::one(), Velocity::one()),
+        ('b', span, Time(r(1, 1)), Length::one(), Velocity::one()),
+        ('a', span, Time(r(2, 1)), Length::one(), Velocity::one()),
+    ];
+
+    check(expected, program, name("it"));
+}
+
+#[test]
+fn repeats() {
+    let mut name = names();
+    let span = span();
+
+    let a = Melody {
+        node: Node::Note('a'),
+        length: Length::one(),
+        span,
+    };
+
+    let b = Melody {
+        node: Node::Note('b'),
+        length: Length::one(),
+        span,
+    };
+
+    let sequence = Melody {
+        node: Node::Sequence(vec![a, b]),
+        length: Length::Bounded(r(2, 1)),
+        span,
+    };
+
+    let melody = Melody {
+        node: Node::Repeat(Some(2), Box::new(sequence)),
+        length: Length::Bounded(r(4, 1)),
+        span,
+    };
+
+    let program = HashMap::from([(name("it"), Box::new(melody))]);
+
+    let expected = vec![
+        ('a', span, Time(r(0, 1)), Length::one(), Velocity::one()),
+        ('b', span, Time(r(1, 1)), Length::one(), Velocity::one()),
+        ('a', span, Time(r(2, 1)), Length::one(), Velocity::one()),
+        ('b', span, Time(r(3, 1)), Length::one(), Velocity::one()),
+    ];
+
+    check(expected, program, name("it"));
+}
+
+#[test]
+fn repeats_forever() {
+    let mut name = names();
+    let span = span();
+
+    let a = Melody {
+        node: Node::Note('a'),
+        length: Length::one(),
+        span,
+    };
+
+    let melody = Melody {
+        node: Node::Repeat(None, Box::new(a)),
+        length: Length::Unbounded,
+        span,
+    };
+
+    let program = HashMap::from([(name("it"), Box::new(melody))]);
+    let eval: Evaluator<_, _, Heap> = Evaluator::new(&program, name("it"));
+    let starts: Vec<_> = eval.iter().take(3).map(|played| played.start).collect();
+
+    assert_eq!(vec![Time(r(0, 1)), Time(r(1, 1)), Time(r(2, 1))], starts);
+}
+
+#[test]
+fn repeats_nothing_forever() {
+    let mut name = names();
+    let span = span();
+
+    let pause = Melody {
+        node: Node::Pause,
+        length: Length::one(),
+        span,
+    };
+
+    let pauses = Melody {
+        node: Node::Repeat(None, Box::new(pause)),
+        length: Length::Unbounded,
+        span,
+    };
+
+    let a = Melody {
+        node: Node::Note('a'),
+        length: Length::one(),
+        span,
+    };
+
+    let melody = Melody {
+        node: Node::Stack(vec![a, pauses]),
+        length: Length::Unbounded,
+        span,
+    };
+
+    let program = HashMap::from([(name("it"), Box::new(melody))]);
+    let expected = vec![('a', span, Time(r(0, 1)), Length::one(), Velocity::one())];
+    check(expected, program, name("it"));
+}
+
+#[test]
+fn reversed() {
+    let mut name = names();
+    let span = span();
+
+    let a = Melody {
+        node: Node::Note('a'),
+        length: Length::one(),
+        span,
+    };
+
+    let b = Melody {
+        node: Node::Note('b'),
+        length: Length::one(),
+        span,
+    };
+
+    let b = Melody {
+        node: Node::Scale(Factor(r(2, 1)), Box::new(b)),
+        length: Length::Bounded(r(2, 1)),
+        span,
+    };
+
+    let c = Melody {
+        node: Node::Note('c'),
+        length: Length::one(),
+        span,
+    };
+
+    // ~(a, 2 b, ~~c)
+    let c = Melody {
+        node: Node::Reverse(Box::new(c)),
+        length: Length::one(),
+        span,
+    };
+
+    let c = Melody {
+        node: Node::Reverse(Box::new(c)),
+        length: Length::one(),
+        span,
+    };
+
+    let sequence = Melody {
+        node: Node::Sequence(vec![a, b, c]),
+        length: Length::Bounded(r(4, 1)),
+        span,
+    };
+
+    let melody = Melody {
+        node: Node::Reverse(Box::new(sequence)),
+        length: Length::Bounded(r(4, 1)),
+        span,
+    };
+
+    let program = HashMap::from([(name("it"), Box::new(melody))]);
+
+    let expected = vec![
+        ('c', span, Time(r(0, 1)), Length::one(), Velocity::one()),
+        (
+            'b',
+            span,
+            Time(r(1, 1)),
+            Length::Bounded(r(2, 1)),
+            Velocity::one(),
+        ),
+        ('a', span, Time(r(3, 1)), Length::one(), Velocity::one()),
+    ];
+
+    check(expected, program, name("it"));
+}
+
+/// A note which is some number of semitones above an arbitrary root.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Semitones(isize);
+
+impl Note for Semitones {
+    fn parse(_: &str) -> Option<Self> {
+        None
+    }
+
+    fn add_sharp(&self, by: isize) -> Option<Self> {
+        self.0.checked_add(by).map(Self)
+    }
+
+    fn add_octave(&self, by: isize) -> Option<Self> {
+        self.0.checked_add(by.checked_mul(12)?).map(Self)
+    }
+
+    fn invert(&self, axis: &Self) -> Option<Self> {
+        axis.0.checked_mul(2)?.checked_sub(self.0).map(Self)
+    }
+}
+
+#[test]
+fn inversions() {
+    let mut name = names();
+    let span = span();
+
+    let note = |n| Melody {
+        node: Node::Note(Semitones(n)),
+        length: Length::one(),
+        span,
+    };
+
+    let wrap = |node| Melody {
+        node,
+        length: Length::one(),
+        span,
+    };
+
+    // (inv(0) (5^2))^1
+    let first = wrap(Node::Sharp(2, Box::new(note(5))));
+    let first = wrap(Node::Invert(Semitones(0), Box::new(first)));
+    let first = wrap(Node::Sharp(1, Box::new(first)));
+
+    // inv(10) inv(0) 3
+    let second = wrap(Node::Invert(Semitones(0), Box::new(note(3))));
+    let second = wrap(Node::Invert(Semitones(10), Box::new(second)));
+
+    let melody = Melody {
+        node: Node::Sequence(vec![first, second]),
+        length: Length::Bounded(r(2, 1)),
+        span,
+    };
+
+    let program = HashMap::from([(name("it"), Box::new(melody))]);
+    let eval: Evaluator<_, _, Heap> = Evaluator::new(&program, name("it"));
+    let notes: Vec<_> = eval.iter().map(|played| played.note).collect();
+
+    assert_eq!(vec![Semitones(-6), Semitones(23)], notes);
+}
+
+#[test]
+fn degrees() {
+    let mut name = names();
+    let span = span();
+
+    let degree = |d| Melody {
+        node: Node::Degree(d),
+        length: Length::one(),
+        span,
+    };
+
+    let wrap = |node| Melody {
+        node,
+        length: Length::one(),
+        span,
+    };
+
+    let dorian = Key {
+        tonic: Semitones(2),
+        mode: Mode::Dorian,
+    };
+
+    // 3, in D dorian 7^^1, in D dorian 3#
+    let first = degree(3);
+    let second = wrap(Node::Steps(1, Box::new(degree(7))));
+    let second = wrap(Node::Key(dorian.clone(), Box::new(second)));
+    let third = wrap(Node::Sharp(1, Box::new(degree(3))));
+    let third = wrap(Node::Key(dorian, Box::new(third)));
+
+    let melody = Melody {
+        node: Node::Sequence(vec![first, second, third]),
+        length: Length::Bounded(r(3, 1)),
+        span,
+    };
+
+    let program = HashMap::from([(name("it"), Box::new(melody))]);
+    let major = Key {
+        tonic: Semitones(0),
+        mode: Mode::Ionian,
+    };
+
+    let eval: Evaluator<_, _, Heap> = Evaluator::new(&program, name("it")).with_key(major);
+    let notes: Vec<_> = eval.iter().map(|played| played.note).collect();
+    assert_eq!(vec![Semitones(4), Semitones(14), Semitones(6)], notes);
+
+    // Without a key to play them in, degrees outside of any key are skipped.
+    let eval: Evaluator<_, _, Heap> = Evaluator::new(&program, name("it"));
+    let notes: Vec<_> = eval.iter().map(|played| played.note).collect();
+    assert_eq!(vec![Semitones(14), Semitones(6)], notes);
+}
+
+#[test]
+fn tempos() {
+    let mut name = names();
+    let span = span();
+    let bpm = |bpm: i32| Tempo::from_bpm(BigInt::from(bpm)).unwrap();
+
+    let note = |c| Melody {
+        node: Node::Note(c),
+        length: Length::one(),
+        span,
+    };
+
+    // tempo(60) (a, tempo(..90) b), c
+    let inner = Melody {
+        node: Node::Tempo(None, bpm(90), Box::new(note('b'))),
+        length: Length::one(),
+        span,
+    };
+
+    let outer = Melody {
+        node: Node::Sequence(vec![note('a'), inner]),
+        length: Length::Bounded(r(2, 1)),
+        span,
+    };
+
+    let outer = Melody {
+        node: Node::Tempo(Some(bpm(60)), bpm(60), Box::new(outer)),
+        length: Length::Bounded(r(2, 1)),
+        span,
+    };
+
+    let melody = Melody {
+        node: Node::Sequence(vec![outer, note('c')]),
+        length: Length::Bounded(r(3, 1)),
+        span,
+    };
+
+    let program = HashMap::from([(name("it"), Box::new(melody))]);
+    let eval: Evaluator<_, _, Heap> = Evaluator::new(&program, name("it")).with_tempo(bpm(100));
+    let actual: Vec<_> = eval.events().collect();
+
+    let tempo = |at, length, from, to| Event::Tempo {
+        start: Time(r(at, 1)),
+        length: Length::Bounded(r(length, 1)),
+        from: bpm(from),
+        to: bpm(to),
+    };
+
+    let note = |c, at| {
+        Event::Note(Played {
+            note: c,
+            span,
+            start: Time(r(at, 1)),
+            length: Length::one(),
+            velocity: Velocity::one(),
+            voice: Voice::default(),
+        })
+    };
+
+    let expected = vec![
+        tempo(0, 0, 100, 100),
+        tempo(0, 2, 60, 60),
+        note('a', 0),
+        tempo(1, 1, 60, 90),
+        note('b', 1),
+        tempo(2, 0, 60, 60),
+        tempo(2, 0, 100, 100),
+        note('c', 2),
+    ];
+
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn reversed_tempos() {
+    let mut name = names();
+    let span = span();
+    let bpm = |bpm: i32| Tempo::from_bpm(BigInt::from(bpm)).unwrap();
+
+    let note = |c| Melody {
+        node: Node::Note(c),
+        length: Length::one(),
+        span,
+    };
+
+    // ~(tempo(60..120) (a, b)), ~(tempo(60..120) (a, b))[1..2]
+    let reversed = || {
+        let sequence = Melody {
+            node: Node::Sequence(vec![note('a'), note('b')]),
+            length: Length::Bounded(r(2, 1)),
+            span,
+        };
+
+        let changing = Melody {
+            node: Node::Tempo(Some(bpm(60)), bpm(120), Box::new(sequence)),
+            length: Length::Bounded(r(2, 1)),
+            span,
+        };
+
+        Melody {
+            node: Node::Reverse(Box::new(changing)),
+            length: Length::Bounded(r(2, 1)),
+            span,
+        }
+    };
+
+    let sliced = Melody {
+        node: Node::Slice(Time(r(1, 1)), Time(r(2, 1)), Box::new(reversed())),
+        length: Length::one(),
+        span,
+    };
+
+    let melody = Melody {
+        node: Node::Sequence(vec![reversed(), sliced]),
+        length: Length::Bounded(r(3, 1)),
+        span,
+    };
+
+    let program = HashMap::from([(name("it"), Box::new(melody))]);
+    let eval: Evaluator<_, _, Heap> = Evaluator::new(&program, name("it")).with_tempo(bpm(100));
+    let actual: Vec<_> = eval.events().collect();
+
+    let tempo = |at, length, from, to| Event::Tempo {
+        start: Time(r(at, 1)),
+        length: Length::Bounded(r(length, 1)),
+        from: bpm(from),
+        to: bpm(to),
+    };
+
+    let note = |c, at| {
+        Event::Note(Played {
+            note: c,
+            span,
+            start: Time(r(at, 1)),
+            length: Length::one(),
+            velocity: Velocity::one(),
+            voice: Voice::default(),
+        })
+    };
+
+    let expected = vec![
+        tempo(0, 0, 100, 100),
+        tempo(0, 2, 120, 60),
+        note('b', 0),
+        note('a', 1),
+        tempo(2, 0, 100, 100),
+        tempo(2, 1, 90, 60),
+        note('a', 2),
+        tempo(3, 0, 100, 100),
+    ];
+
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn voices() {
+    let mut name = names();
+    let span = span();
+
+    let note = |c| Melody {
+        node: Node::Note(c),
+        length: Length::one(),
+        span,
+    };
+
+    let channel = Voice::channel(2).unwrap();
+    let bass = Voice::instrument(33).unwrap();
+
+    // (instrument(33) a | b)@ch2, c
+    let a = Melody {
+        node: Node::Voice(bass, Box::new(note('a'))),
+        length: Length::one(),
+        span,
+    };
+
+    let stack = Melody {
+        node: Node::Stack(vec![a, note('b')]),
+        length: Length::one(),
+        span,
+    };
+
+    let stack = Melody {
+        node: Node::Voice(channel, Box::new(stack)),
+        length: Length::one(),
+        span,
+    };
+
+    let melody = Melody {
+        node: Node::Sequence(vec![stack, note('c')]),
+        length: Length::Bounded(r(2, 1)),
+        span,
+    };
+
+    let program = HashMap::from([(name("it"), Box::new(melody))]);
+    let eval: Evaluator<_, _, Heap> = Evaluator::new(&program, name("it"));
+    let mut actual: Vec<_> = eval
+        .iter()
+        .map(|played| (played.note, played.voice))
+        .collect();
+
+    // Notes which start together come in no particular order.
+    actual.sort();
+
+    let expected = vec![
+        ('a', bass.within(channel)),
+        ('b', channel),
+        ('c', Voice::default()),
+    ];
+
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn choices() {
+    let mut name = names();
+    let span = span();
+
+    let note = |c| Melody {
+        node: Node::Note(c),
+        length: Length::one(),
+        span,
+    };
+
+    let choice = Melody {
+        node: Node::Choice(vec![1, 1], vec![note('a'), note('b')]),
+        length: Length::one(),
+        span,
+    };
+
+    let melody = Melody {
+        node: Node::Repeat(Some(20), Box::new(choice)),
+        length: Length::Bounded(r(20, 1)),
+        span,
+    };
+
+    let it = name("it");
+    let program = HashMap::from([(it, Box::new(melody))]);
+    let notes = |seed| {
+        let eval: Evaluator<_, _, Heap> = Evaluator::new(&program, it).with_seed(seed);
+        eval.iter().map(|played| played.note).collect::<String>()
+    };
+
+    let first = notes(1);
+    assert_eq!(20, first.len());
+    assert!(first.contains('a') && first.contains('b'));
+    assert_eq!(first, notes(1));
+    assert_ne!(first, notes(2));
+}
+
+#[test]
+fn reversed_branches() {
+    let mut name = names();
+    let span = span();
+
+    let note = |c| Melody {
+        node: Node::Note(c),
+        length: Length::one(),
+        span,
+    };
+
+    let sequence = || Melody {
+        node: Node::Sequence(vec![note('a'), note('b')]),
+        length: Length::Bounded(r(2, 1)),
+        span,
+    };
+
+    // ~((a, b) | c), ~{(a, b): 0 | c: 1}
+    let stack = Melody {
+        node: Node::Stack(vec![sequence(), note('c')]),
+        length: Length::Bounded(r(2, 1)),
+        span,
+    };
+
+    let choice = Melody {
+        node: Node::Choice(vec![0, 1], vec![sequence(), note('c')]),
+        length: Length::Bounded(r(2, 1)),
+        span,
+    };
+
+    let reverse = |melody| Melody {
+        node: Node::Reverse(Box::new(melody)),
+        length: Length::Bounded(r(2, 1)),
+        span,
+    };
+
+    let melody = Melody {
+        node: Node::Sequence(vec![reverse(stack), reverse(choice)]),
+        length: Length::Bounded(r(4, 1)),
+        span,
+    };
+
+    let program = HashMap::from([(name("it"), Box::new(melody))]);
+
+    let expected = vec![
+        ('b', span, Time(r(0, 1)), Length::one(), Velocity::one()),
+        ('a', span, Time(r(1, 1)), Length::one(), Velocity::one()),
+        ('c', span, Time(r(1, 1)), Length::one(), Velocity::one()),
+        ('c', span, Time(r(3, 1)), Length::one(), Velocity::one()),
+    ];
+
+    check(expected, program, name("it"));
+}
+
+#[test]
+fn slices() {
+    let mut name = names();
+    let span = span();
+
+    let note = |c| Melody {
+        node: Node::Note(c),
+        length: Length::one(),
+        span,
+    };
+
+    // (a, 2 b)*oo[1..4], ~(a, 2 b)*oo[0..2], c
+    let sequence = || Melody {
+        node: Node::Sequence(vec![
+            note('a'),
+            Melody {
+                node: Node::Scale(Factor(r(2, 1)), Box::new(note('b'))),
+                length: Length::Bounded(r(2, 1)),
+                span,
+            },
+        ]),
+        length: Length::Bounded(r(3, 1)),
+        span,
+    };
+
+    let repeated = || Melody {
+        node: Node::Repeat(None, Box::new(sequence())),
+        length: Length::Unbounded,
+        span,
+    };
+
+    let first = Melody {
+        node: Node::Slice(Time(r(1, 1)), Time(r(4, 1)), Box::new(repeated())),
+        length: Length::Bounded(r(3, 1)),
+        span,
+    };
+
+    let second = Melody {
+        node: Node::Slice(Time(r(0, 1)), Time(r(2, 1)), Box::new(repeated())),
+        length: Length::Bounded(r(2, 1)),
+        span,
+    };
+
+    let second = Melody {
+        node: Node::Reverse(Box::new(second)),
+        length: Length::Bounded(r(2, 1)),
+        span,
+    };
+
+    let melody = Melody {
+        node: Node::Sequence(vec![first, second, note('c')]),
+        length: Length::Bounded(r(6, 1)),
+        span,
+    };
+
+    let program = HashMap::from([(name("it"), Box::new(melody))]);
+
+    let expected = vec![
+        (
+            'b',
+            span,
+            Time(r(0, 1)),
+            Length::Bounded(r(2, 1)),
+            Velocity::one(),
+        ),
+        ('a', span, Time(r(2, 1)), Length::one(), Velocity::one()),
+        ('b', span, Time(r(3, 1)), Length::one(), Velocity::one()),
+        ('a', span, Time(r(4, 1)), Length::one(), Velocity::one()),
+        ('c', span, Time(r(5, 1)), Length::one(), Velocity::one()),
+    ];
+
+    check(expected, program, name("it"));
+}
+
+#[test]
+fn overflows() {
+    let mut name = names();
+    let span = span();
+    let at = |start| Span {
+        source: "",
+        start,
+        end: start + 1,
+    };
+
+    let note = |c| Melody {
+        node: Node::Note(c),
+        length: Length::one(),
+        span,
+    };
+
+    // (a^oo)^1, (a+oo)+1, b, where oo stands for the largest transposition.
+    // Transpositions add up from the outside in, so the inner ones overflow.
+    let sharp = |by, melody, span| Melody {
+        node: Node::Sharp(by, Box::new(melody)),
+        length: Length::one(),
+        span,
+    };
+
+    let offset = |by, melody, span| Melody {
+        node: Node::Offset(by, Box::new(melody)),
+        length: Length::one(),
+        span,
+    };
+
+    let sharps = sharp(1, sharp(isize::MAX, note('a'), at(1)), span);
+    let offsets = offset(1, offset(isize::MAX, note('a'), at(2)), span);
+
+    let melody = Melody {
+        node: Node::Sequence(vec![sharps, offsets, note('b')]),
+        length: Length::Bounded(r(3, 1)),
+        span,
+    };
+
+    let program = HashMap::from([(name("it"), Box::new(melody))]);
+    let eval: Evaluator<_, _, Heap> = Evaluator::new(&program, name("it"));
+    let actual: Vec<_> = eval.events().skip(1).collect();
+
+    let expected = vec![
+        Event::Overflow(at(1)),
+        Event::Overflow(at(2)),
+        Event::Note(Played {
+            note: 'b',
+            span,
+            start: Time(r(2, 1)),
+            length: Length::one(),
+            velocity: Velocity::one(),
+            voice: Voice::default(),
+        }),
+    ];
+
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn note_overflows() {
+    let mut name = names();
+    let span = span();
+    let at = |start| Span {
+        source: "",
+        start,
+        end: start + 1,
+    };
+
+    let note = |n, span| Melody {
+        node: Node::Note(Semitones(n)),
+        length: Length::one(),
+        span,
+    };
+
+    let wrap = |node| Melody {
+        node,
+        length: Length::one(),
+        span,
+    };
+
+    // The transpositions fit on their own, but the notes they give do not.
+    let sharp = wrap(Node::Sharp(2, Box::new(note(isize::MAX - 1, at(1)))));
+    let offset = wrap(Node::Offset(-1, Box::new(note(isize::MIN, at(2)))));
+    let inverted = wrap(Node::Invert(
+        Semitones(isize::MAX),
+        Box::new(note(0, at(3))),
+    ));
+
+    let melody = Melody {
+        node: Node::Sequence(vec![sharp, offset, inverted, note(0, span)]),
+        length: Length::Bounded(r(4, 1)),
+        span,
+    };
+
+    let program = HashMap::from([(name("it"), Box::new(melody))]);
+    let eval: Evaluator<_, _, Heap> = Evaluator::new(&program, name("it"));
+    let actual: Vec<_> = eval.events().skip(1).collect();
+
+    let expected = vec![
+        Event::Overflow(at(1)),
+        Event::Overflow(at(2)),
+        Event::Overflow(at(3)),
+        Event::Note(Played {
+            note: Semitones(0),
+            span,
+            start: Time(r(3, 1)),
+            length: Length::one(),
+            velocity: Velocity::one(),
+            voice: Voice::default(),
+        }),
+    ];
+
+    assert_eq!(expected, actual);
+}
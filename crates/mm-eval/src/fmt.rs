@@ -0,0 +1,210 @@
+//! Formatting of sources, which only ever changes the whitespace between
+//! tokens, such that comments and parentheses are kept as they are.
+
+use crate::note::Note;
+use crate::parse::cst::{Child, Kind, Node, Tree};
+use crate::parse::lex::Token;
+use crate::parse::{Error, Parser};
+use crate::{Heap, Names};
+
+/// Format `source`, which must parse without errors. Commas, semicolons and
+/// equals signs are followed by a single space and pipes are surrounded by one,
+/// unless they are at the end or start of a line. The equals signs of definitions on
+/// consecutive lines are aligned, and trailing whitespace is removed.
+pub fn format<N: Note, Id: Clone + Eq>(name: Id, source: &str) -> Result<String, Vec<Error<Id>>> {
+    let (parsed, tree) =
+        Parser::<N, Id, Heap>::parse_tree(&mut Heap, &mut Names::new(), name, source);
+    parsed?;
+
+    let mut trivia: Vec<String> = tree.tokens.iter().map(|token| tidy(token.trivia)).collect();
+
+    for (index, token) in tree.tokens.iter().enumerate() {
+        let (before, after) = match token.kind {
+            Token::Comma | Token::Semicolon => (Some(""), " "),
+            Token::Pipe => (Some(" "), " "),
+            Token::Equal => (None, " "),
+            _ => continue,
+        };
+
+        if let Some(before) = before.filter(|_| is_inline(&trivia[index])) {
+            trivia[index] = before.into();
+        }
+
+        if let Some(next) = trivia.get_mut(index + 1) {
+            if is_inline(next) {
+                *next = after.into();
+            }
+        }
+    }
+
+    align(&tree, &mut trivia, &tree.root);
+
+    let mut formatted = String::with_capacity(source.len());
+    for (token, trivia) in tree.tokens.iter().zip(trivia) {
+        formatted.push_str(&trivia);
+        formatted.push_str(token.text);
+    }
+
+    let end = tidy(tree.end);
+    formatted.push_str(end.trim_end());
+    if !formatted.is_empty() {
+        formatted.push('\n');
+    }
+
+    Ok(formatted)
+}
+
+/// Align the equals signs of the definitions in `node` and in every node
+/// within it. Definitions are aligned with each other when they are on
+/// consecutive lines and their names and parameters fit on a single line.
+fn align(tree: &Tree, trivia: &mut [String], node: &Node) {
+    let mut block = Vec::new();
+
+    for child in node.children.iter() {
+        let Child::Node(def) = child else {
+            continue;
+        };
+
+        if !matches!(def.kind, Kind::Definition | Kind::Local) {
+            align_block(tree, trivia, &mut block);
+            continue;
+        }
+
+        let Some(first) = def.first_token() else {
+            continue;
+        };
+
+        // Trivia has been tidied already, such that blank lines are empty.
+        let before = &trivia[first];
+        if before.contains("\n\n") || !before.contains('\n') {
+            align_block(tree, trivia, &mut block);
+        }
+
+        match equals(tree, trivia, def, first) {
+            Some(equals) => block.push(equals),
+            None => align_block(tree, trivia, &mut block),
+        }
+    }
+
+    align_block(tree, trivia, &mut block);
+
+    for child in node.children.iter() {
+        if let Child::Node(child) = child {
+            align(tree, trivia, child);
+        }
+    }
+}
+
+/// Align the given equals signs, which are each on a line of their own, and
+/// empty the block.
+fn align_block(tree: &Tree, trivia: &mut [String], block: &mut Vec<usize>) {
+    for equals in block.iter() {
+        trivia[*equals] = " ".into();
+    }
+
+    let columns: Vec<_> = block
+        .iter()
+        .map(|equals| column(tree, trivia, *equals))
+        .collect();
+
+    let target = columns.iter().copied().max().unwrap_or_default();
+    for (equals, column) in block.drain(..).zip(columns) {
+        trivia[equals].push_str(&" ".repeat(target - column));
+    }
+}
+
+/// Get the equals sign of the definition `def` starting at the token `first`,
+/// if everything before it is on the same line.
+fn equals(tree: &Tree, trivia: &[String], def: &Node, first: usize) -> Option<usize> {
+    let equals = def.children.iter().find_map(|child| match child {
+        Child::Token(index) if tree.tokens[*index].kind == Token::Equal => Some(*index),
+        _ => None,
+    })?;
+
+    (first + 1..=equals)
+        .all(|index| !trivia[index].contains('\n'))
+        .then_some(equals)
+}
+
+/// Get the column the token at `index` starts at.
+fn column(tree: &Tree, trivia: &[String], index: usize) -> usize {
+    let mut column = 0;
+
+    for index in (0..=index).rev() {
+        if let Some((_, line)) = trivia[index].rsplit_once('\n') {
+            return column + line.chars().count();
+        }
+
+        column += trivia[index].chars().count();
+        if index > 0 {
+            column += tree.tokens[index - 1].text.chars().count();
+        }
+    }
+
+    column
+}
+
+/// Whether `trivia` is only spaces within a line.
+fn is_inline(trivia: &str) -> bool {
+    trivia.chars().all(|c| c == ' ' || c == '\t')
+}
+
+/// Remove the whitespace at the end of every line of `trivia`.
+fn tidy(trivia: &str) -> String {
+    let mut lines: Vec<_> = trivia.split('\n').collect();
+    let last = lines.pop().unwrap_or_default();
+
+    let mut tidied: String = lines
+        .into_iter()
+        .map(|line| line.trim_end_matches([' ', '\t']).to_string() + "\n")
+        .collect();
+
+    tidied.push_str(last);
+    tidied
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format;
+
+    fn check(expected: &str, source: &str) {
+        let formatted = format::<char, _>("", source).unwrap();
+        assert_eq!(expected, formatted);
+        assert_eq!(Ok(formatted.clone()), format::<char, _>("", &formatted));
+    }
+
+    #[test]
+    fn spacing() {
+        check(
+            "it = ab, (cd, ef) | gh\n\nxy(pq, rs) = {pq | 2: rs}\n",
+            "it = ab,(cd ,ef)|gh   \n\nxy(pq,rs) = {pq|2: rs}",
+        );
+    }
+
+    #[test]
+    fn line_breaks() {
+        check("it = ab,\n  cd\n  | ef\n", "it = ab,  \n  cd\n  | ef\n\n\n");
+    }
+
+    #[test]
+    fn alignment() {
+        check(
+            "it     = ab\n--- The next one\nlonger = cd\n\nxy      = ef\nabc(xy) = xy where\n  pq  = xy;\n  rst = xy\n",
+            "it = ab\n--- The next one\nlonger    = cd\n\nxy = ef\nabc(xy) =  xy where\n  pq = xy;\n  rst = xy\n",
+        );
+    }
+
+    #[test]
+    fn comments() {
+        let source = "-- a tune\nit = ab , -- first\n  cd -- second\n-- the end";
+        check(
+            "-- a tune\nit = ab, -- first\n  cd -- second\n-- the end\n",
+            source,
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert!(format::<char, _>("", "it = (ab").is_err());
+    }
+}
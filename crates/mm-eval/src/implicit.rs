@@ -1,23 +1,61 @@
 use std::collections::HashMap;
 use std::fmt;
 
+use crate::key::Key;
 use crate::span::Span;
-use crate::{Allocator, Factor, Name};
+use crate::{
+    Allocator, Factor, Length, Metadata, Name, Tempo, Time, TimeSignature, Velocity, Voice,
+};
 
 #[derive(Eq)]
 pub struct Program<N, Id, A: Allocator<Melody<N, Id, A>>> {
     pub defs: HashMap<Name, A::Holder>,
+    pub params: HashMap<Name, Vec<Name>>,
     pub spans: HashMap<Name, Span<Id>>,
     pub public: Vec<Name>,
+    pub imports: Vec<Import<Id>>,
+    /// The tempo given by a `tempo` directive, if any.
+    pub tempo: Option<Tempo>,
+    /// The time signature given by a `time` directive, if any.
+    pub time: Option<TimeSignature>,
+    pub bars: HashMap<Name, Bars<Id>>,
+    /// The title, composer and so on declared in the source.
+    pub metadata: Metadata,
+    /// The doc comments of the definitions which have them.
+    pub docs: HashMap<Name, String>,
     pub source: Span<Id>,
 }
 
+/// An `import "path"` declaration, bringing the public definitions of another
+/// source into scope.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Import<Id> {
+    pub path: String,
+    pub span: Span<Id>,
+}
+
+/// A `: 4 bars` annotation on a definition, along with the length that many
+/// bars have in the time signature of the source it occurs in.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Bars<Id> {
+    pub count: usize,
+    pub length: Length,
+    pub span: Span<Id>,
+}
+
 impl<N, Id, A: Allocator<Melody<N, Id, A>>> Program<N, Id, A> {
     pub fn new(source: Span<Id>) -> Self {
         Self {
             defs: HashMap::new(),
+            params: HashMap::new(),
             spans: HashMap::new(),
             public: Vec::new(),
+            imports: Vec::new(),
+            tempo: None,
+            time: None,
+            bars: HashMap::new(),
+            metadata: Metadata::default(),
+            docs: HashMap::new(),
             source,
         }
     }
@@ -27,12 +65,38 @@ impl<N, Id, A: Allocator<Melody<N, Id, A>>> Program<N, Id, A> {
 pub enum Melody<N, Id, A: Allocator<Self>> {
     Pause(Span<Id>),
     Note(Span<Id>, N),
+    /// A scale degree, relative to the key it is played in.
+    Degree(Span<Id>, isize),
     Name(Span<Id>, Name),
+    Parameter(Span<Id>, Name),
+    Call(Span<Id>, Name, A::Several),
     Scale(Span<Id>, Factor, A::Holder),
-    Sharp(Span<Id>, usize, A::Holder),
+    Sharp(Span<Id>, isize, A::Holder),
     Offset(Span<Id>, isize, A::Holder),
+    Dynamic(Span<Id>, Velocity, A::Holder),
+    /// Repeat a melody some number of times, or forever if the count is `None`.
+    Repeat(Span<Id>, Option<usize>, A::Holder),
+    /// Play a melody backwards.
+    Reverse(Span<Id>, A::Holder),
+    /// Mirror the notes of a melody around the given note.
+    Invert(Span<Id>, N, A::Holder),
+    /// Play a melody in the given key.
+    Key(Span<Id>, Key<N>, A::Holder),
+    /// Transpose the scale degrees of a melody by some number of scale steps.
+    Steps(Span<Id>, isize, A::Holder),
+    /// Play a melody at a tempo which changes gradually from the first tempo,
+    /// or the surrounding tempo if there is none, to the second.
+    Tempo(Span<Id>, Option<Tempo>, Tempo, A::Holder),
+    /// Play a melody with a channel or instrument of its own.
+    Voice(Span<Id>, Voice, A::Holder),
+    /// Play the part of a melody between two times within it.
+    Slice(Span<Id>, Time, Time, A::Holder),
+    /// Stretch or squash a melody so that it lasts for the given length.
+    Fit(Span<Id>, Time, A::Holder),
     Sequence(A::Several),
     Stack(A::Several),
+    /// Play one of several melodies, chosen at random with the given weights.
+    Choice(Span<Id>, Vec<usize>, A::Several),
 }
 
 impl<N, Id: Clone + Eq, A: Allocator<Self>> Melody<N, Id, A> {
@@ -40,10 +104,23 @@ impl<N, Id: Clone + Eq, A: Allocator<Self>> Melody<N, Id, A> {
         match self {
             Self::Pause(span) => span.clone(),
             Self::Note(span, _) => span.clone(),
+            Self::Degree(span, _) => span.clone(),
             Self::Name(span, _) => span.clone(),
+            Self::Parameter(span, _) => span.clone(),
+            Self::Call(span, _, _) => span.clone(),
             Self::Scale(factor_span, _, inner) => factor_span.clone() + A::as_ref(inner).span(),
             Self::Sharp(sharp_span, _, inner) => sharp_span.clone() + A::as_ref(inner).span(),
             Self::Offset(offset_span, _, inner) => offset_span.clone() + A::as_ref(inner).span(),
+            Self::Dynamic(dynamic_span, _, inner) => dynamic_span.clone() + A::as_ref(inner).span(),
+            Self::Repeat(repeat_span, _, inner) => repeat_span.clone() + A::as_ref(inner).span(),
+            Self::Reverse(reverse_span, inner) => reverse_span.clone() + A::as_ref(inner).span(),
+            Self::Invert(invert_span, _, inner) => invert_span.clone() + A::as_ref(inner).span(),
+            Self::Key(key_span, _, inner) => key_span.clone() + A::as_ref(inner).span(),
+            Self::Steps(steps_span, _, inner) => steps_span.clone() + A::as_ref(inner).span(),
+            Self::Tempo(tempo_span, _, _, inner) => tempo_span.clone() + A::as_ref(inner).span(),
+            Self::Voice(voice_span, _, inner) => voice_span.clone() + A::as_ref(inner).span(),
+            Self::Slice(slice_span, _, _, inner) => A::as_ref(inner).span() + slice_span.clone(),
+            Self::Fit(fit_span, _, inner) => A::as_ref(inner).span() + fit_span.clone(),
 
             Self::Sequence(melodies) => A::as_slice(melodies)
                 .iter()
@@ -56,6 +133,8 @@ impl<N, Id: Clone + Eq, A: Allocator<Self>> Melody<N, Id, A> {
                 .map(|melody| melody.span())
                 .reduce(|a, b| a + b)
                 .unwrap(),
+
+            Self::Choice(span, _, _) => span.clone(),
         }
     }
 }
@@ -82,7 +161,136 @@ impl<N: Eq, Id: Eq, A: Allocator<Melody<N, Id, A>>> PartialEq for Program<N, Id,
             }
         }
 
-        self.public == other.public && self.source == other.source && self.spans == other.spans
+        self.public == other.public
+            && self.imports == other.imports
+            && self.tempo == other.tempo
+            && self.time == other.time
+            && self.bars == other.bars
+            && self.metadata == other.metadata
+            && self.docs == other.docs
+            && self.source == other.source
+            && self.params == other.params
+            && self.spans == other.spans
+    }
+}
+
+impl<N: Clone, Id: Clone, A: Allocator<Self>> Melody<N, Id, A> {
+    /// Copy this melody, replacing every reference to a definition with what
+    /// `replace` makes of its span, its name and its arguments, if it is a
+    /// call. The arguments have had their references replaced already.
+    pub fn replace_names(
+        &self,
+        alloc: &mut A,
+        replace: &mut impl FnMut(&mut A, Span<Id>, Name, Option<Vec<Self>>) -> Self,
+    ) -> Self {
+        match self {
+            Melody::Pause(span) => Melody::Pause(span.clone()),
+            Melody::Note(span, note) => Melody::Note(span.clone(), note.clone()),
+            Melody::Degree(span, degree) => Melody::Degree(span.clone(), *degree),
+            Melody::Name(span, name) => replace(alloc, span.clone(), *name, None),
+            Melody::Parameter(span, name) => Melody::Parameter(span.clone(), *name),
+
+            Melody::Call(span, name, args) => {
+                let args: Vec<_> = A::as_slice(args)
+                    .iter()
+                    .map(|arg| arg.replace_names(alloc, replace))
+                    .collect();
+
+                replace(alloc, span.clone(), *name, Some(args))
+            }
+
+            Melody::Scale(span, factor, inner) => {
+                let inner = A::as_ref(inner).replace_names(alloc, replace);
+                Melody::Scale(span.clone(), factor.clone(), alloc.pack(inner))
+            }
+
+            Melody::Sharp(span, by, inner) => {
+                let inner = A::as_ref(inner).replace_names(alloc, replace);
+                Melody::Sharp(span.clone(), *by, alloc.pack(inner))
+            }
+
+            Melody::Offset(span, by, inner) => {
+                let inner = A::as_ref(inner).replace_names(alloc, replace);
+                Melody::Offset(span.clone(), *by, alloc.pack(inner))
+            }
+
+            Melody::Dynamic(span, velocity, inner) => {
+                let inner = A::as_ref(inner).replace_names(alloc, replace);
+                Melody::Dynamic(span.clone(), velocity.clone(), alloc.pack(inner))
+            }
+
+            Melody::Repeat(span, count, inner) => {
+                let inner = A::as_ref(inner).replace_names(alloc, replace);
+                Melody::Repeat(span.clone(), *count, alloc.pack(inner))
+            }
+
+            Melody::Reverse(span, inner) => {
+                let inner = A::as_ref(inner).replace_names(alloc, replace);
+                Melody::Reverse(span.clone(), alloc.pack(inner))
+            }
+
+            Melody::Invert(span, axis, inner) => {
+                let inner = A::as_ref(inner).replace_names(alloc, replace);
+                Melody::Invert(span.clone(), axis.clone(), alloc.pack(inner))
+            }
+
+            Melody::Key(span, key, inner) => {
+                let inner = A::as_ref(inner).replace_names(alloc, replace);
+                Melody::Key(span.clone(), key.clone(), alloc.pack(inner))
+            }
+
+            Melody::Steps(span, by, inner) => {
+                let inner = A::as_ref(inner).replace_names(alloc, replace);
+                Melody::Steps(span.clone(), *by, alloc.pack(inner))
+            }
+
+            Melody::Tempo(span, from, to, inner) => {
+                let inner = A::as_ref(inner).replace_names(alloc, replace);
+                Melody::Tempo(span.clone(), from.clone(), to.clone(), alloc.pack(inner))
+            }
+
+            Melody::Voice(span, voice, inner) => {
+                let inner = A::as_ref(inner).replace_names(alloc, replace);
+                Melody::Voice(span.clone(), *voice, alloc.pack(inner))
+            }
+
+            Melody::Slice(span, from, to, inner) => {
+                let inner = A::as_ref(inner).replace_names(alloc, replace);
+                Melody::Slice(span.clone(), from.clone(), to.clone(), alloc.pack(inner))
+            }
+
+            Melody::Fit(span, length, inner) => {
+                let inner = A::as_ref(inner).replace_names(alloc, replace);
+                Melody::Fit(span.clone(), length.clone(), alloc.pack(inner))
+            }
+
+            Melody::Sequence(melodies) => {
+                let melodies: Vec<_> = A::as_slice(melodies)
+                    .iter()
+                    .map(|melody| melody.replace_names(alloc, replace))
+                    .collect();
+
+                Melody::Sequence(alloc.pack_many(melodies))
+            }
+
+            Melody::Stack(melodies) => {
+                let melodies: Vec<_> = A::as_slice(melodies)
+                    .iter()
+                    .map(|melody| melody.replace_names(alloc, replace))
+                    .collect();
+
+                Melody::Stack(alloc.pack_many(melodies))
+            }
+
+            Melody::Choice(span, weights, melodies) => {
+                let melodies: Vec<_> = A::as_slice(melodies)
+                    .iter()
+                    .map(|melody| melody.replace_names(alloc, replace))
+                    .collect();
+
+                Melody::Choice(span.clone(), weights.clone(), alloc.pack_many(melodies))
+            }
+        }
     }
 }
 
@@ -91,7 +299,13 @@ impl<N: Eq, Id: Eq, A: Allocator<Self>> PartialEq for Melody<N, Id, A> {
         match (self, other) {
             (Melody::Pause(a), Melody::Pause(b)) => a == b,
             (Melody::Note(a, n), Melody::Note(b, m)) => a == b && n == m,
+            (Melody::Degree(a, n), Melody::Degree(b, m)) => a == b && n == m,
             (Melody::Name(a, n), Melody::Name(b, m)) => a == b && n == m,
+            (Melody::Parameter(a, n), Melody::Parameter(b, m)) => a == b && n == m,
+
+            (Melody::Call(a, n, is), Melody::Call(b, m, js)) => {
+                a == b && n == m && A::as_slice(is) == A::as_slice(js)
+            }
 
             (Melody::Scale(a, f, i), Melody::Scale(b, g, j)) => {
                 a == b && f == g && A::as_ref(i) == A::as_ref(j)
@@ -105,9 +319,53 @@ impl<N: Eq, Id: Eq, A: Allocator<Self>> PartialEq for Melody<N, Id, A> {
                 a == b && f == g && A::as_ref(i) == A::as_ref(j)
             }
 
+            (Melody::Dynamic(a, f, i), Melody::Dynamic(b, g, j)) => {
+                a == b && f == g && A::as_ref(i) == A::as_ref(j)
+            }
+
+            (Melody::Repeat(a, f, i), Melody::Repeat(b, g, j)) => {
+                a == b && f == g && A::as_ref(i) == A::as_ref(j)
+            }
+
+            (Melody::Reverse(a, i), Melody::Reverse(b, j)) => {
+                a == b && A::as_ref(i) == A::as_ref(j)
+            }
+
+            (Melody::Invert(a, n, i), Melody::Invert(b, m, j)) => {
+                a == b && n == m && A::as_ref(i) == A::as_ref(j)
+            }
+
+            (Melody::Key(a, n, i), Melody::Key(b, m, j)) => {
+                a == b && n == m && A::as_ref(i) == A::as_ref(j)
+            }
+
+            (Melody::Steps(a, n, i), Melody::Steps(b, m, j)) => {
+                a == b && n == m && A::as_ref(i) == A::as_ref(j)
+            }
+
+            (Melody::Tempo(a, f, t, i), Melody::Tempo(b, g, u, j)) => {
+                a == b && f == g && t == u && A::as_ref(i) == A::as_ref(j)
+            }
+
+            (Melody::Voice(a, v, i), Melody::Voice(b, w, j)) => {
+                a == b && v == w && A::as_ref(i) == A::as_ref(j)
+            }
+
+            (Melody::Slice(a, f, t, i), Melody::Slice(b, g, u, j)) => {
+                a == b && f == g && t == u && A::as_ref(i) == A::as_ref(j)
+            }
+
+            (Melody::Fit(a, t, i), Melody::Fit(b, u, j)) => {
+                a == b && t == u && A::as_ref(i) == A::as_ref(j)
+            }
+
             (Melody::Sequence(ns), Melody::Sequence(ms)) => A::as_slice(ns) == A::as_slice(ms),
             (Melody::Stack(ns), Melody::Stack(ms)) => A::as_slice(ns) == A::as_slice(ms),
 
+            (Melody::Choice(a, v, ns), Melody::Choice(b, w, ms)) => {
+                a == b && v == w && A::as_slice(ns) == A::as_slice(ms)
+            }
+
             _ => false,
         }
     }
@@ -125,8 +383,18 @@ impl<N: fmt::Debug, Id: fmt::Debug, A: Allocator<Melody<N, Id, A>>> fmt::Debug
 
         write!(
             f,
-            ", public: {:?}, source: {:?}, spans: {:?} }}",
-            self.public, self.source, self.spans
+            ", params: {:?}, public: {:?}, imports: {:?}, tempo: {:?}, time: {:?}, bars: {:?}, \
+             metadata: {:?}, docs: {:?}, source: {:?}, spans: {:?} }}",
+            self.params,
+            self.public,
+            self.imports,
+            self.tempo,
+            self.time,
+            self.bars,
+            self.metadata,
+            self.docs,
+            self.source,
+            self.spans
         )
     }
 }
@@ -136,7 +404,15 @@ impl<N: fmt::Debug, Id: fmt::Debug, A: Allocator<Self>> fmt::Debug for Melody<N,
         match self {
             Melody::Pause(span) => write!(f, "Pause({span:?})"),
             Melody::Note(span, note) => write!(f, "Note({span:?}, {note:?})"),
+            Melody::Degree(span, degree) => write!(f, "Degree({span:?}, {degree:?})"),
             Melody::Name(span, name) => write!(f, "Name({span:?}, {name:?})"),
+            Melody::Parameter(span, name) => write!(f, "Parameter({span:?}, {name:?})"),
+
+            Melody::Call(span, name, args) => {
+                write!(f, "Call({span:?}, {name:?}, ")?;
+                f.debug_list().entries(A::as_slice(args)).finish()?;
+                write!(f, ")")
+            }
 
             Melody::Scale(span, factor, inner) => {
                 write!(f, "Scale({span:?}, {factor:?}, {:?})", A::as_ref(inner))
@@ -150,6 +426,52 @@ impl<N: fmt::Debug, Id: fmt::Debug, A: Allocator<Self>> fmt::Debug for Melody<N,
                 write!(f, "Offset({span:?}, {by:?}, {:?})", A::as_ref(inner))
             }
 
+            Melody::Dynamic(span, by, inner) => {
+                write!(f, "Dynamic({span:?}, {by:?}, {:?})", A::as_ref(inner))
+            }
+
+            Melody::Repeat(span, count, inner) => {
+                write!(f, "Repeat({span:?}, {count:?}, {:?})", A::as_ref(inner))
+            }
+
+            Melody::Reverse(span, inner) => write!(f, "Reverse({span:?}, {:?})", A::as_ref(inner)),
+
+            Melody::Invert(span, axis, inner) => {
+                write!(f, "Invert({span:?}, {axis:?}, {:?})", A::as_ref(inner))
+            }
+
+            Melody::Key(span, key, inner) => {
+                write!(f, "Key({span:?}, {key:?}, {:?})", A::as_ref(inner))
+            }
+
+            Melody::Steps(span, by, inner) => {
+                write!(f, "Steps({span:?}, {by:?}, {:?})", A::as_ref(inner))
+            }
+
+            Melody::Tempo(span, from, to, inner) => {
+                write!(
+                    f,
+                    "Tempo({span:?}, {from:?}, {to:?}, {:?})",
+                    A::as_ref(inner)
+                )
+            }
+
+            Melody::Voice(span, voice, inner) => {
+                write!(f, "Voice({span:?}, {voice:?}, {:?})", A::as_ref(inner))
+            }
+
+            Melody::Slice(span, from, to, inner) => {
+                write!(
+                    f,
+                    "Slice({span:?}, {from:?}, {to:?}, {:?})",
+                    A::as_ref(inner)
+                )
+            }
+
+            Melody::Fit(span, length, inner) => {
+                write!(f, "Fit({span:?}, {length:?}, {:?})", A::as_ref(inner))
+            }
+
             Melody::Sequence(melodies) => {
                 write!(f, "Sequence")?;
                 f.debug_list().entries(A::as_slice(melodies)).finish()
@@ -159,6 +481,11 @@ impl<N: fmt::Debug, Id: fmt::Debug, A: Allocator<Self>> fmt::Debug for Melody<N,
                 write!(f, "Stack")?;
                 f.debug_list().entries(A::as_slice(melodies)).finish()
             }
+
+            Melody::Choice(span, weights, melodies) => {
+                write!(f, "Choice({span:?}, {weights:?})")?;
+                f.debug_list().entries(A::as_slice(melodies)).finish()
+            }
         }
     }
 }
@@ -0,0 +1,95 @@
+use crate::note::Note;
+
+/// The semitones above the tonic of each step in the major scale.
+const IONIAN: [isize; 7] = [0, 2, 4, 5, 7, 9, 11];
+
+/// A tonic and a mode, which scale degrees are resolved relative to.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Key<N> {
+    pub tonic: N,
+    pub mode: Mode,
+}
+
+impl<N: Note> Key<N> {
+    /// Get the note at the given scale degree, where `1` is the tonic. Degrees
+    /// past the seventh or below the first continue into the neighbouring
+    /// octaves. Gives `None` if the note cannot be represented.
+    pub fn degree(&self, degree: isize) -> Option<N> {
+        let step = degree - 1;
+        let octave = step.div_euclid(7);
+        let semitones = self.mode.semitones()[step.rem_euclid(7) as usize];
+
+        self.tonic.add_sharp(semitones)?.add_octave(octave)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Mode {
+    Ionian,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    Aeolian,
+    Locrian,
+}
+
+impl Mode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "major" | "ionian" => Self::Ionian,
+            "dorian" => Self::Dorian,
+            "phrygian" => Self::Phrygian,
+            "lydian" => Self::Lydian,
+            "mixolydian" => Self::Mixolydian,
+            "minor" | "aeolian" => Self::Aeolian,
+            "locrian" => Self::Locrian,
+
+            _ => return None,
+        })
+    }
+
+    /// Get the name this mode is written with, which is `major` and `minor`
+    /// rather than `ionian` and `aeolian`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Ionian => "major",
+            Self::Dorian => "dorian",
+            Self::Phrygian => "phrygian",
+            Self::Lydian => "lydian",
+            Self::Mixolydian => "mixolydian",
+            Self::Aeolian => "minor",
+            Self::Locrian => "locrian",
+        }
+    }
+
+    /// Get the semitones above the tonic of each step in this mode.
+    fn semitones(&self) -> [isize; 7] {
+        let rotation = *self as usize;
+        let base = IONIAN[rotation];
+
+        std::array::from_fn(|step| {
+            let index = step + rotation;
+            IONIAN[index % 7] + 12 * (index / 7) as isize - base
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Mode;
+
+    #[test]
+    fn modes() {
+        assert_eq!([0, 2, 4, 5, 7, 9, 11], Mode::Ionian.semitones());
+        assert_eq!([0, 2, 3, 5, 7, 9, 10], Mode::Dorian.semitones());
+        assert_eq!([0, 2, 3, 5, 7, 8, 10], Mode::Aeolian.semitones());
+        assert_eq!([0, 1, 3, 5, 6, 8, 10], Mode::Locrian.semitones());
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(Some(Mode::Aeolian), Mode::from_name(Mode::Aeolian.name()));
+        assert_eq!(Some(Mode::Lydian), Mode::from_name(Mode::Lydian.name()));
+    }
+}
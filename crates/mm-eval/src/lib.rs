@@ -1,20 +1,33 @@
 pub mod check;
+pub mod chord;
 pub mod eval;
+pub mod fmt;
 pub mod implicit;
+pub mod key;
+pub mod link;
 pub mod melody;
 pub mod names;
 pub mod note;
 pub mod parse;
+pub mod print;
 pub mod span;
 
 pub use crate::alloc::{Allocator, Arena, Heap};
-pub use crate::compile::compile;
+pub use crate::compile::{compile, compile_linked};
+pub use crate::metadata::Metadata;
 pub use crate::names::{Name, Names};
-pub use crate::time::{Factor, Length, Time};
+pub use crate::tempo::Tempo;
+pub use crate::time::{Factor, Length, Time, TimeSignature};
+pub use crate::velocity::Velocity;
+pub use crate::voice::Voice;
 
 mod alloc;
 mod compile;
+mod metadata;
+mod tempo;
 mod time;
+mod velocity;
+mod voice;
 
 mod dependency;
 mod topology;
@@ -22,6 +35,7 @@ mod topology;
 #[derive(Debug)]
 pub enum Error<Id> {
     Parse(parse::Error<Id>),
+    Link(link::Error<Id>),
     Check(check::Error<Id>),
 }
 
@@ -31,6 +45,12 @@ impl<Id> From<check::Error<Id>> for Error<Id> {
     }
 }
 
+impl<Id> From<link::Error<Id>> for Error<Id> {
+    fn from(value: link::Error<Id>) -> Self {
+        Self::Link(value)
+    }
+}
+
 impl<Id> From<parse::Error<Id>> for Error<Id> {
     fn from(value: parse::Error<Id>) -> Self {
         Self::Parse(value)
@@ -0,0 +1,313 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use crate::implicit::{Melody, Program};
+use crate::span::Span;
+use crate::{Allocator, Name, Names};
+
+#[derive(Debug, Eq, Hash, PartialEq)]
+pub enum Error<Id> {
+    UnknownImport(Span<Id>),
+}
+
+/// Link the program `entry` together with every program it transitively
+/// imports into a single program. `resolve` is given the source an import
+/// occurs in and its path, and should produce the source it refers to, if it
+/// has been loaded into `modules`.
+///
+/// Within each source, a name refers to a definition in that source if there is
+/// one, or else to a public definition of one of its direct imports, with
+/// earlier imports taking precedence. Definitions from different sources which
+/// are spelled the same are given distinct names in the linked program. Only
+/// the public definitions of `entry` are public in the linked program.
+pub fn link<N, Id, A>(
+    alloc: &mut A,
+    names: &mut Names,
+    entry: &Id,
+    modules: &HashMap<Id, Program<N, Id, A>>,
+    resolve: impl Fn(&Id, &str) -> Option<Id>,
+) -> Result<Program<N, Id, A>, Vec<Error<Id>>>
+where
+    N: Clone,
+    Id: Clone + Eq + Hash,
+    A: Allocator<Melody<N, Id, A>>,
+{
+    let mut errors = Vec::new();
+
+    let mut order = vec![entry.clone()];
+    let mut imports: HashMap<Id, Vec<Id>> = HashMap::new();
+    let mut index = 0;
+
+    while let Some(id) = order.get(index).cloned() {
+        index += 1;
+
+        let Some(module) = modules.get(&id) else {
+            continue;
+        };
+
+        let mut resolved = Vec::new();
+        for import in module.imports.iter() {
+            match resolve(&id, &import.path) {
+                Some(other) if modules.contains_key(&other) => {
+                    if !order.contains(&other) {
+                        order.push(other.clone());
+                    }
+
+                    resolved.push(other);
+                }
+
+                _ => errors.push(Error::UnknownImport(import.span.clone())),
+            }
+        }
+
+        imports.insert(id, resolved);
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut taken = HashSet::new();
+    let mut renames: HashMap<Id, HashMap<Name, Name>> = HashMap::new();
+
+    for id in order.iter() {
+        let module = &modules[id];
+
+        let mut defined: Vec<_> = module.spans.iter().collect();
+        defined.sort_by_key(|(_, span)| span.start);
+
+        let renamed = defined
+            .into_iter()
+            .map(|(name, _)| {
+                let linked = if taken.insert(*name) {
+                    *name
+                } else {
+                    let spelling = names.get(name).to_string();
+                    names.fresh(spelling)
+                };
+
+                (*name, linked)
+            })
+            .collect();
+
+        renames.insert(id.clone(), renamed);
+    }
+
+    let module = &modules[entry];
+    let mut program = Program::new(module.source.clone());
+    program.tempo = module.tempo.clone();
+    program.time = module.time;
+    program.metadata = module.metadata.clone();
+    program.public = module
+        .public
+        .iter()
+        .map(|name| renames[entry][name])
+        .collect();
+
+    for id in order.iter() {
+        let module = &modules[id];
+
+        let mut scope = renames[id].clone();
+        for import in imports[id].iter() {
+            for name in modules[import].public.iter() {
+                scope.entry(*name).or_insert(renames[import][name]);
+            }
+        }
+
+        for (name, body) in module.defs.iter() {
+            let linked = renames[id][name];
+            let body = rename(alloc, names, &scope, A::as_ref(body));
+            let body = alloc.pack(body);
+
+            program.defs.insert(linked, body);
+            program.spans.insert(linked, module.spans[name].clone());
+
+            if let Some(params) = module.params.get(name) {
+                program.params.insert(linked, params.clone());
+            }
+
+            if let Some(bars) = module.bars.get(name) {
+                program.bars.insert(linked, bars.clone());
+            }
+
+            if let Some(doc) = module.docs.get(name) {
+                program.docs.insert(linked, doc.clone());
+            }
+        }
+    }
+
+    Ok(program)
+}
+
+/// Copy `melody`, replacing every name it refers to with the name it resolves
+/// to in `scope`. Names which are not in scope are replaced with fresh names,
+/// such that they cannot accidentally refer to a definition in another source.
+fn rename<N, Id, A>(
+    alloc: &mut A,
+    names: &mut Names,
+    scope: &HashMap<Name, Name>,
+    melody: &Melody<N, Id, A>,
+) -> Melody<N, Id, A>
+where
+    N: Clone,
+    Id: Clone,
+    A: Allocator<Melody<N, Id, A>>,
+{
+    melody.replace_names(alloc, &mut |alloc, span, name, args| {
+        let name = resolve(names, scope, &name);
+        match args {
+            Some(args) => Melody::Call(span, name, alloc.pack_many(args)),
+            None => Melody::Name(span, name),
+        }
+    })
+}
+
+fn resolve(names: &mut Names, scope: &HashMap<Name, Name>, name: &Name) -> Name {
+    match scope.get(name) {
+        Some(name) => *name,
+        None => {
+            let spelling = names.get(name).to_string();
+            names.fresh(spelling)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{link, Error};
+    use crate::implicit::Program;
+    use crate::parse::Parser;
+    use crate::span::Span;
+    use crate::{check, compile, Heap, Names};
+
+    fn parse(
+        names: &mut Names,
+        id: &'static str,
+        source: &str,
+    ) -> Program<char, &'static str, Heap> {
+        Parser::parse(&mut Heap, names, id, source).unwrap()
+    }
+
+    #[test]
+    fn imported_names() {
+        let mut names = Names::new();
+        let main = parse(&mut names, "main", "import \"lib\"\nab = C\nit! = ab, lib");
+        let lib = parse(&mut names, "lib", "ab = A\nlib! = ab, B");
+
+        let modules = HashMap::from([("main", main), ("lib", lib)]);
+        let linked = link(&mut Heap, &mut names, &"main", &modules, |_, path| {
+            (path == "lib").then_some("lib")
+        })
+        .unwrap();
+
+        // Both sources define `ab`, so the linked program contains two of them.
+        assert_eq!(4, linked.defs.len());
+        assert_eq!(vec![names.make("it")], linked.public);
+
+        let program = check::check(&mut Heap, &mut names, linked);
+        assert!(program.is_ok());
+    }
+
+    #[test]
+    fn imported_docs() {
+        let mut names = Names::new();
+        let main = parse(
+            &mut names,
+            "main",
+            "import \"lib\"\n--- Main\nab = C\nit! = ab, lib",
+        );
+        let lib = parse(&mut names, "lib", "--- Library\nab = A\nlib! = ab, B");
+
+        let modules = HashMap::from([("main", main), ("lib", lib)]);
+        let linked = link(&mut Heap, &mut names, &"main", &modules, |_, path| {
+            (path == "lib").then_some("lib")
+        })
+        .unwrap();
+
+        // The `ab` of the library is renamed, and keeps its own doc comment.
+        let mut docs: Vec<_> = linked.docs.values().map(String::as_str).collect();
+        docs.sort();
+        assert_eq!(vec!["Library", "Main"], docs);
+        assert_eq!("Main", linked.docs[&names.make("ab")]);
+    }
+
+    #[test]
+    fn private_names() {
+        let mut names = Names::new();
+        let main = parse(&mut names, "main", "import \"lib\"\nit! = ab");
+        let lib = parse(&mut names, "lib", "ab = A\nlib! = ab");
+
+        let modules = HashMap::from([("main", main), ("lib", lib)]);
+        let linked = link(&mut Heap, &mut names, &"main", &modules, |_, path| {
+            (path == "lib").then_some("lib")
+        })
+        .unwrap();
+
+        let errors = check::check(&mut Heap, &mut names, linked).unwrap_err();
+        let [check::Error::UnknownName(span, name)] = errors.as_slice() else {
+            panic!("expected a single unknown name, got {errors:?}");
+        };
+
+        assert_eq!(&Span::new("main", 19..21), span);
+        assert_eq!("ab", names.get(name));
+    }
+
+    #[test]
+    fn unknown_chord_quality() {
+        let mut names = Names::new();
+        let errors = compile::<char, _, Heap>(&mut Heap, &mut names, "main", "it! = Cmaj8, Cmotif")
+            .unwrap_err();
+
+        let spans: Vec<_> = errors
+            .into_iter()
+            .map(|error| match error {
+                crate::Error::Check(check::Error::UnknownChordQuality(span)) => span,
+                error => panic!("expected an unknown chord quality, got {error:?}"),
+            })
+            .collect();
+
+        let expected = vec![Span::new("main", 7..11), Span::new("main", 14..19)];
+        assert_eq!(expected, spans);
+
+        // Names which start like chords are only chords if they are undefined.
+        let source = "Cmotif = A\nit! = Cmotif";
+        assert!(compile::<char, _, Heap>(&mut Heap, &mut names, "main", source).is_ok());
+    }
+
+    #[test]
+    fn cyclic_imports() {
+        let mut names = Names::new();
+        let main = parse(&mut names, "main", "import \"lib\"\nit! = A, 1/2 lib");
+        let lib = parse(&mut names, "lib", "import \"main\"\nlib! = B, 1/2 it");
+
+        let modules = HashMap::from([("main", main), ("lib", lib)]);
+        let linked = link(&mut Heap, &mut names, &"main", &modules, |_, path| {
+            Some(if path == "lib" { "lib" } else { "main" })
+        })
+        .unwrap();
+
+        let program = check::check(&mut Heap, &mut names, linked).unwrap();
+        assert_eq!(vec![names.make("it")], program.public);
+    }
+
+    #[test]
+    fn unknown_import() {
+        let mut names = Names::new();
+        let main = parse(&mut names, "main", "import \"lib\"\nit! = A");
+
+        let modules = HashMap::from([("main", main)]);
+        let linked = link(&mut Heap, &mut names, &"main", &modules, |_, _| None);
+
+        let expected = vec![Error::UnknownImport(Span::new("main", 0..12))];
+        assert_eq!(Err(expected), linked);
+    }
+
+    #[test]
+    fn single_source() {
+        let mut names = Names::new();
+        let program = compile::<char, _, Heap>(&mut Heap, &mut names, "main", "it! = A, B");
+        assert!(program.is_ok());
+    }
+}
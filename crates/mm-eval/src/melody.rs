@@ -1,13 +1,23 @@
 use std::collections::HashMap;
 use std::fmt;
 
+use crate::check::Warning;
+use crate::key::Key;
 use crate::span::Span;
-use crate::{Allocator, Factor, Length, Name};
+use crate::{
+    Allocator, Factor, Length, Metadata, Name, Tempo, Time, TimeSignature, Velocity, Voice,
+};
 
 pub struct Program<N, Id, A: Allocator<Melody<N, Id, A>>> {
     pub defs: HashMap<Name, A::Holder>,
     pub spans: HashMap<Name, Span<Id>>,
     pub public: Vec<Name>,
+    pub tempo: Option<Tempo>,
+    pub time: Option<TimeSignature>,
+    pub metadata: Metadata,
+    /// The doc comments of the definitions which have them.
+    pub docs: HashMap<Name, String>,
+    pub warnings: Vec<Warning<Id>>,
 }
 
 pub struct Melody<N, Id, A: Allocator<Self>> {
@@ -19,13 +29,37 @@ pub struct Melody<N, Id, A: Allocator<Self>> {
 pub enum Node<N, Id, A: Allocator<Melody<N, Id, A>>> {
     Pause,
     Note(N),
+    /// A scale degree, relative to the key it is played in.
+    Degree(isize),
     Name(Name),
     Recur(Name),
+    Parameter(usize),
+    Call(Name, A::Several),
     Scale(Factor, A::Holder),
-    Sharp(usize, A::Holder),
+    Sharp(isize, A::Holder),
     Offset(isize, A::Holder),
+    Dynamic(Velocity, A::Holder),
+    /// Repeat a melody some number of times, or forever if the count is `None`.
+    Repeat(Option<usize>, A::Holder),
+    /// Play a melody backwards.
+    Reverse(A::Holder),
+    /// Mirror the notes of a melody around the given note.
+    Invert(N, A::Holder),
+    /// Play a melody in the given key.
+    Key(Key<N>, A::Holder),
+    /// Transpose the scale degrees of a melody by some number of scale steps.
+    Steps(isize, A::Holder),
+    /// Play a melody at a tempo which changes gradually from the first tempo,
+    /// or the surrounding tempo if there is none, to the second.
+    Tempo(Option<Tempo>, Tempo, A::Holder),
+    /// Play a melody with a channel or instrument of its own.
+    Voice(Voice, A::Holder),
+    /// Play the part of a melody between two times within it.
+    Slice(Time, Time, A::Holder),
     Sequence(A::Several),
     Stack(A::Several),
+    /// Play one of several melodies, chosen at random with the given weights.
+    Choice(Vec<usize>, A::Several),
 }
 
 impl<N: PartialEq, Id: PartialEq, A: Allocator<Melody<N, Id, A>>> PartialEq for Program<N, Id, A> {
@@ -50,7 +84,13 @@ impl<N: PartialEq, Id: PartialEq, A: Allocator<Melody<N, Id, A>>> PartialEq for
             }
         }
 
-        self.public == other.public && self.spans == other.spans
+        self.public == other.public
+            && self.tempo == other.tempo
+            && self.time == other.time
+            && self.metadata == other.metadata
+            && self.docs == other.docs
+            && self.warnings == other.warnings
+            && self.spans == other.spans
     }
 }
 
@@ -65,13 +105,32 @@ impl<N: PartialEq, Id: PartialEq, A: Allocator<Melody<N, Id, A>>> PartialEq for
         match (self, other) {
             (Self::Pause, Self::Pause) => true,
             (Self::Note(n), Self::Note(m)) => n == m,
+            (Self::Degree(n), Self::Degree(m)) => n == m,
             (Self::Name(n), Self::Name(m)) => n == m,
             (Self::Recur(n), Self::Recur(m)) => n == m,
+            (Self::Parameter(n), Self::Parameter(m)) => n == m,
+            (Self::Call(n, is), Self::Call(m, js)) => n == m && A::as_slice(is) == A::as_slice(js),
             (Self::Scale(n, i), Self::Scale(m, j)) => n == m && A::as_ref(i) == A::as_ref(j),
             (Self::Sharp(n, i), Self::Sharp(m, j)) => n == m && A::as_ref(i) == A::as_ref(j),
             (Self::Offset(n, i), Self::Offset(m, j)) => n == m && A::as_ref(i) == A::as_ref(j),
+            (Self::Dynamic(n, i), Self::Dynamic(m, j)) => n == m && A::as_ref(i) == A::as_ref(j),
+            (Self::Repeat(n, i), Self::Repeat(m, j)) => n == m && A::as_ref(i) == A::as_ref(j),
+            (Self::Reverse(i), Self::Reverse(j)) => A::as_ref(i) == A::as_ref(j),
+            (Self::Invert(n, i), Self::Invert(m, j)) => n == m && A::as_ref(i) == A::as_ref(j),
+            (Self::Key(n, i), Self::Key(m, j)) => n == m && A::as_ref(i) == A::as_ref(j),
+            (Self::Steps(n, i), Self::Steps(m, j)) => n == m && A::as_ref(i) == A::as_ref(j),
+            (Self::Tempo(f, t, i), Self::Tempo(g, u, j)) => {
+                f == g && t == u && A::as_ref(i) == A::as_ref(j)
+            }
+            (Self::Voice(v, i), Self::Voice(w, j)) => v == w && A::as_ref(i) == A::as_ref(j),
+            (Self::Slice(f, t, i), Self::Slice(g, u, j)) => {
+                f == g && t == u && A::as_ref(i) == A::as_ref(j)
+            }
             (Self::Sequence(ns), Self::Sequence(ms)) => A::as_slice(ns) == A::as_slice(ms),
             (Self::Stack(ns), Self::Stack(ms)) => A::as_slice(ns) == A::as_slice(ms),
+            (Self::Choice(v, ns), Self::Choice(w, ms)) => {
+                v == w && A::as_slice(ns) == A::as_slice(ms)
+            }
 
             _ => false,
         }
@@ -88,7 +147,12 @@ impl<N: fmt::Debug, Id: fmt::Debug, A: Allocator<Melody<N, Id, A>>> fmt::Debug
             .entries(self.defs.iter().map(|(k, v)| (k, A::as_ref(v))))
             .finish()?;
 
-        write!(f, ", public: {:?}, spans: {:?} }}", self.public, self.spans)
+        write!(
+            f,
+            ", public: {:?}, tempo: {:?}, time: {:?}, metadata: {:?}, docs: {:?}, \
+             warnings: {:?}, spans: {:?} }}",
+            self.public, self.tempo, self.time, self.metadata, self.docs, self.warnings, self.spans
+        )
     }
 }
 
@@ -107,11 +171,27 @@ impl<N: fmt::Debug, Id: fmt::Debug, A: Allocator<Melody<N, Id, A>>> fmt::Debug f
         match self {
             Self::Pause => write!(f, "Pause"),
             Self::Note(n) => write!(f, "Note({n:?})"),
+            Self::Degree(n) => write!(f, "Degree({n:?})"),
             Self::Name(n) => write!(f, "Name({n:?})"),
             Self::Recur(n) => write!(f, "Recur({n:?})"),
+            Self::Parameter(n) => write!(f, "Parameter({n:?})"),
+            Self::Call(n, args) => {
+                write!(f, "Call({n:?}, ")?;
+                f.debug_list().entries(A::as_slice(args)).finish()?;
+                write!(f, ")")
+            }
             Self::Scale(factor, n) => write!(f, "Scale({factor:?}, {:?})", A::as_ref(n)),
             Self::Sharp(by, n) => write!(f, "Sharp({by:?}, {:?})", A::as_ref(n)),
             Self::Offset(by, n) => write!(f, "Offset({by:?}, {:?})", A::as_ref(n)),
+            Self::Dynamic(by, n) => write!(f, "Dynamic({by:?}, {:?})", A::as_ref(n)),
+            Self::Repeat(count, n) => write!(f, "Repeat({count:?}, {:?})", A::as_ref(n)),
+            Self::Reverse(n) => write!(f, "Reverse({:?})", A::as_ref(n)),
+            Self::Invert(axis, n) => write!(f, "Invert({axis:?}, {:?})", A::as_ref(n)),
+            Self::Key(key, n) => write!(f, "Key({key:?}, {:?})", A::as_ref(n)),
+            Self::Steps(by, n) => write!(f, "Steps({by:?}, {:?})", A::as_ref(n)),
+            Self::Tempo(from, to, n) => write!(f, "Tempo({from:?}, {to:?}, {:?})", A::as_ref(n)),
+            Self::Voice(voice, n) => write!(f, "Voice({voice:?}, {:?})", A::as_ref(n)),
+            Self::Slice(from, to, n) => write!(f, "Slice({from:?}, {to:?}, {:?})", A::as_ref(n)),
             Self::Sequence(ns) => {
                 write!(f, "Sequence")?;
                 f.debug_list().entries(A::as_slice(ns)).finish()
@@ -120,6 +200,10 @@ impl<N: fmt::Debug, Id: fmt::Debug, A: Allocator<Melody<N, Id, A>>> fmt::Debug f
                 write!(f, "Stack")?;
                 f.debug_list().entries(A::as_slice(ns)).finish()
             }
+            Self::Choice(weights, ns) => {
+                write!(f, "Choice({weights:?})")?;
+                f.debug_list().entries(A::as_slice(ns)).finish()
+            }
         }
     }
 }
@@ -0,0 +1,43 @@
+/// Information about a piece which is not part of its music, declared at the
+/// top level of a source like `title "Gymnopédie No. 1"`.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub composer: Option<String>,
+    pub copyright: Option<String>,
+    pub description: Option<String>,
+}
+
+impl Metadata {
+    /// Get the field declared with the given name, if there is one.
+    pub fn field_mut(&mut self, name: &str) -> Option<&mut Option<String>> {
+        match name {
+            "title" => Some(&mut self.title),
+            "composer" => Some(&mut self.composer),
+            "copyright" => Some(&mut self.copyright),
+            "description" => Some(&mut self.description),
+            _ => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Metadata;
+
+    #[test]
+    fn fields() {
+        let mut metadata = Metadata::default();
+        assert!(metadata.is_empty());
+
+        *metadata.field_mut("composer").unwrap() = Some("Erik Satie".into());
+        assert_eq!(Some("Erik Satie"), metadata.composer.as_deref());
+        assert!(!metadata.is_empty());
+
+        assert_eq!(None, metadata.field_mut("tempo"));
+    }
+}
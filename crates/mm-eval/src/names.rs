@@ -4,7 +4,7 @@ pub struct Name {
     end: usize,
 }
 
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 pub struct Names {
     intern: String,
 }
@@ -32,6 +32,19 @@ impl Names {
         }
     }
 
+    /// Create a name which is spelled the same as `name`, but which is distinct
+    /// from every other name.
+    pub fn fresh(&mut self, name: impl AsRef<str>) -> Name {
+        let name = name.as_ref();
+        let start = self.intern.len();
+        self.intern.push_str(name);
+
+        Name {
+            start,
+            end: start + name.len(),
+        }
+    }
+
     pub fn get(&self, name: &Name) -> &str {
         &self.intern[name.start..name.end]
     }
@@ -73,6 +86,18 @@ mod tests {
         assert_eq!("abc", names.get(&d));
     }
 
+    #[test]
+    fn fresh() {
+        let mut names = Names::new();
+        let a = names.make("abc");
+        let b = names.fresh("abc");
+        let c = names.make("abc");
+
+        assert_ne!(a, b);
+        assert_eq!(a, c);
+        assert_eq!("abc", names.get(&b));
+    }
+
     #[test]
     fn substring() {
         let mut names = Names::new();
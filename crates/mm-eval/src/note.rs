@@ -1,7 +1,13 @@
+/// A note which melodies are made of. Transpositions and inversions give
+/// `None` if the note they would give cannot be represented.
 pub trait Note: Clone {
     fn parse(name: &str) -> Option<Self>;
-    fn add_sharp(&self, by: usize) -> Self;
-    fn add_octave(&self, by: isize) -> Self;
+    fn add_sharp(&self, by: isize) -> Option<Self>;
+    fn add_octave(&self, by: isize) -> Option<Self>;
+
+    /// Mirror this note around the given axis, such that a note some distance
+    /// above the axis ends up the same distance below it.
+    fn invert(&self, axis: &Self) -> Option<Self>;
 }
 
 impl Note for char {
@@ -13,11 +19,15 @@ impl Note for char {
         }
     }
 
-    fn add_sharp(&self, _: usize) -> Self {
-        *self
+    fn add_sharp(&self, _: isize) -> Option<Self> {
+        Some(*self)
+    }
+
+    fn add_octave(&self, _: isize) -> Option<Self> {
+        Some(*self)
     }
 
-    fn add_octave(&self, _: isize) -> Self {
-        *self
+    fn invert(&self, _: &Self) -> Option<Self> {
+        Some(*self)
     }
 }
@@ -0,0 +1,122 @@
+//! A lossless concrete syntax tree, which keeps every token of a source along
+//! with the whitespace, comments and unrecognised characters before it, such
+//! that the source can be rewritten faithfully.
+
+use std::fmt;
+
+use super::lex;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Tree<'src> {
+    /// Every token of the source, in order.
+    pub tokens: Vec<Token<'src>>,
+    pub root: Node,
+    /// What comes after the last token.
+    pub end: &'src str,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Token<'src> {
+    pub kind: lex::Token<'src>,
+    /// The whitespace, comments and unrecognised characters right before the
+    /// token.
+    pub trivia: &'src str,
+    pub text: &'src str,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Node {
+    pub kind: Kind,
+    pub children: Vec<Child>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Child {
+    Node(Node),
+    /// The index of a token in [`Tree::tokens`].
+    Token(usize),
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Kind {
+    Program,
+    Import,
+    Tempo,
+    Time,
+    Metadata,
+    Definition,
+    Parameters,
+    Where,
+    Local,
+    Group,
+    Call,
+    Choice,
+    /// Tokens which do not make up anything else.
+    Error,
+}
+
+impl Node {
+    /// Get the index of the first token in this node, if it has any.
+    pub fn first_token(&self) -> Option<usize> {
+        self.children.iter().find_map(|child| match child {
+            Child::Node(node) => node.first_token(),
+            Child::Token(index) => Some(*index),
+        })
+    }
+}
+
+impl fmt::Display for Tree<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for token in self.tokens.iter() {
+            write!(f, "{}{}", token.trivia, token.text)?;
+        }
+
+        write!(f, "{}", self.end)
+    }
+}
+
+/// The position in a tree being built where a node may later start.
+#[derive(Clone, Copy)]
+pub(super) struct Checkpoint(usize);
+
+/// Builds a tree bottom up, by wrapping the children added since a checkpoint
+/// in a node once it is known what they make up.
+pub(super) struct Builder<'src> {
+    tokens: Vec<Token<'src>>,
+    children: Vec<Child>,
+}
+
+impl<'src> Builder<'src> {
+    pub fn new() -> Self {
+        Self {
+            tokens: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    pub fn token(&mut self, token: Token<'src>) {
+        self.children.push(Child::Token(self.tokens.len()));
+        self.tokens.push(token);
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint(self.children.len())
+    }
+
+    /// Wrap everything added since `checkpoint` in a node of the given kind.
+    pub fn wrap(&mut self, Checkpoint(start): Checkpoint, kind: Kind) {
+        let children = self.children.split_off(start);
+        self.children.push(Child::Node(Node { kind, children }));
+    }
+
+    pub fn finish(self, end: &'src str) -> Tree<'src> {
+        Tree {
+            tokens: self.tokens,
+            root: Node {
+                kind: Kind::Program,
+                children: self.children,
+            },
+            end,
+        }
+    }
+}
@@ -1,15 +1,37 @@
-use logos::Logos;
+use logos::{Lexer, Logos};
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Logos)]
 #[logos(skip r"\s+")]
-#[logos(skip r"--[^\n]*")]
+#[logos(skip r"--([^-\n][^\n]*)?")]
 pub enum Token<'src> {
-    #[regex(r"\p{XID_Start}[\p{XID_Continue}_']*", |lex| lex.slice())]
+    #[regex(r"\p{XID_Start}[\p{XID_Continue}_']*", name)]
     Name(&'src str),
 
+    /// A `---` doc comment, which documents the definition after it.
+    #[regex(r"---[^\n]*", |lex| lex.slice())]
+    Doc(&'src str),
+
     #[regex(r"[0-9][0-9_]*", |lex| lex.slice())]
     Number(&'src str),
 
+    #[regex(r#""[^"\n]*""#, |lex| lex.slice())]
+    String(&'src str),
+
+    #[token("import")]
+    Import,
+    #[token("inv")]
+    Inv,
+    #[token("in")]
+    In,
+    #[token("tempo")]
+    Tempo,
+    #[token("instrument")]
+    Instrument,
+    #[token("time")]
+    Time,
+    #[token("where")]
+    Where,
+
     #[token("<>")]
     Pause,
 
@@ -19,6 +41,8 @@ pub enum Token<'src> {
     Comma,
     #[token("|")]
     Pipe,
+    #[token(";")]
+    Semicolon,
 
     #[token("+")]
     Plus,
@@ -26,14 +50,57 @@ pub enum Token<'src> {
     Minus,
     #[token("/")]
     Slash,
+    #[token("*")]
+    Star,
 
     #[token("#")]
     Sharp,
     #[token("!")]
     Exclaim,
+    #[token(":")]
+    Colon,
+    #[token("@")]
+    At,
+    #[token("^")]
+    Caret,
+    #[token("^^")]
+    CaretCaret,
+    #[token("~")]
+    Tilde,
+    #[token("..")]
+    DotDot,
 
     #[token("(")]
     LeftParen,
     #[token(")")]
     RightParen,
+    #[token("[")]
+    LeftBracket,
+    #[token("]")]
+    RightBracket,
+    #[token("{")]
+    LeftBrace,
+    #[token("}")]
+    RightBrace,
+}
+
+/// Sharps directly followed by a number or letter are part of the name, such
+/// that note names like `C#4` and chord symbols like `F#m7#11` are a single
+/// token.
+fn name<'src>(lex: &mut Lexer<'src, Token<'src>>) -> &'src str {
+    loop {
+        let rest = lex.remainder();
+        let after_sharps = rest.trim_start_matches('#');
+        let sharps = rest.len() - after_sharps.len();
+        let continued = after_sharps.len()
+            - after_sharps
+                .trim_start_matches(|c: char| c.is_alphanumeric() || c == '_' || c == '\'')
+                .len();
+
+        if sharps == 0 || continued == 0 {
+            break lex.slice();
+        }
+
+        lex.bump(sharps + continued);
+    }
 }
@@ -1,4 +1,5 @@
-mod lex;
+pub mod cst;
+pub mod lex;
 mod rules;
 
 #[cfg(test)]
@@ -9,30 +10,66 @@ use logos::{Logos, SpannedIter};
 use crate::implicit::{Melody, Program};
 use crate::note::Note;
 use crate::span::Span;
-use crate::{Allocator, Heap, Length, Names};
+use crate::{Allocator, Heap, Length, Name, Names};
 
+use self::cst::{Builder, Tree};
 use self::lex::Token;
 
 #[derive(Debug, Eq, Hash, PartialEq)]
 pub enum Error<Id> {
+    ExpectedCount(Span<Id>),
+    ExpectedDegree(Span<Id>),
+    ExpectedDynamic(Span<Id>),
     ExpectedEqual(Span<Id>),
+    ExpectedInterval(Span<Id>),
+    ExpectedMode(Span<Id>),
     ExpectedName(Span<Id>),
     ExpectedNote(Span<Id>),
     ExpectedNumber(Span<Id>),
+    ExpectedPath(Span<Id>),
+    ExpectedTempo(Span<Id>),
+    ExpectedInstrument(Span<Id>),
+    ExpectedChannel(Span<Id>),
+    ExpectedTimeSignature(Span<Id>),
+    ExpectedBars(Span<Id>),
+    UnexpectedCharacter(Span<Id>),
+    UnexpectedToken(Span<Id>),
 
     Redefinition { previous: Span<Id>, new: Span<Id> },
+    ShadowedParameter { parameter: Span<Id>, new: Span<Id> },
 
     DivisionByZero(Span<Id>),
+    Overflow(Span<Id>),
+    UnknownMetadata(Span<Id>),
+    ExpectedWeight(Span<Id>),
+    ExpectedSlice(Span<Id>),
+    ExpectedLength(Span<Id>),
     UnclosedParen { opener: Span<Id>, at: Span<Id> },
+    UnclosedBrace { opener: Span<Id>, at: Span<Id> },
+    UnclosedBracket { opener: Span<Id>, at: Span<Id> },
 }
 
+/// The result of parsing a source, which is either the program or every error
+/// in it.
+pub type Parsed<N, Id, A> = Result<Program<N, Id, A>, Vec<Error<Id>>>;
+
 pub struct Parser<'a, 'names, 'src, N, Id, A: Allocator<Melody<N, Id, A>>> {
     name: Id,
     alloc: &'a mut A,
     names: &'names mut Names,
+    source: &'src str,
     lexer: SpannedIter<'src, Token<'src>>,
     next: Option<(Token<'src>, Span<Id>)>,
     span: Span<Id>,
+    params: Vec<Name>,
+    /// The lines of the doc comments right before the next token.
+    docs: Vec<&'src str>,
+    /// The tokens consumed so far, and where the last of them ends.
+    tree: Builder<'src>,
+    end: usize,
+    /// Whether unrecognised characters were skipped right before the next
+    /// token, in which case the next token is not reported as well.
+    after_unexpected: bool,
 
     errors: Vec<Error<Id>>,
     _n: std::marker::PhantomData<N>,
@@ -49,14 +86,26 @@ where
         names: &'names mut Names,
         name: Id,
         source: &'src str,
-    ) -> Result<Program<N, Id, A>, Vec<Error<Id>>> {
+    ) -> Parsed<N, Id, A> {
+        Self::parse_tree(alloc, names, name, source).0
+    }
+
+    /// Parse `source` like [`Parser::parse`], along with a lossless syntax tree
+    /// of it, which is built even if there are errors.
+    pub fn parse_tree(
+        alloc: &'a mut A,
+        names: &'names mut Names,
+        name: Id,
+        source: &'src str,
+    ) -> (Parsed<N, Id, A>, Tree<'src>) {
         let mut parser = Self::new(alloc, names, name, source);
         parser.advance();
         let parsed = parser.parse_program();
+        let tree = parser.tree.finish(&source[parser.end..]);
         if parser.errors.is_empty() {
-            Ok(parsed)
+            (Ok(parsed), tree)
         } else {
-            Err(parser.errors)
+            (Err(parser.errors), tree)
         }
     }
 
@@ -77,9 +126,15 @@ where
         Self {
             names,
             alloc,
+            source,
             lexer: Token::lexer(source).spanned(),
             next: None,
             span: Span::new(name.clone(), 0..0),
+            params: Vec::new(),
+            docs: Vec::new(),
+            tree: Builder::new(),
+            end: 0,
+            after_unexpected: false,
             errors: Vec::new(),
             name,
 
@@ -89,13 +144,42 @@ where
 
     fn advance(&mut self) -> Option<(Token<'src>, Span<Id>)> {
         let prev = self.next.take();
+        self.docs.clear();
+        self.after_unexpected = false;
+
+        if let Some((kind, span)) = prev.as_ref() {
+            self.tree.token(cst::Token {
+                kind: *kind,
+                trivia: &self.source[self.end..span.start],
+                text: &self.source[span.start..span.end],
+            });
+
+            self.end = span.end;
+        }
 
         for (next, span) in self.lexer.by_ref() {
-            if let Ok(token) = next {
-                let span = Span::new(self.name.clone(), span);
-                self.next = Some((token, span.clone()));
-                self.span = span;
-                break;
+            match next {
+                Ok(Token::Doc(line)) => self.docs.push(line),
+                Ok(token) => {
+                    let span = Span::new(self.name.clone(), span);
+                    self.next = Some((token, span.clone()));
+                    self.span = span;
+                    break;
+                }
+
+                // Consecutive unrecognised characters are reported together.
+                Err(_) => {
+                    let span = Span::new(self.name.clone(), span);
+                    match self.errors.last_mut() {
+                        Some(Error::UnexpectedCharacter(last)) if last.end == span.start => {
+                            *last += span;
+                        }
+
+                        _ => self.errors.push(Error::UnexpectedCharacter(span)),
+                    }
+
+                    self.after_unexpected = true;
+                }
             }
         }
 
@@ -110,10 +194,25 @@ where
         }
     }
 
+    /// Get the token after the next one, if there is one.
+    fn peek_second(&self) -> Option<Token<'src>> {
+        let mut lexer = (*self.lexer).clone();
+        lexer.find_map(|token| match token {
+            Ok(Token::Doc(_)) | Err(_) => None,
+            Ok(token) => Some(token),
+        })
+    }
+
+    /// Whether the next token is the first on its line.
+    fn at_line_start(&self) -> bool {
+        self.next.as_ref().is_some_and(|(_, span)| {
+            self.end == 0 || self.source[self.end..span.start].contains('\n')
+        })
+    }
+
     fn consume(&mut self, m: impl Matcher) -> Option<(Token<'src>, Span<Id>)> {
-        self.peek(m).map(|v| {
+        self.peek(m).inspect(|_| {
             self.advance();
-            v
         })
     }
 }
@@ -127,6 +226,7 @@ impl Matcher for Token<'_> {
         match (self, token) {
             (Token::Name(_), Token::Name(_)) => true,
             (Token::Number(_), Token::Number(_)) => true,
+            (Token::String(_), Token::String(_)) => true,
 
             _ => self == token,
         }
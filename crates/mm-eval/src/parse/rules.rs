@@ -1,19 +1,49 @@
+use std::collections::HashMap;
+
 use num_bigint::BigInt;
 use num_rational::BigRational;
-use num_traits::ToPrimitive;
+use num_traits::{ToPrimitive, Zero};
 
+use super::cst::{Checkpoint, Kind};
 use super::lex::Token;
 use super::{Error, Parser};
-use crate::implicit::{Melody, Program};
+use crate::chord::Chord;
+use crate::implicit::{Bars, Import, Melody, Program};
+use crate::key::{Key, Mode};
 use crate::note::Note;
 use crate::span::Span;
-use crate::{Allocator, Factor, Name};
+use crate::{Allocator, Factor, Length, Name, Tempo, Time, TimeSignature, Velocity, Voice};
+
+/// The tokens which may start a [simple](Parser::simple) melody.
+const SIMPLE_START: [Token<'static>; 10] = [
+    Token::Name(""),
+    Token::Number(""),
+    Token::Pause,
+    Token::LeftParen,
+    Token::Tilde,
+    Token::Inv,
+    Token::In,
+    Token::Tempo,
+    Token::Instrument,
+    Token::LeftBrace,
+];
+
+/// What follows an `@`, which is either a dynamic or a channel.
+enum Marking {
+    Dynamic(Velocity),
+    Channel(Voice),
+}
 
 struct ParsedDefinition<N, Id, A: Allocator<Melody<N, Id, A>>> {
     name: Name,
     name_span: Span<Id>,
+    params: Vec<Name>,
     is_public: bool,
+    bars: Option<(usize, Span<Id>)>,
     body: A::Holder,
+    /// The definitions of its `where` block and of any nested ones, hoisted
+    /// to definitions of their own.
+    locals: Vec<ParsedDefinition<N, Id, A>>,
 }
 
 impl<N, Id, A> Parser<'_, '_, '_, N, Id, A>
@@ -24,28 +54,210 @@ where
 {
     pub(super) fn parse_program(&mut self) -> Program<N, Id, A> {
         let mut program = Program::new(self.span.clone());
+        let mut tempo_span: Option<Span<Id>> = None;
+        let mut time_span: Option<Span<Id>> = None;
+        let mut metadata_spans = HashMap::new();
+        let mut bars = Vec::new();
+
+        // Each item is wrapped in a node of the syntax tree once it has been
+        // parsed, which is at the start of the next one.
+        let mut item: Option<(Checkpoint, Kind)> = None;
 
         while self.next.is_some() {
-            let Some(ParsedDefinition { name, name_span, is_public, body }) = self.definition() else { continue; };
+            if let Some((checkpoint, kind)) = item.take() {
+                self.tree.wrap(checkpoint, kind);
+            }
 
-            if let Some(previous) = program.spans.get(&name).cloned() {
-                self.errors.push(Error::Redefinition {
-                    previous,
-                    new: name_span.clone(),
-                });
+            let checkpoint = self.tree.checkpoint();
+
+            if let Some((_, span)) = self.consume(Token::Import) {
+                item = Some((checkpoint, Kind::Import));
+                program.imports.extend(self.import(span));
+                continue;
+            }
+
+            if let Some((_, span)) = self.consume(Token::Tempo) {
+                item = Some((checkpoint, Kind::Tempo));
+                let Some((tempo, span)) = self.bpm(span) else {
+                    continue;
+                };
+
+                if let Some(previous) = tempo_span.replace(span.clone()) {
+                    self.errors.push(Error::Redefinition {
+                        previous,
+                        new: span,
+                    });
+                }
+
+                program.tempo = Some(tempo);
+                continue;
             }
 
-            program.defs.insert(name, body);
-            program.spans.insert(name, name_span);
+            if let Some((_, span)) = self.consume(Token::Time) {
+                item = Some((checkpoint, Kind::Time));
+                let Some((time, span)) = self.time_signature(span) else {
+                    continue;
+                };
+
+                if let Some(previous) = time_span.replace(span.clone()) {
+                    self.errors.push(Error::Redefinition {
+                        previous,
+                        new: span,
+                    });
+                }
 
-            if is_public {
-                program.public.push(name);
+                program.time = Some(time);
+                continue;
             }
+
+            let docs = self.doc();
+            let Some((Token::Name(name), name_span)) = self.peek(Token::Name("")) else {
+                if !self.after_unexpected {
+                    self.errors.push(Error::UnexpectedToken(self.span.clone()));
+                }
+
+                self.synchronise();
+                continue;
+            };
+
+            self.advance();
+
+            item = Some((checkpoint, Kind::Definition));
+
+            // A name followed by a string declares metadata rather than a
+            // definition.
+            if let Some((Token::String(value), value_span)) = self.consume(Token::String("")) {
+                item = Some((checkpoint, Kind::Metadata));
+                let Some(field) = program.metadata.field_mut(name) else {
+                    self.errors.push(Error::UnknownMetadata(name_span));
+                    continue;
+                };
+
+                let span = name_span + value_span;
+                if let Some(previous) = metadata_spans.insert(name, span.clone()) {
+                    self.errors.push(Error::Redefinition {
+                        previous,
+                        new: span,
+                    });
+                }
+
+                *field = Some(value.trim_matches('"').into());
+                continue;
+            }
+
+            let errors = self.errors.len();
+            let Some(mut definition) = self.definition(name, name_span) else { continue; };
+
+            // Whatever follows a definition which is not the start of another
+            // item is only reported if the definition had no errors, which
+            // would most likely have caused it.
+            if self.next.is_some() && !self.at_item() {
+                if self.errors.len() == errors {
+                    self.errors.push(Error::UnexpectedToken(self.span.clone()));
+                }
+
+                self.synchronise();
+            }
+
+            if let Some(docs) = docs {
+                program.docs.insert(definition.name, docs);
+            }
+
+            let locals = std::mem::take(&mut definition.locals);
+            for ParsedDefinition {
+                name,
+                name_span,
+                params,
+                is_public,
+                bars: count,
+                body,
+                ..
+            } in std::iter::once(definition).chain(locals)
+            {
+                if let Some(previous) = program.spans.get(&name).cloned() {
+                    self.errors.push(Error::Redefinition {
+                        previous,
+                        new: name_span.clone(),
+                    });
+                }
+
+                program.defs.insert(name, body);
+                program.spans.insert(name, name_span);
+
+                if !params.is_empty() {
+                    program.params.insert(name, params);
+                }
+
+                if is_public {
+                    program.public.push(name);
+                }
+
+                if let Some((count, span)) = count {
+                    bars.push((name, count, span));
+                }
+            }
+        }
+
+        if let Some((checkpoint, kind)) = item {
+            self.tree.wrap(checkpoint, kind);
+        }
+
+        // Bars are measured in the time signature of the whole source, which
+        // may be given after the definitions it applies to.
+        let bar_length = program.time.unwrap_or_default().bar_length();
+        for (name, count, span) in bars {
+            let length = Length::Bounded(&bar_length * BigInt::from(count));
+            program.bars.insert(
+                name,
+                Bars {
+                    count,
+                    length,
+                    span,
+                },
+            );
         }
 
         program
     }
 
+    /// Whether the next token starts an item, which is either a declaration
+    /// or a name followed by what may follow the name of a definition or
+    /// metadata field.
+    fn at_item(&self) -> bool {
+        match self.next {
+            Some((Token::Import | Token::Tempo | Token::Time, _)) => true,
+            Some((Token::Name(_), _)) => matches!(
+                self.peek_second(),
+                Some(
+                    Token::Equal
+                        | Token::LeftParen
+                        | Token::At
+                        | Token::Exclaim
+                        | Token::Colon
+                        | Token::String(_)
+                )
+            ),
+            _ => false,
+        }
+    }
+
+    /// Skip ahead to the next line which starts with an item, such that an
+    /// error in one item does not cause others in the items after it. The
+    /// skipped tokens are wrapped in an error node of the syntax tree.
+    fn synchronise(&mut self) {
+        let checkpoint = self.tree.checkpoint();
+        let mut skipped = false;
+
+        while self.next.is_some() && !(self.at_line_start() && self.at_item()) {
+            self.advance();
+            skipped = true;
+        }
+
+        if skipped {
+            self.tree.wrap(checkpoint, Kind::Error);
+        }
+    }
+
     pub(super) fn parse_factor(&mut self) -> (Factor, Span<Id>) {
         let (first, mut span) = match self.advance() {
             Some((Token::Number(s), span)) => (Self::parse_int(s), span),
@@ -75,39 +287,380 @@ where
         (Factor(BigRational::new(first, second)), span)
     }
 
-    fn definition(&mut self) -> Option<ParsedDefinition<N, Id, A>> {
-        let (name, name_span) = match self.advance() {
-            Some((Token::Name(name), span)) => {
-                if N::parse(name).is_some() {
-                    self.errors.push(Error::ExpectedName(span.clone()));
-                }
+    fn import(&mut self, span: Span<Id>) -> Option<Import<Id>> {
+        let Some((Token::String(path), path_span)) = self.consume(Token::String("")) else {
+            self.errors.push(Error::ExpectedPath(self.span.clone()));
+            return None;
+        };
 
-                (self.names.make(name), span)
-            }
+        Some(Import {
+            path: path.trim_matches('"').into(),
+            span: span + path_span,
+        })
+    }
 
-            _ => {
-                self.errors.push(Error::ExpectedName(self.span.clone()));
-                return None;
-            }
+    /// Parse the beats per minute of a tempo, which must be a positive whole
+    /// number.
+    fn bpm(&mut self, span: Span<Id>) -> Option<(Tempo, Span<Id>)> {
+        let Some((Token::Number(s), bpm_span)) = self.consume(Token::Number("")) else {
+            self.errors.push(Error::ExpectedTempo(self.span.clone()));
+            return None;
         };
 
+        match Tempo::from_bpm(Self::parse_int(s)) {
+            Some(tempo) => Some((tempo, span + bpm_span)),
+            None => {
+                self.errors.push(Error::ExpectedTempo(bpm_span));
+                None
+            }
+        }
+    }
+
+    /// Get the text of the doc comments right before the next token, if any,
+    /// with the dashes of every line stripped.
+    fn doc(&self) -> Option<String> {
+        if self.docs.is_empty() {
+            return None;
+        }
+
+        let lines: Vec<_> = self
+            .docs
+            .iter()
+            .map(|line| line.trim_start_matches('-').trim())
+            .collect();
+
+        Some(lines.join("\n"))
+    }
+
+    /// Parse the rest of the definition of the given name.
+    fn definition(
+        &mut self,
+        name: &str,
+        name_span: Span<Id>,
+    ) -> Option<ParsedDefinition<N, Id, A>> {
+        if N::parse(name).is_some() || Chord::<N>::parse(name).is_some() {
+            self.errors.push(Error::ExpectedName(name_span.clone()));
+        }
+
+        let name = self.names.make(name);
+
+        let params = self.parameters();
+
+        // A marking on the name applies to the whole body.
+        let marking = self.peek(Token::At).map(|_| self.marking());
+
         let is_public = self.consume(Token::Exclaim).is_some();
 
+        let bars = match self.consume(Token::Colon) {
+            Some((_, span)) => self.bars(span),
+            None => None,
+        };
+
         if self.consume(Token::Equal).is_none() {
-            self.errors.push(Error::ExpectedEqual(self.span.clone()));
+            if !self.after_unexpected {
+                self.errors.push(Error::ExpectedEqual(self.span.clone()));
+            }
+
+            self.synchronise();
             return None;
         }
 
-        let body = self.expression();
+        self.params = params.iter().map(|(param, _)| *param).collect();
+        let mut body = self.expression();
+        if let Some((marking, span)) = marking {
+            body = self.mark(marking, span, body);
+        }
+
+        let locals = self.locals(&params, &mut body);
         let body = self.alloc.pack(body);
+        self.params.clear();
+
         Some(ParsedDefinition {
             name,
             name_span,
+            params: params.into_iter().map(|(param, _)| param).collect(),
             is_public,
+            bars,
             body,
+            locals,
         })
     }
 
+    /// Parse the `where` block after `body`, if there is one, and return its
+    /// definitions. Its definitions are local to `body` and to each other, and
+    /// may use the parameters in `scope`. They are hoisted to definitions of
+    /// their own with fresh names, which take the parameters in `scope` before
+    /// their own, and every reference to them is replaced accordingly.
+    fn locals(
+        &mut self,
+        scope: &[(Name, Span<Id>)],
+        body: &mut Melody<N, Id, A>,
+    ) -> Vec<ParsedDefinition<N, Id, A>> {
+        let checkpoint = self.tree.checkpoint();
+        if self.consume(Token::Where).is_none() {
+            return Vec::new();
+        }
+
+        let mut renames = HashMap::new();
+        let mut bindings = Vec::new();
+        let mut locals = Vec::new();
+
+        loop {
+            let local_checkpoint = self.tree.checkpoint();
+            let Some((Token::Name(name), name_span)) = self.consume(Token::Name("")) else {
+                self.errors.push(Error::ExpectedName(self.span.clone()));
+                break;
+            };
+
+            if N::parse(name).is_some() || Chord::<N>::parse(name).is_some() {
+                self.errors.push(Error::ExpectedName(name_span.clone()));
+            }
+
+            let local = self.names.make(name);
+            let fresh = self.names.fresh(name);
+            self.shadows(scope, local, &name_span);
+
+            if let Some((_, previous)) = renames.insert(local, (fresh, name_span.clone())) {
+                self.errors.push(Error::Redefinition {
+                    previous,
+                    new: name_span.clone(),
+                });
+            }
+
+            let own = self.parameters();
+
+            for (param, span) in own.iter() {
+                self.shadows(scope, *param, span);
+            }
+
+            if self.consume(Token::Equal).is_none() {
+                self.errors.push(Error::ExpectedEqual(self.span.clone()));
+                self.tree.wrap(local_checkpoint, Kind::Local);
+                break;
+            }
+
+            let params: Vec<_> = scope.iter().chain(own.iter()).cloned().collect();
+            self.params = params.iter().map(|(param, _)| *param).collect();
+            let mut local_body = self.expression();
+            let nested = self.locals(&params, &mut local_body);
+
+            bindings.push((fresh, name_span, self.params.clone(), local_body));
+            self.params = scope.iter().map(|(param, _)| *param).collect();
+            locals.extend(nested);
+            self.tree.wrap(local_checkpoint, Kind::Local);
+
+            if self.consume(Token::Semicolon).is_none() {
+                break;
+            }
+        }
+
+        self.tree.wrap(checkpoint, Kind::Where);
+
+        let renames: HashMap<_, _> = renames
+            .into_iter()
+            .map(|(local, (fresh, _))| (local, fresh))
+            .collect();
+
+        let scope: Vec<_> = scope.iter().map(|(param, _)| *param).collect();
+        *body = self.substitute(&renames, &scope, body);
+
+        for local in locals.iter_mut() {
+            let body = self.substitute(&renames, &scope, A::as_ref(&local.body));
+            local.body = self.alloc.pack(body);
+        }
+
+        for (name, name_span, params, body) in bindings {
+            let body = self.substitute(&renames, &scope, &body);
+            locals.push(ParsedDefinition {
+                name,
+                name_span,
+                params,
+                is_public: false,
+                bars: None,
+                body: self.alloc.pack(body),
+                locals: Vec::new(),
+            });
+        }
+
+        locals
+    }
+
+    /// Report the parameter in `scope` which a local definition or parameter
+    /// of the given name would shadow, if there is one.
+    fn shadows(&mut self, scope: &[(Name, Span<Id>)], name: Name, span: &Span<Id>) {
+        if let Some((_, parameter)) = scope.iter().find(|(param, _)| *param == name) {
+            self.errors.push(Error::ShadowedParameter {
+                parameter: parameter.clone(),
+                new: span.clone(),
+            });
+        }
+    }
+
+    /// Copy `melody`, replacing every reference to a local definition with a
+    /// reference to its hoisted definition, which is passed the parameters in
+    /// `scope` before any arguments of its own.
+    fn substitute(
+        &mut self,
+        renames: &HashMap<Name, Name>,
+        scope: &[Name],
+        melody: &Melody<N, Id, A>,
+    ) -> Melody<N, Id, A> {
+        melody.replace_names(self.alloc, &mut |alloc, span, name, args| {
+            let Some(fresh) = renames.get(&name) else {
+                return match args {
+                    Some(args) => Melody::Call(span, name, alloc.pack_many(args)),
+                    None => Melody::Name(span, name),
+                };
+            };
+
+            if scope.is_empty() && args.is_none() {
+                return Melody::Name(span, *fresh);
+            }
+
+            let passed: Vec<_> = scope
+                .iter()
+                .map(|param| Melody::Parameter(span.clone(), *param))
+                .chain(args.into_iter().flatten())
+                .collect();
+
+            Melody::Call(span, *fresh, alloc.pack_many(passed))
+        })
+    }
+
+    /// Parse the number of bars in a `: 4 bars` annotation.
+    fn bars(&mut self, span: Span<Id>) -> Option<(usize, Span<Id>)> {
+        let count = self.consume(Token::Number(""));
+        let unit = self.consume(Token::Name(""));
+
+        // The unit is consumed even when the count is missing, such that the
+        // rest of the definition can still be parsed.
+        match (count, unit) {
+            (Some((Token::Number(s), count_span)), Some((Token::Name("bar" | "bars"), unit))) => {
+                match Self::parse_int(s).to_usize() {
+                    Some(count) => Some((count, span + unit)),
+                    None => {
+                        self.errors.push(Error::Overflow(count_span));
+                        None
+                    }
+                }
+            }
+
+            (_, Some((_, unit))) => {
+                self.errors.push(Error::ExpectedBars(unit));
+                None
+            }
+
+            (_, None) => {
+                self.errors.push(Error::ExpectedBars(self.span.clone()));
+                None
+            }
+        }
+    }
+
+    /// Parse a time signature like `3/4`, with a positive number of beats and
+    /// a unit which is a power of two.
+    fn time_signature(&mut self, span: Span<Id>) -> Option<(TimeSignature, Span<Id>)> {
+        let Some((Token::Number(beats), beats_span)) = self.consume(Token::Number("")) else {
+            self.errors
+                .push(Error::ExpectedTimeSignature(self.span.clone()));
+            return None;
+        };
+
+        let unit = match (self.consume(Token::Slash), self.consume(Token::Number(""))) {
+            (Some(_), Some((Token::Number(unit), unit_span))) => Some((unit, unit_span)),
+            _ => None,
+        };
+
+        let Some((unit, unit_span)) = unit else {
+            self.errors
+                .push(Error::ExpectedTimeSignature(self.span.clone()));
+            return None;
+        };
+
+        let beats = Self::parse_int(beats).to_u8();
+        let unit = Self::parse_int(unit).to_u8();
+
+        match beats
+            .zip(unit)
+            .and_then(|(beats, unit)| TimeSignature::new(beats, unit))
+        {
+            Some(time) => Some((time, span + unit_span)),
+            None => {
+                self.errors
+                    .push(Error::ExpectedTimeSignature(beats_span + unit_span));
+                None
+            }
+        }
+    }
+
+    /// Parse the parenthesised parameters of a definition, if it has any.
+    fn parameters(&mut self) -> Vec<(Name, Span<Id>)> {
+        let checkpoint = self.tree.checkpoint();
+        let Some((_, opener)) = self.consume(Token::LeftParen) else {
+            return Vec::new();
+        };
+
+        let mut params: Vec<(Name, Span<Id>)> = Vec::new();
+
+        loop {
+            match self.consume(Token::Name("")) {
+                Some((Token::Name(param), span)) => {
+                    if N::parse(param).is_some() || Chord::<N>::parse(param).is_some() {
+                        self.errors.push(Error::ExpectedName(span.clone()));
+                    }
+
+                    let param = self.names.make(param);
+                    if let Some((_, previous)) = params.iter().find(|(other, _)| *other == param) {
+                        self.errors.push(Error::Redefinition {
+                            previous: previous.clone(),
+                            new: span.clone(),
+                        });
+                    }
+
+                    params.push((param, span));
+                }
+
+                _ => {
+                    self.errors.push(Error::ExpectedName(self.span.clone()));
+                    break;
+                }
+            }
+
+            if self.consume(Token::Comma).is_none() {
+                break;
+            }
+        }
+
+        if self.consume(Token::RightParen).is_none() {
+            self.errors.push(Error::UnclosedParen {
+                opener,
+                at: self.span.clone(),
+            });
+        }
+
+        self.tree.wrap(checkpoint, Kind::Parameters);
+        params
+    }
+
+    fn arguments(&mut self, opener: Span<Id>) -> (Vec<Melody<N, Id, A>>, Span<Id>) {
+        let mut args = vec![self.scale()];
+
+        while self.consume(Token::Comma).is_some() {
+            args.push(self.scale());
+        }
+
+        if let Some((_, closer)) = self.consume(Token::RightParen) {
+            (args, opener + closer)
+        } else {
+            self.errors.push(Error::UnclosedParen {
+                opener: opener.clone(),
+                at: self.span.clone(),
+            });
+
+            (args, opener)
+        }
+    }
+
     fn expression(&mut self) -> Melody<N, Id, A> {
         self.stack()
     }
@@ -128,7 +681,13 @@ where
     }
 
     fn sequence(&mut self) -> Melody<N, Id, A> {
-        let mut melodies = vec![self.scale()];
+        let first = self.scale();
+        self.sequence_from(first)
+    }
+
+    /// Parse the rest of a sequence which starts with `first`.
+    fn sequence_from(&mut self, first: Melody<N, Id, A>) -> Melody<N, Id, A> {
+        let mut melodies = vec![first];
 
         while self.consume(Token::Comma).is_some() {
             melodies.push(self.scale());
@@ -143,11 +702,23 @@ where
     }
 
     fn scale(&mut self) -> Melody<N, Id, A> {
-        let mut melody = if self.peek(Token::Number("")).is_some() {
-            let (by, factor_span) = self.parse_factor();
-            let melody = self.simple();
-            let melody = self.alloc.pack(melody);
-            Melody::Scale(factor_span, by, melody)
+        let factor = self.peek(Token::Number("")).map(|_| self.parse_factor());
+
+        self.scaled(factor)
+    }
+
+    /// Parse the rest of a melody which starts with the given factor, if any.
+    fn scaled(&mut self, factor: Option<(Factor, Span<Id>)>) -> Melody<N, Id, A> {
+        let mut melody = if let Some((by, factor_span)) = factor {
+            // A number which is not followed by a melody is a scale degree,
+            // as is one which ends its definition before the next item.
+            if self.peek(SIMPLE_START).is_some() && !(self.at_line_start() && self.at_item()) {
+                let melody = self.simple();
+                let melody = self.alloc.pack(melody);
+                Melody::Scale(factor_span, by, melody)
+            } else {
+                Self::degree(&mut self.errors, by, factor_span)
+            }
         } else {
             self.simple()
         };
@@ -168,24 +739,158 @@ where
             melody = Melody::Sharp(sharp_span, sharps, inner);
         }
 
+        if self.peek(Token::CaretCaret).is_some() {
+            let (by, steps_span) = self.steps();
+            let inner = self.alloc.pack(melody);
+            melody = Melody::Steps(steps_span, by, inner);
+        }
+
+        if self.peek(Token::Caret).is_some() {
+            let (by, transpose_span) = self.transpose();
+            let inner = self.alloc.pack(melody);
+            melody = Melody::Sharp(transpose_span, by, inner);
+        }
+
         if self.peek([Token::Minus, Token::Plus]).is_some() {
             let (offset, offset_span) = self.offset();
-            let melody = self.alloc.pack(melody);
-            Melody::Offset(offset_span, offset, melody)
-        } else {
-            melody
+            let inner = self.alloc.pack(melody);
+            melody = Melody::Offset(offset_span, offset, inner);
+        }
+
+        if self.peek(Token::At).is_some() {
+            let (marking, span) = self.marking();
+            melody = self.mark(marking, span, melody);
         }
+
+        if self.peek(Token::Star).is_some() {
+            let (count, repeat_span) = self.repeat();
+            let inner = self.alloc.pack(melody);
+            melody = Melody::Repeat(repeat_span, count, inner);
+        }
+
+        if let Some((_, opener)) = self.consume(Token::LeftBracket) {
+            if let Some((from, to, slice_span)) = self.slice(opener) {
+                let inner = self.alloc.pack(melody);
+                melody = Melody::Slice(slice_span, from, to, inner);
+            }
+        }
+
+        if let Some((_, colon)) = self.consume(Token::Colon) {
+            if let Some((length, fit_span)) = self.fit(colon) {
+                let inner = self.alloc.pack(melody);
+                melody = Melody::Fit(fit_span, length, inner);
+            }
+        }
+
+        melody
     }
 
     fn simple(&mut self) -> Melody<N, Id, A> {
+        if self.peek(Token::Number("")).is_some() {
+            let (degree, span) = self.parse_factor();
+            return Self::degree(&mut self.errors, degree, span);
+        }
+
+        // What cannot start a melody is left for the melody around it, which
+        // may be able to continue after it.
+        if self.peek(SIMPLE_START).is_none() {
+            self.errors.push(Error::ExpectedNote(self.span.clone()));
+            return Melody::Pause(self.span.clone());
+        }
+
+        let checkpoint = self.tree.checkpoint();
         let melody = match self.advance() {
             Some((Token::Name(n), span)) => match N::parse(n) {
                 Some(note) => Melody::Note(span, note),
-                None => Melody::Name(span, self.names.make(n)),
+                None => match Chord::parse(n) {
+                    Some(chord) => self.chord(chord, span),
+                    None => {
+                        let name = self.names.make(n);
+
+                        if self.params.contains(&name) {
+                            Melody::Parameter(span, name)
+                        } else if let Some((_, opener)) = self.consume(Token::LeftParen) {
+                            let (args, args_span) = self.arguments(opener);
+                            let args = self.alloc.pack_many(args);
+                            self.tree.wrap(checkpoint, Kind::Call);
+                            Melody::Call(span + args_span, name, args)
+                        } else {
+                            Melody::Name(span, name)
+                        }
+                    }
+                },
             },
 
             Some((Token::Pause, span)) => Melody::Pause(span),
 
+            Some((Token::Inv, span)) => {
+                let axis = self.axis(span);
+                let melody = self.simple();
+
+                match axis {
+                    Some((axis, span)) => {
+                        let melody = self.alloc.pack(melody);
+                        Melody::Invert(span, axis, melody)
+                    }
+
+                    None => melody,
+                }
+            }
+
+            Some((Token::Tempo, span)) => {
+                let tempo = self.tempo(span);
+                let melody = self.simple();
+
+                match tempo {
+                    Some((from, to, span)) => {
+                        let melody = self.alloc.pack(melody);
+                        Melody::Tempo(span, from, to, melody)
+                    }
+
+                    None => melody,
+                }
+            }
+
+            Some((Token::Instrument, span)) => {
+                let instrument = self.instrument(span);
+                let melody = self.simple();
+
+                match instrument {
+                    Some((voice, span)) => {
+                        let melody = self.alloc.pack(melody);
+                        Melody::Voice(span, voice, melody)
+                    }
+
+                    None => melody,
+                }
+            }
+
+            Some((Token::In, span)) => {
+                let key = self.key(span);
+                let melody = self.simple();
+
+                match key {
+                    Some((key, span)) => {
+                        let melody = self.alloc.pack(melody);
+                        Melody::Key(span, key, melody)
+                    }
+
+                    None => melody,
+                }
+            }
+
+            Some((Token::Tilde, span)) => {
+                let melody = self.simple();
+                let melody = self.alloc.pack(melody);
+                Melody::Reverse(span, melody)
+            }
+
+            Some((Token::LeftBrace, opener)) => {
+                let melody = self.choice(opener);
+                self.tree.wrap(checkpoint, Kind::Choice);
+                melody
+            }
+
             Some((Token::LeftParen, opener)) => {
                 let melody = self.expression();
 
@@ -196,16 +901,227 @@ where
                     });
                 }
 
+                self.tree.wrap(checkpoint, Kind::Group);
                 melody
             }
 
+            _ => unreachable!(),
+        };
+
+        melody
+    }
+
+    fn degree(errors: &mut Vec<Error<Id>>, degree: Factor, span: Span<Id>) -> Melody<N, Id, A> {
+        let degree = match Self::whole(&degree) {
+            Some(degree) => degree.to_isize().ok_or(Error::Overflow(span.clone())),
+            None => Err(Error::ExpectedDegree(span.clone())),
+        };
+
+        match degree {
+            Ok(degree) => Melody::Degree(span, degree),
+            Err(error) => {
+                errors.push(error);
+                Melody::Pause(span)
+            }
+        }
+    }
+
+    /// Get a factor as a positive whole number, if it is one.
+    fn whole(factor: &Factor) -> Option<BigInt> {
+        (factor.0.is_integer() && factor.0 > BigRational::zero()).then(|| factor.0.to_integer())
+    }
+
+    /// Parse the tonic and mode of an `in` operator.
+    fn key(&mut self, span: Span<Id>) -> Option<(Key<N>, Span<Id>)> {
+        let tonic = match self.consume(Token::Name("")) {
+            Some((Token::Name(name), note_span)) => {
+                let note = N::parse(name);
+                if note.is_none() {
+                    self.errors.push(Error::ExpectedNote(note_span));
+                }
+
+                note
+            }
+
             _ => {
                 self.errors.push(Error::ExpectedNote(self.span.clone()));
-                Melody::Pause(self.span.clone())
+                return None;
             }
         };
 
-        melody
+        let (mode, mode_span) = match self.consume(Token::Name("")) {
+            Some((Token::Name(name), mode_span)) => (Mode::from_name(name), mode_span),
+            _ => (None, self.span.clone()),
+        };
+
+        let Some(mode) = mode else {
+            self.errors.push(Error::ExpectedMode(mode_span));
+            return None;
+        };
+
+        tonic.map(|tonic| (Key { tonic, mode }, span + mode_span))
+    }
+
+    fn steps(&mut self) -> (isize, Span<Id>) {
+        let mut span = match self.advance() {
+            Some((Token::CaretCaret, span)) => span,
+            _ => unreachable!(),
+        };
+
+        let sign = match self.consume([Token::Minus, Token::Plus]) {
+            Some((Token::Minus, sign_span)) => {
+                span += sign_span;
+                -1
+            }
+
+            Some((_, sign_span)) => {
+                span += sign_span;
+                1
+            }
+
+            None => 1,
+        };
+
+        let Some((Token::Number(s), by_span)) = self.consume(Token::Number("")) else {
+            self.errors.push(Error::ExpectedNumber(self.span.clone()));
+            return (0, span);
+        };
+
+        span += by_span;
+        match Self::parse_int(s).to_isize() {
+            Some(by) => (sign * by, span),
+            None => {
+                self.errors.push(Error::Overflow(span.clone()));
+                (0, span)
+            }
+        }
+    }
+
+    /// Expand a chord symbol, along with its bass note if it is followed by a
+    /// slash, into a stack of notes. Bass notes without an octave number are
+    /// played an octave below the root.
+    fn chord(&mut self, chord: Chord<N>, mut span: Span<Id>) -> Melody<N, Id, A> {
+        let mut notes = chord.notes().unwrap_or_else(|| {
+            self.errors.push(Error::Overflow(span.clone()));
+            Vec::new()
+        });
+
+        if self.consume(Token::Slash).is_some() {
+            match self.consume(Token::Name("")) {
+                Some((Token::Name(name), bass_span)) => {
+                    span += bass_span.clone();
+
+                    // Any cents the note is raised by come after its octave.
+                    let (note, _) = name.split_once('\'').unwrap_or((name, ""));
+                    match N::parse(name) {
+                        Some(bass) if note.ends_with(|c: char| c.is_ascii_digit()) => {
+                            notes.insert(0, bass)
+                        }
+                        Some(bass) => match bass.add_octave(-1) {
+                            Some(bass) => notes.insert(0, bass),
+                            None => self.errors.push(Error::Overflow(bass_span)),
+                        },
+                        None => self.errors.push(Error::ExpectedNote(bass_span)),
+                    }
+                }
+
+                _ => self.errors.push(Error::ExpectedNote(self.span.clone())),
+            }
+        }
+
+        let notes: Vec<_> = notes
+            .into_iter()
+            .map(|note| Melody::Note(span.clone(), note))
+            .collect();
+
+        if notes.is_empty() {
+            Melody::Pause(span)
+        } else {
+            Melody::Stack(self.alloc.pack_many(notes))
+        }
+    }
+
+    /// Parse the parenthesized tempo of a `tempo` operator, which is either a
+    /// single tempo like `(96)`, or a gradual change like `(80..96)`. Gradual
+    /// changes without a first tempo like `(..96)` start from the surrounding
+    /// tempo.
+    fn tempo(&mut self, span: Span<Id>) -> Option<(Option<Tempo>, Tempo, Span<Id>)> {
+        let Some((_, opener)) = self.consume(Token::LeftParen) else {
+            self.errors.push(Error::ExpectedTempo(self.span.clone()));
+            return None;
+        };
+
+        // Tempos which are given but invalid are reported as they are parsed,
+        // after which the rest of the operator is still consumed.
+        let mut valid = true;
+
+        let from = match self.peek(Token::Number("")) {
+            Some((_, span)) => {
+                let from = self.bpm(span);
+                valid &= from.is_some();
+                from.map(|(from, _)| from)
+            }
+
+            None => None,
+        };
+
+        let to = if let Some((_, dots)) = self.consume(Token::DotDot) {
+            let to = self.bpm(dots);
+            valid &= to.is_some();
+            to.map(|(to, _)| to)
+        } else if from.is_none() && valid {
+            self.errors.push(Error::ExpectedTempo(self.span.clone()));
+            None
+        } else {
+            from.clone()
+        };
+
+        let Some((_, closer)) = self.consume(Token::RightParen) else {
+            self.errors.push(Error::UnclosedParen {
+                opener,
+                at: self.span.clone(),
+            });
+
+            return None;
+        };
+
+        let to = to.filter(|_| valid)?;
+        Some((from, to, span + closer))
+    }
+
+    /// Parse the parenthesized note of an `inv` operator.
+    fn axis(&mut self, span: Span<Id>) -> Option<(N, Span<Id>)> {
+        let Some((_, opener)) = self.consume(Token::LeftParen) else {
+            self.errors.push(Error::ExpectedNote(self.span.clone()));
+            return None;
+        };
+
+        let axis = match self.consume(Token::Name("")) {
+            Some((Token::Name(name), note_span)) => {
+                let note = N::parse(name);
+                if note.is_none() {
+                    self.errors.push(Error::ExpectedNote(note_span));
+                }
+
+                note
+            }
+
+            _ => {
+                self.errors.push(Error::ExpectedNote(self.span.clone()));
+                None
+            }
+        };
+
+        let Some((_, closer)) = self.consume(Token::RightParen) else {
+            self.errors.push(Error::UnclosedParen {
+                opener,
+                at: self.span.clone(),
+            });
+
+            return None;
+        };
+
+        axis.map(|axis| (axis, span + closer))
     }
 
     fn offset(&mut self) -> (isize, Span<Id>) {
@@ -223,13 +1139,325 @@ where
                 (BigInt::from(1), self.span.clone())
             };
 
-        if let Some(offset) = num.to_isize() {
-            (offset, span)
-        } else {
-            todo!()
+        match num.to_isize() {
+            Some(offset) => (offset, span),
+            None => {
+                self.errors.push(Error::Overflow(span.clone()));
+                (0, span)
+            }
         }
     }
 
+    fn repeat(&mut self) -> (Option<usize>, Span<Id>) {
+        let span = match self.advance() {
+            Some((Token::Star, span)) => span,
+            _ => unreachable!(),
+        };
+
+        match self.consume([Token::Name(""), Token::Number("")]) {
+            Some((Token::Number(s), count_span)) => {
+                let span = span + count_span;
+                match Self::parse_int(s).to_usize() {
+                    Some(count) => (Some(count), span),
+                    None => {
+                        self.errors.push(Error::Overflow(span.clone()));
+                        (Some(1), span)
+                    }
+                }
+            }
+
+            Some((Token::Name("oo"), count_span)) => (None, span + count_span),
+
+            Some((_, count_span)) => {
+                self.errors.push(Error::ExpectedCount(count_span.clone()));
+                (Some(1), span + count_span)
+            }
+
+            None => {
+                self.errors.push(Error::ExpectedCount(self.span.clone()));
+                (Some(1), span)
+            }
+        }
+    }
+
+    fn transpose(&mut self) -> (isize, Span<Id>) {
+        let mut span = match self.advance() {
+            Some((Token::Caret, span)) => span,
+            _ => unreachable!(),
+        };
+
+        let sign = match self.consume([Token::Minus, Token::Plus]) {
+            Some((Token::Minus, sign_span)) => {
+                span += sign_span;
+                -1
+            }
+
+            Some((_, sign_span)) => {
+                span += sign_span;
+                1
+            }
+
+            None => 1,
+        };
+
+        let by = match self.consume([Token::Name(""), Token::Number("")]) {
+            Some((Token::Number(s), by_span)) => {
+                span += by_span;
+                let Some(by) = Self::parse_int(s).to_isize() else {
+                    self.errors.push(Error::Overflow(span.clone()));
+                    return (0, span);
+                };
+
+                Some(by)
+            }
+
+            Some((Token::Name(name), by_span)) => {
+                span += by_span;
+                Self::interval(name)
+            }
+
+            _ => {
+                self.errors.push(Error::ExpectedInterval(self.span.clone()));
+                return (0, span);
+            }
+        };
+
+        match by {
+            Some(by) => (sign * by, span),
+            None => {
+                self.errors.push(Error::ExpectedInterval(span.clone()));
+                (0, span)
+            }
+        }
+    }
+
+    /// Get the number of semitones in a named interval like `m3` or `P5`.
+    fn interval(name: &str) -> Option<isize> {
+        Some(match name {
+            "P1" => 0,
+            "m2" => 1,
+            "M2" => 2,
+            "m3" => 3,
+            "M3" => 4,
+            "P4" => 5,
+            "A4" | "d5" | "TT" => 6,
+            "P5" => 7,
+            "m6" => 8,
+            "M6" => 9,
+            "m7" => 10,
+            "M7" => 11,
+            "P8" => 12,
+
+            _ => return None,
+        })
+    }
+
+    /// Parse a dynamic like `@80` or `@mf`, or a channel like `@ch2`.
+    fn marking(&mut self) -> (Marking, Span<Id>) {
+        let span = match self.advance() {
+            Some((Token::At, span)) => span,
+            _ => unreachable!(),
+        };
+
+        match self.consume([Token::Name(""), Token::Number("")]) {
+            Some((Token::Number(s), level_span)) => {
+                let velocity = Velocity::from_level(Self::parse_int(s)).unwrap_or_else(|| {
+                    self.errors.push(Error::ExpectedDynamic(level_span.clone()));
+                    Velocity::one()
+                });
+
+                (Marking::Dynamic(velocity), span + level_span)
+            }
+
+            Some((Token::Name(marking), marking_span)) => {
+                let channel = marking
+                    .strip_prefix("ch")
+                    .filter(|number| !number.is_empty())
+                    .filter(|number| number.chars().all(|c| c.is_ascii_digit()));
+
+                if let Some(number) = channel {
+                    let voice = Self::parse_int(number).to_u8().and_then(Voice::channel);
+                    let voice = voice.unwrap_or_else(|| {
+                        self.errors
+                            .push(Error::ExpectedChannel(marking_span.clone()));
+                        Voice::default()
+                    });
+
+                    (Marking::Channel(voice), span + marking_span)
+                } else if let Some(velocity) = Velocity::from_marking(marking) {
+                    (Marking::Dynamic(velocity), span + marking_span)
+                } else {
+                    self.errors
+                        .push(Error::ExpectedDynamic(marking_span.clone()));
+                    (Marking::Dynamic(Velocity::one()), span + marking_span)
+                }
+            }
+
+            _ => {
+                self.errors.push(Error::ExpectedDynamic(self.span.clone()));
+                (Marking::Dynamic(Velocity::one()), span)
+            }
+        }
+    }
+
+    /// Apply a dynamic or channel to a melody.
+    fn mark(
+        &mut self,
+        marking: Marking,
+        span: Span<Id>,
+        melody: Melody<N, Id, A>,
+    ) -> Melody<N, Id, A> {
+        let inner = self.alloc.pack(melody);
+        match marking {
+            Marking::Dynamic(velocity) => Melody::Dynamic(span, velocity, inner),
+            Marking::Channel(voice) => Melody::Voice(span, voice, inner),
+        }
+    }
+
+    /// Parse the bounds of a slice like `[4..12]` or `[..8]` up to the closing
+    /// bracket, where the start defaults to zero and must come before the end.
+    fn slice(&mut self, opener: Span<Id>) -> Option<(Time, Time, Span<Id>)> {
+        let from = self.peek(Token::Number("")).map(|_| self.parse_factor());
+
+        let to = match self.consume(Token::DotDot) {
+            Some(_) if self.peek(Token::Number("")).is_some() => Some(self.parse_factor()),
+            _ => {
+                self.errors.push(Error::ExpectedSlice(self.span.clone()));
+                None
+            }
+        };
+
+        let Some((_, closer)) = self.consume(Token::RightBracket) else {
+            self.errors.push(Error::UnclosedBracket {
+                opener,
+                at: self.span.clone(),
+            });
+
+            return None;
+        };
+
+        let (Factor(to), to_span) = to?;
+        let from = match from {
+            Some((Factor(from), _)) => from,
+            None => BigRational::from_integer(BigInt::from(0)),
+        };
+
+        if from >= to {
+            self.errors.push(Error::ExpectedSlice(to_span));
+            return None;
+        }
+
+        Some((Time(from), Time(to), opener + closer))
+    }
+
+    /// Parse the length after the colon of a fit like `motif : 3`, which must
+    /// be positive.
+    fn fit(&mut self, colon: Span<Id>) -> Option<(Time, Span<Id>)> {
+        if self.peek(Token::Number("")).is_none() {
+            self.errors.push(Error::ExpectedLength(self.span.clone()));
+            return None;
+        }
+
+        let (Factor(length), length_span) = self.parse_factor();
+        if length.is_zero() {
+            self.errors.push(Error::ExpectedLength(length_span));
+            return None;
+        }
+
+        Some((Time(length), colon + length_span))
+    }
+
+    /// Parse the branches of a choice like `{A | 2: B}` up to the closing
+    /// brace, where every branch may be preceded by a whole number weight.
+    fn choice(&mut self, opener: Span<Id>) -> Melody<N, Id, A> {
+        let mut weights = Vec::new();
+        let mut melodies = Vec::new();
+
+        loop {
+            let factor = self.peek(Token::Number("")).map(|_| self.parse_factor());
+
+            let (weight, first) = match factor {
+                Some((by, span)) if self.consume(Token::Colon).is_some() => {
+                    (self.weight(by, span), self.scale())
+                }
+
+                factor => (1, self.scaled(factor)),
+            };
+
+            weights.push(weight);
+            melodies.push(self.sequence_from(first));
+
+            if self.consume(Token::Pipe).is_none() {
+                break;
+            }
+        }
+
+        let span = match self.consume(Token::RightBrace) {
+            Some((_, closer)) => opener + closer,
+            None => {
+                self.errors.push(Error::UnclosedBrace {
+                    opener: opener.clone(),
+                    at: self.span.clone(),
+                });
+
+                opener + self.span.clone()
+            }
+        };
+
+        Melody::Choice(span, weights, self.alloc.pack_many(melodies))
+    }
+
+    /// Check that the weight of a branch is a positive whole number.
+    fn weight(&mut self, by: Factor, span: Span<Id>) -> usize {
+        let weight = match Self::whole(&by) {
+            Some(weight) => weight.to_usize().ok_or(Error::Overflow(span)),
+            None => Err(Error::ExpectedWeight(span)),
+        };
+
+        weight.unwrap_or_else(|error| {
+            self.errors.push(error);
+            1
+        })
+    }
+
+    /// Parse the parenthesized General MIDI program number of an `instrument`
+    /// operator, counting from one.
+    fn instrument(&mut self, span: Span<Id>) -> Option<(Voice, Span<Id>)> {
+        let Some((_, opener)) = self.consume(Token::LeftParen) else {
+            self.errors
+                .push(Error::ExpectedInstrument(self.span.clone()));
+            return None;
+        };
+
+        let voice = match self.consume(Token::Number("")) {
+            Some((Token::Number(s), number_span)) => {
+                let voice = Self::parse_int(s).to_u8().and_then(Voice::instrument);
+                if voice.is_none() {
+                    self.errors.push(Error::ExpectedInstrument(number_span));
+                }
+
+                voice
+            }
+
+            _ => {
+                self.errors
+                    .push(Error::ExpectedInstrument(self.span.clone()));
+                None
+            }
+        };
+
+        let Some((_, closer)) = self.consume(Token::RightParen) else {
+            self.errors.push(Error::UnclosedParen {
+                opener,
+                at: self.span.clone(),
+            });
+
+            return None;
+        };
+
+        Some((voice?, span + closer))
+    }
+
     fn parse_int(s: &str) -> BigInt {
         let mut res = BigInt::from(0);
         for c in s.chars() {
@@ -3,10 +3,16 @@ use std::collections::HashMap;
 use num_bigint::BigInt;
 use num_rational::BigRational;
 
-use crate::implicit::{Melody, Program};
+use crate::implicit::{Bars, Import, Melody, Program};
+use crate::key::{Key, Mode};
+use crate::note::Note;
 use crate::span::span_in;
-use crate::{Allocator, Factor, Heap, Name, Names};
+use crate::{
+    Allocator, Factor, Heap, Length, Metadata, Name, Names, Tempo, Time, TimeSignature, Velocity,
+    Voice,
+};
 
+use super::cst::{Child, Kind};
 use super::{Error, Parser};
 
 fn r(n: i128, d: i128) -> BigRational {
@@ -137,6 +143,531 @@ fn sharps() {
     check_ok(names, expected, source);
 }
 
+#[test]
+fn sharps_in_names() {
+    let source = r#"it = xs#4, xs#"#;
+    let s = span_in(source);
+
+    let mut names = Names::new();
+    let mut name = |name| names.make(name);
+
+    let first = Melody::Name(s(5, 9), name("xs#4"));
+    let second = Melody::Name(s(11, 13), name("xs"));
+    let second = Melody::Sharp(s(13, 14), 1, Box::new(second));
+    let sequence = Melody::Sequence(vec![first, second]);
+    let expected = HashMap::from([(name("it"), Box::new(sequence))]);
+
+    check_ok(names, expected, source);
+}
+
+#[test]
+fn transpositions() {
+    let source = r#"it = a^-3, b^m3, c#^+P5"#;
+    let s = span_in(source);
+
+    let mut names = Names::new();
+    let mut name = |name| names.make(name);
+
+    let a = Melody::Note(s(5, 6), 'a');
+    let a = Melody::Sharp(s(6, 9), -3, Box::new(a));
+    let b = Melody::Note(s(11, 12), 'b');
+    let b = Melody::Sharp(s(12, 15), 3, Box::new(b));
+    let c = Melody::Note(s(17, 18), 'c');
+    let c = Melody::Sharp(s(18, 19), 1, Box::new(c));
+    let c = Melody::Sharp(s(19, 23), 7, Box::new(c));
+
+    let sequence = Melody::Sequence(vec![a, b, c]);
+    let expected = HashMap::from([(name("it"), Box::new(sequence))]);
+
+    check_ok(names, expected, source);
+}
+
+#[test]
+fn unknown_interval() {
+    let source = r#"it = a^-x3"#;
+    let s = span_in(source);
+
+    let expected = vec![Error::ExpectedInterval(s(6, 10))];
+    check_err(expected, source);
+}
+
+#[test]
+fn repeats() {
+    let source = r#"it = ab * 4, a@p * oo"#;
+    let s = span_in(source);
+
+    let mut names = Names::new();
+    let mut name = |name| names.make(name);
+
+    let ab = Melody::Name(s(5, 7), name("ab"));
+    let ab = Melody::Repeat(s(8, 11), Some(4), Box::new(ab));
+    let a = Melody::Note(s(13, 14), 'a');
+    let a = Melody::Dynamic(s(14, 16), Velocity::from_marking("p").unwrap(), Box::new(a));
+    let a = Melody::Repeat(s(17, 21), None, Box::new(a));
+
+    let sequence = Melody::Sequence(vec![ab, a]);
+    let expected = HashMap::from([(name("it"), Box::new(sequence))]);
+
+    check_ok(names, expected, source);
+}
+
+#[test]
+fn unknown_count() {
+    let source = r#"it = a * xs"#;
+    let s = span_in(source);
+
+    let expected = vec![Error::ExpectedCount(s(9, 11))];
+    check_err(expected, source);
+}
+
+#[test]
+fn reverse() {
+    let source = r#"it = ~(a, b), 2 ~c"#;
+    let s = span_in(source);
+
+    let mut names = Names::new();
+    let mut name = |name| names.make(name);
+
+    let a = Melody::Note(s(7, 8), 'a');
+    let b = Melody::Note(s(10, 11), 'b');
+    let first = Melody::Sequence(vec![a, b]);
+    let first = Melody::Reverse(s(5, 6), Box::new(first));
+
+    let c = Melody::Note(s(17, 18), 'c');
+    let second = Melody::Reverse(s(16, 17), Box::new(c));
+    let second = Melody::Scale(s(14, 15), Factor(r(2, 1)), Box::new(second));
+
+    let sequence = Melody::Sequence(vec![first, second]);
+    let expected = HashMap::from([(name("it"), Box::new(sequence))]);
+
+    check_ok(names, expected, source);
+}
+
+#[test]
+fn inversion() {
+    let source = r#"it = inv(a) (b, c)"#;
+    let s = span_in(source);
+
+    let mut names = Names::new();
+    let mut name = |name| names.make(name);
+
+    let b = Melody::Note(s(13, 14), 'b');
+    let c = Melody::Note(s(16, 17), 'c');
+    let sequence = Melody::Sequence(vec![b, c]);
+    let inverted = Melody::Invert(s(5, 11), 'a', Box::new(sequence));
+    let expected = HashMap::from([(name("it"), Box::new(inverted))]);
+
+    check_ok(names, expected, source);
+}
+
+#[test]
+fn inversion_without_axis() {
+    let source = r#"it = inv(xs) a"#;
+    let s = span_in(source);
+
+    let expected = vec![Error::ExpectedNote(s(9, 11))];
+    check_err(expected, source);
+}
+
+#[test]
+fn keys() {
+    let source = r#"it = in D dorian (1, 3#, 2 5^^-1)"#;
+    let s = span_in(source);
+
+    let mut names = Names::new();
+    let mut name = |name| names.make(name);
+
+    let first = Melody::Degree(s(18, 19), 1);
+    let second = Melody::Degree(s(21, 22), 3);
+    let second = Melody::Sharp(s(22, 23), 1, Box::new(second));
+    let third = Melody::Degree(s(27, 28), 5);
+    let third = Melody::Scale(s(25, 26), Factor(r(2, 1)), Box::new(third));
+    let third = Melody::Steps(s(28, 32), -1, Box::new(third));
+
+    let sequence = Melody::Sequence(vec![first, second, third]);
+    let key = Key {
+        tonic: 'D',
+        mode: Mode::Dorian,
+    };
+
+    let melody = Melody::Key(s(5, 16), key, Box::new(sequence));
+    let expected = HashMap::from([(name("it"), Box::new(melody))]);
+
+    check_ok(names, expected, source);
+}
+
+#[test]
+fn fractional_degree() {
+    let source = r#"it = 1/2, ab"#;
+    let s = span_in(source);
+
+    let expected = vec![Error::ExpectedDegree(s(5, 8))];
+    check_err(expected, source);
+}
+
+#[test]
+fn degree_before_definition() {
+    let source = "mel = 1, 3, 5\nbass = C";
+    let s = span_in(source);
+
+    let mut names = Names::new();
+    let mut name = |name| names.make(name);
+
+    let degrees = vec![
+        Melody::Degree(s(6, 7), 1),
+        Melody::Degree(s(9, 10), 3),
+        Melody::Degree(s(12, 13), 5),
+    ];
+
+    let expected = HashMap::from([
+        (name("mel"), Box::new(Melody::Sequence(degrees))),
+        (name("bass"), Box::new(Melody::Note(s(21, 22), 'C'))),
+    ]);
+
+    check_ok(names, expected, source);
+}
+
+#[test]
+fn unknown_mode() {
+    let source = r#"it = in C blues 1"#;
+    let s = span_in(source);
+
+    let expected = vec![Error::ExpectedMode(s(10, 15))];
+    check_err(expected, source);
+}
+
+#[test]
+fn chords() {
+    let source = r#"it = Cmaj7, Dm/F"#;
+    let s = span_in(source);
+
+    let mut names = Names::new();
+    let mut name = |name| names.make(name);
+
+    let first = (0..4).map(|_| Melody::Note(s(5, 10), 'C')).collect();
+    let second = vec![
+        Melody::Note(s(12, 16), 'F'),
+        Melody::Note(s(12, 16), 'D'),
+        Melody::Note(s(12, 16), 'D'),
+        Melody::Note(s(12, 16), 'D'),
+    ];
+
+    let sequence = Melody::Sequence(vec![Melody::Stack(first), Melody::Stack(second)]);
+    let expected = HashMap::from([(name("it"), Box::new(sequence))]);
+
+    check_ok(names, expected, source);
+}
+
+#[test]
+fn chord_like_names() {
+    let source = "it = F#maj8, Emily\nCmotif = Amelody";
+    let s = span_in(source);
+
+    let mut names = Names::new();
+    let mut name = |name| names.make(name);
+
+    let sequence = Melody::Sequence(vec![
+        Melody::Name(s(5, 11), name("F#maj8")),
+        Melody::Name(s(13, 18), name("Emily")),
+    ]);
+
+    let expected = HashMap::from([
+        (name("it"), Box::new(sequence)),
+        (
+            name("Cmotif"),
+            Box::new(Melody::Name(s(28, 35), name("Amelody"))),
+        ),
+    ]);
+
+    check_ok(names, expected, source);
+}
+
+#[test]
+fn unknown_bass() {
+    let source = r#"it = Dm/xs"#;
+    let s = span_in(source);
+
+    let expected = vec![Error::ExpectedNote(s(8, 10))];
+    check_err(expected, source);
+}
+
+/// A note which may be given an octave, like `C6`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Octave(&'static str);
+
+impl Note for Octave {
+    fn parse(name: &str) -> Option<Self> {
+        ["C", "C6"].into_iter().find(|note| *note == name).map(Self)
+    }
+
+    fn add_sharp(&self, _: isize) -> Option<Self> {
+        Some(self.clone())
+    }
+
+    fn add_octave(&self, _: isize) -> Option<Self> {
+        Some(self.clone())
+    }
+
+    fn invert(&self, _: &Self) -> Option<Self> {
+        Some(self.clone())
+    }
+}
+
+#[test]
+fn octaves_before_chords() {
+    let source = r#"it = C6, Cadd6"#;
+    let s = span_in(source);
+
+    let mut names = Names::new();
+    let it = names.make("it");
+
+    let chord = (0..4)
+        .map(|_| Melody::Note(s(9, 14), Octave("C")))
+        .collect();
+    let sequence = Melody::Sequence(vec![
+        Melody::Note(s(5, 7), Octave("C6")),
+        Melody::Stack(chord),
+    ]);
+
+    let actual = Parser::parse(&mut Heap, &mut names, source, source).map(|program| program.defs);
+    assert_eq!(Ok(HashMap::from([(it, Box::new(sequence))])), actual);
+}
+
+#[test]
+fn tempos() {
+    let source = "tempo 96\nit = tempo(80..100) ab, tempo(..60) ab, tempo(72) ab";
+    let s = span_in(source);
+    let bpm = |bpm: i32| Tempo::from_bpm(BigInt::from(bpm)).unwrap();
+
+    let mut names = Names::new();
+    let program: Program<char, &str, _> =
+        Parser::parse(&mut Heap, &mut names, source, source).unwrap();
+
+    let it = names.make("it");
+    let ab = names.make("ab");
+    let ab = |start| Box::new(Melody::Name(s(start, start + 2), ab));
+
+    let first = Melody::Tempo(s(14, 28), Some(bpm(80)), bpm(100), ab(29));
+    let second = Melody::Tempo(s(33, 44), None, bpm(60), ab(45));
+    let third = Melody::Tempo(s(49, 58), Some(bpm(72)), bpm(72), ab(59));
+    let sequence = Melody::Sequence(vec![first, second, third]);
+
+    assert_eq!(Some(bpm(96)), program.tempo);
+    assert_eq!(HashMap::from([(it, Box::new(sequence))]), program.defs);
+}
+
+#[test]
+fn invalid_tempos() {
+    let source = r#"it = tempo(0) ab, tempo() ab, tempo(1..0) ab"#;
+    let s = span_in(source);
+
+    let expected = vec![
+        Error::ExpectedTempo(s(11, 12)),
+        Error::ExpectedTempo(s(24, 25)),
+        Error::ExpectedTempo(s(39, 40)),
+    ];
+
+    check_err(expected, source);
+}
+
+#[test]
+fn voices() {
+    let source = "bass@ch2 = instrument(33) ab, xs@ch10";
+    let s = span_in(source);
+
+    let mut names = Names::new();
+    let mut name = |name| names.make(name);
+
+    let ab = Box::new(Melody::Name(s(26, 28), name("ab")));
+    let xs = Box::new(Melody::Name(s(30, 32), name("xs")));
+
+    let instrument = Voice::instrument(33).unwrap();
+    let drums = Voice::channel(10).unwrap();
+    let sequence = Melody::Sequence(vec![
+        Melody::Voice(s(11, 25), instrument, ab),
+        Melody::Voice(s(32, 37), drums, xs),
+    ]);
+
+    let bass = Melody::Voice(s(4, 8), Voice::channel(2).unwrap(), Box::new(sequence));
+    let expected = HashMap::from([(name("bass"), Box::new(bass))]);
+
+    check_ok(names, expected, source);
+}
+
+#[test]
+fn invalid_voices() {
+    let source = r#"it = instrument(0) ab, ab@ch17, instrument ab"#;
+    let s = span_in(source);
+
+    let expected = vec![
+        Error::ExpectedInstrument(s(16, 17)),
+        Error::ExpectedChannel(s(26, 30)),
+        Error::ExpectedInstrument(s(43, 45)),
+    ];
+
+    check_err(expected, source);
+}
+
+#[test]
+fn choices() {
+    let source = "it = {ab, 2 xs | 3: xs}";
+    let s = span_in(source);
+
+    let mut names = Names::new();
+    let mut name = |name| names.make(name);
+
+    let ab = Melody::Name(s(6, 8), name("ab"));
+    let xs = Melody::Name(s(12, 14), name("xs"));
+    let scaled = Melody::Scale(s(10, 11), Factor(r(2, 1)), Box::new(xs));
+    let first = Melody::Sequence(vec![ab, scaled]);
+    let second = Melody::Name(s(20, 22), name("xs"));
+
+    let choice = Melody::Choice(s(5, 23), vec![1, 3], vec![first, second]);
+    let expected = HashMap::from([(name("it"), Box::new(choice))]);
+
+    check_ok(names, expected, source);
+}
+
+#[test]
+fn invalid_choices() {
+    let source = r#"it = {0: ab | 1/2: ab | ab"#;
+    let s = span_in(source);
+
+    let expected = vec![
+        Error::ExpectedWeight(s(6, 7)),
+        Error::ExpectedWeight(s(14, 17)),
+        Error::UnclosedBrace {
+            opener: s(5, 6),
+            at: s(24, 26),
+        },
+    ];
+
+    check_err(expected, source);
+}
+
+#[test]
+fn slices() {
+    let source = "it = xs*oo[4..12], ab[..1/2]";
+    let s = span_in(source);
+
+    let mut names = Names::new();
+    let mut name = |name| names.make(name);
+
+    let xs = Box::new(Melody::Name(s(5, 7), name("xs")));
+    let repeated = Box::new(Melody::Repeat(s(7, 10), None, xs));
+    let first = Melody::Slice(s(10, 17), Time(r(4, 1)), Time(r(12, 1)), repeated);
+
+    let ab = Box::new(Melody::Name(s(19, 21), name("ab")));
+    let second = Melody::Slice(s(21, 28), Time(r(0, 1)), Time(r(1, 2)), ab);
+
+    let sequence = Melody::Sequence(vec![first, second]);
+    let expected = HashMap::from([(name("it"), Box::new(sequence))]);
+
+    check_ok(names, expected, source);
+}
+
+#[test]
+fn invalid_slices() {
+    let source = r#"it = ab[2..1], ab[3], ab[1..2"#;
+    let s = span_in(source);
+
+    let expected = vec![
+        Error::ExpectedSlice(s(11, 12)),
+        Error::ExpectedSlice(s(19, 20)),
+        Error::UnclosedBracket {
+            opener: s(24, 25),
+            at: s(28, 29),
+        },
+    ];
+
+    check_err(expected, source);
+}
+
+#[test]
+fn fits() {
+    let source = "it = (ab, xs) : 3, ab*2 : 1/2";
+    let s = span_in(source);
+
+    let mut names = Names::new();
+    let mut name = |name| names.make(name);
+
+    let ab = Melody::Name(s(6, 8), name("ab"));
+    let xs = Melody::Name(s(10, 12), name("xs"));
+    let inner = Box::new(Melody::Sequence(vec![ab, xs]));
+    let first = Melody::Fit(s(14, 17), Time(r(3, 1)), inner);
+
+    let ab = Box::new(Melody::Name(s(19, 21), name("ab")));
+    let repeated = Box::new(Melody::Repeat(s(21, 23), Some(2), ab));
+    let second = Melody::Fit(s(24, 29), Time(r(1, 2)), repeated);
+
+    let sequence = Melody::Sequence(vec![first, second]);
+    let expected = HashMap::from([(name("it"), Box::new(sequence))]);
+
+    check_ok(names, expected, source);
+}
+
+#[test]
+fn invalid_fits() {
+    let source = "it = ab : 0, ab :";
+    let s = span_in(source);
+
+    let expected = vec![
+        Error::ExpectedLength(s(10, 11)),
+        Error::ExpectedLength(s(16, 17)),
+    ];
+
+    check_err(expected, source);
+}
+
+#[test]
+fn bars() {
+    let source = "it: 2 bars = ab\nxs! : 1 bar = it\ntime 3/4";
+    let s = span_in(source);
+
+    let mut names = Names::new();
+    let program: Program<char, &str, _> =
+        Parser::parse(&mut Heap, &mut names, source, source).unwrap();
+
+    let it = names.make("it");
+    let xs = names.make("xs");
+
+    let expected = HashMap::from([
+        (
+            it,
+            Bars {
+                count: 2,
+                length: Length::Bounded(r(6, 1)),
+                span: s(2, 10),
+            },
+        ),
+        (
+            xs,
+            Bars {
+                count: 1,
+                length: Length::Bounded(r(3, 1)),
+                span: s(20, 27),
+            },
+        ),
+    ]);
+
+    assert_eq!(TimeSignature::new(3, 4), program.time);
+    assert_eq!(expected, program.bars);
+}
+
+#[test]
+fn invalid_bars() {
+    let source = "it: 2 beats = ab\nxs: bars = ab\ntime 3/5";
+    let s = span_in(source);
+
+    let expected = vec![
+        Error::ExpectedBars(s(6, 11)),
+        Error::ExpectedBars(s(21, 25)),
+        Error::ExpectedTimeSignature(s(36, 39)),
+    ];
+
+    check_err(expected, source);
+}
+
 #[test]
 fn offsets() {
     let source = r#"it = (a+1)-1"#;
@@ -153,6 +684,111 @@ fn offsets() {
     check_ok(names, expected, source);
 }
 
+#[test]
+fn dynamics() {
+    let source = r#"it = (a@ff, b)@40"#;
+    let s = span_in(source);
+
+    let mut names = Names::new();
+    let mut name = |name| names.make(name);
+
+    let a = Melody::Note(s(6, 7), 'a');
+    let b = Melody::Note(s(12, 13), 'b');
+    let loud = Melody::Dynamic(s(7, 10), Velocity(r(112, 100)), Box::new(a));
+    let sequence = Melody::Sequence(vec![loud, b]);
+    let outer = Melody::Dynamic(s(14, 17), Velocity(r(40, 100)), Box::new(sequence));
+    let expected = HashMap::from([(name("it"), Box::new(outer))]);
+
+    check_ok(names, expected, source);
+}
+
+#[test]
+fn unknown_dynamic() {
+    let source = r#"it = a@loud"#;
+    let s = span_in(source);
+
+    let expected = vec![Error::ExpectedDynamic(s(7, 11))];
+    check_err(expected, source);
+}
+
+#[test]
+fn dynamic_levels() {
+    let source = r#"it = a@0, a@1, a@127, a@128, a@300"#;
+    let s = span_in(source);
+
+    let expected = vec![
+        Error::ExpectedDynamic(s(7, 8)),
+        Error::ExpectedDynamic(s(24, 27)),
+        Error::ExpectedDynamic(s(31, 34)),
+    ];
+
+    check_err(expected, source);
+}
+
+#[test]
+fn imports() {
+    let source = "import \"drums.mms\"\nit = kick";
+    let s = span_in(source);
+
+    let mut alloc = Heap;
+    let program: Program<char, _, _> =
+        Parser::parse(&mut alloc, &mut Names::new(), source, source).unwrap();
+
+    let expected = vec![Import {
+        path: "drums.mms".into(),
+        span: s(0, 18),
+    }];
+
+    assert_eq!(expected, program.imports);
+}
+
+#[test]
+fn missing_import_path() {
+    let source = "import it = A";
+    let s = span_in(source);
+
+    let expected = vec![Error::ExpectedPath(s(7, 9))];
+    check_err(expected, source);
+}
+
+#[test]
+fn parameters() {
+    let source = "it = arp(a, b)\narp(xx, yy) = xx, yy";
+    let s = span_in(source);
+
+    let mut names = Names::new();
+    let mut name = |name| names.make(name);
+
+    let a = Melody::Note(s(9, 10), 'a');
+    let b = Melody::Note(s(12, 13), 'b');
+    let call = Melody::Call(s(5, 14), name("arp"), vec![a, b]);
+
+    let xx = Melody::Parameter(s(29, 31), name("xx"));
+    let yy = Melody::Parameter(s(33, 35), name("yy"));
+    let arp = Melody::Sequence(vec![xx, yy]);
+
+    let expected_defs = HashMap::from([(name("it"), Box::new(call)), (name("arp"), Box::new(arp))]);
+    let expected_params = HashMap::from([(name("arp"), vec![name("xx"), name("yy")])]);
+
+    let actual = Parser::parse(&mut Heap, &mut names, source, source)
+        .map(|program| (program.defs, program.params));
+
+    assert_eq!(Ok((expected_defs, expected_params)), actual);
+}
+
+#[test]
+fn duplicate_parameters() {
+    let source = r#"arp(xx, xx) = xx"#;
+    let s = span_in(source);
+
+    let expected = vec![Error::Redefinition {
+        previous: s(4, 6),
+        new: s(8, 10),
+    }];
+
+    check_err(expected, source);
+}
+
 #[test]
 fn expected_equal() {
     let source = r#"aa bb = A"#;
@@ -211,6 +847,65 @@ fn odd_parens() {
     check_err(expected, source);
 }
 
+#[test]
+fn metadata() {
+    let source = "title \"Gymnopédie\"\ncomposer \"Erik Satie\"\ntitle = ab";
+
+    let mut names = Names::new();
+    let program: Program<char, &str, _> =
+        Parser::parse(&mut Heap, &mut names, source, source).unwrap();
+
+    let expected = Metadata {
+        title: Some("Gymnopédie".into()),
+        composer: Some("Erik Satie".into()),
+        ..Metadata::default()
+    };
+
+    // Metadata fields are only special when followed by a string.
+    assert_eq!(expected, program.metadata);
+    assert!(program.defs.contains_key(&names.make("title")));
+}
+
+#[test]
+fn invalid_metadata() {
+    let source = r#"genre "waltz" title "a" title "b""#;
+    let s = span_in(source);
+
+    let expected = vec![
+        Error::UnknownMetadata(s(0, 5)),
+        Error::Redefinition {
+            previous: s(14, 23),
+            new: s(24, 33),
+        },
+    ];
+
+    check_err(expected, source);
+}
+
+#[test]
+fn docs() {
+    let source = "-- not documentation
+--- Thirds,
+---   rising.
+three = ab, -- nor this
+--- stray
+  xs
+---- Fives
+five = xs
+six = ab";
+
+    let mut names = Names::new();
+    let program: Program<char, &str, _> =
+        Parser::parse(&mut Heap, &mut names, source, source).unwrap();
+
+    let expected = HashMap::from([
+        (names.make("three"), "Thirds,\nrising.".to_string()),
+        (names.make("five"), "Fives".to_string()),
+    ]);
+
+    assert_eq!(expected, program.docs);
+}
+
 #[test]
 fn redefinition() {
     let source = r#"it = a it = b"#;
@@ -223,3 +918,237 @@ fn redefinition() {
 
     check_err(expected, source);
 }
+
+#[test]
+fn locals() {
+    let source = "mel(xs) = fst, fst where fst = xs, ab; ab = xs\nab = yy";
+    let s = span_in(source);
+
+    let mut names = Names::new();
+    let program: Program<char, &str, _> =
+        Parser::parse(&mut Heap, &mut names, source, source).unwrap();
+
+    // Local definitions are hoisted to fresh names, which are only known by
+    // the span of their definition.
+    let local = |span| *program.spans.iter().find(|(_, s)| **s == span).unwrap().0;
+    let fst = local(s(25, 28));
+    let ab = local(s(39, 41));
+    let xs = names.make("xs");
+
+    let call = |span, name| Melody::Call(span, name, vec![Melody::Parameter(span, xs)]);
+
+    let expected = HashMap::from([
+        (
+            names.make("mel"),
+            Box::new(Melody::Sequence(vec![
+                call(s(10, 13), fst),
+                call(s(15, 18), fst),
+            ])),
+        ),
+        (
+            fst,
+            Box::new(Melody::Sequence(vec![
+                Melody::Parameter(s(31, 33), xs),
+                call(s(35, 37), ab),
+            ])),
+        ),
+        (ab, Box::new(Melody::Parameter(s(44, 46), xs))),
+        (
+            names.make("ab"),
+            Box::new(Melody::Name(s(52, 54), names.make("yy"))),
+        ),
+    ]);
+
+    let expected_params = HashMap::from([
+        (names.make("mel"), vec![xs]),
+        (fst, vec![xs]),
+        (ab, vec![xs]),
+    ]);
+
+    assert_eq!(expected, program.defs);
+    assert_eq!(expected_params, program.params);
+    assert!(program.public.is_empty());
+}
+
+#[test]
+fn nested_locals() {
+    let source = "mel = fst(ab) where fst(xs) = xs, snd where snd = xs";
+    let s = span_in(source);
+
+    let mut names = Names::new();
+    let program: Program<char, &str, _> =
+        Parser::parse(&mut Heap, &mut names, source, source).unwrap();
+
+    let local = |span| *program.spans.iter().find(|(_, s)| **s == span).unwrap().0;
+    let fst = local(s(20, 23));
+    let snd = local(s(44, 47));
+    let xs = names.make("xs");
+
+    let expected = HashMap::from([
+        (
+            names.make("mel"),
+            Box::new(Melody::Call(
+                s(6, 13),
+                fst,
+                vec![Melody::Name(s(10, 12), names.make("ab"))],
+            )),
+        ),
+        (
+            fst,
+            Box::new(Melody::Sequence(vec![
+                Melody::Parameter(s(30, 32), xs),
+                Melody::Call(s(34, 37), snd, vec![Melody::Parameter(s(34, 37), xs)]),
+            ])),
+        ),
+        (snd, Box::new(Melody::Parameter(s(50, 52), xs))),
+    ]);
+
+    assert_eq!(expected, program.defs);
+    assert_eq!(Some(&vec![xs]), program.params.get(&snd));
+}
+
+#[test]
+fn invalid_locals() {
+    let source = "mel(xs) = ab where xs = ab; ab(xs) = ab; ab = ab";
+    let s = span_in(source);
+
+    let expected = vec![
+        Error::ShadowedParameter {
+            parameter: s(4, 6),
+            new: s(19, 21),
+        },
+        Error::ShadowedParameter {
+            parameter: s(4, 6),
+            new: s(31, 33),
+        },
+        Error::Redefinition {
+            previous: s(28, 30),
+            new: s(41, 43),
+        },
+    ];
+
+    check_err(expected, source);
+}
+
+#[test]
+fn syntax_tree() {
+    let source = "-- lossless\ntempo 90\n\nit(xs) = (xs ,xs)|{ab} where\n  ab = arp(xs) ? -- odd\n";
+
+    let (parsed, tree): (Result<Program<char, &str, _>, _>, _) =
+        Parser::parse_tree(&mut Heap, &mut Names::new(), source, source);
+
+    // Unrecognised characters are reported, but kept in the tree.
+    let unexpected = source.find('?').unwrap();
+    let s = span_in(source);
+    assert_eq!(
+        Some(vec![Error::UnexpectedCharacter(s(
+            unexpected,
+            unexpected + 1
+        ))]),
+        parsed.err()
+    );
+    assert_eq!(source, tree.to_string());
+
+    let kinds: Vec<_> = tree
+        .root
+        .children
+        .iter()
+        .map(|child| match child {
+            Child::Node(node) => node.kind,
+            Child::Token(_) => Kind::Error,
+        })
+        .collect();
+
+    assert_eq!(vec![Kind::Tempo, Kind::Definition], kinds);
+
+    let Child::Node(definition) = &tree.root.children[1] else {
+        unreachable!();
+    };
+
+    let kinds: Vec<_> = definition
+        .children
+        .iter()
+        .filter_map(|child| match child {
+            Child::Node(node) => Some(node.kind),
+            Child::Token(_) => None,
+        })
+        .collect();
+
+    assert_eq!(
+        vec![Kind::Parameters, Kind::Group, Kind::Choice, Kind::Where],
+        kinds
+    );
+}
+
+#[test]
+fn unexpected_characters() {
+    let source = "it = ab, && cd\nxy = ef";
+    let s = span_in(source);
+
+    let expected = vec![Error::UnexpectedCharacter(s(9, 11))];
+    check_err(expected, source);
+}
+
+#[test]
+fn unexpected_tokens() {
+    let source = "it = ab)\nxy = cd";
+    let s = span_in(source);
+
+    let expected = vec![Error::UnexpectedToken(s(7, 8))];
+    check_err(expected, source);
+
+    // Names which do not start a definition are skipped as well.
+    let source = "it = ab cd\nxy = cd";
+    let s = span_in(source);
+
+    let expected = vec![Error::UnexpectedToken(s(8, 10))];
+    check_err(expected, source);
+}
+
+#[test]
+fn synchronisation() {
+    // Skipping stops at the next line starting with a definition, rather
+    // than at the next definition.
+    let source = "it = ab\n) ) xy = cd\nzz = ab";
+    let s = span_in(source);
+
+    let expected = vec![Error::UnexpectedToken(s(8, 9))];
+    check_err(expected, source);
+
+    let source = "it ab, cd\nxy = (ef\nzz = gh, ]\npq = ab";
+    let s = span_in(source);
+
+    let expected = vec![
+        Error::ExpectedEqual(s(3, 5)),
+        Error::UnclosedParen {
+            opener: s(15, 16),
+            at: s(19, 21),
+        },
+        Error::ExpectedNote(s(28, 29)),
+    ];
+
+    check_err(expected, source);
+}
+
+#[test]
+fn overflows() {
+    let source = "it = A+99999999999999999999, A^^-99999999999999999999, A^99999999999999999999, \
+                  A*99999999999999999999, {99999999999999999999: A}, 99999999999999999999";
+    let s = span_in(source);
+    // Parts are found along with what follows them, but spans end before it.
+    let at = |part: &str| {
+        let start = source.find(part).unwrap();
+        s(start, start + part.trim_end_matches([',', ':']).len())
+    };
+
+    let expected = vec![
+        Error::Overflow(at("+99999999999999999999")),
+        Error::Overflow(at("^^-99999999999999999999")),
+        Error::Overflow(at("^99999999999999999999,")),
+        Error::Overflow(at("*99999999999999999999")),
+        Error::Overflow(at("99999999999999999999:")),
+        Error::Overflow(s(source.len() - 20, source.len())),
+    ];
+
+    check_err(expected, source);
+}
@@ -0,0 +1,769 @@
+//! Printing of programs back to mm source, such that parsing the printed
+//! source gives the same program again, apart from its spans.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write};
+
+use num_rational::BigRational;
+
+use crate::implicit::{self, Import};
+use crate::melody::{self, Node};
+use crate::span::Span;
+use crate::{
+    Allocator, Factor, Metadata, Name, Names, Tempo, Time, TimeSignature, Velocity, Voice,
+};
+
+/// A program which displays as mm source, made with
+/// [`implicit::Program::display`] or [`melody::Program::display`].
+///
+/// Melodies are parenthesised only where the precedence of stacks over
+/// sequences over everything else requires it. Definitions which are spelled
+/// the same, like those of different sources after linking, are told apart by
+/// primes. Values which cannot be written, like fractional tempos, are rounded.
+pub struct Source<'a, P> {
+    program: &'a P,
+    names: &'a Names,
+}
+
+impl<N, Id, A: Allocator<implicit::Melody<N, Id, A>>> implicit::Program<N, Id, A> {
+    pub fn display<'a>(&'a self, names: &'a Names) -> Source<'a, Self> {
+        Source {
+            program: self,
+            names,
+        }
+    }
+}
+
+impl<N, Id, A: Allocator<melody::Melody<N, Id, A>>> melody::Program<N, Id, A> {
+    pub fn display<'a>(&'a self, names: &'a Names) -> Source<'a, Self> {
+        Source {
+            program: self,
+            names,
+        }
+    }
+}
+
+impl<N, Id, A> fmt::Display for Source<'_, implicit::Program<N, Id, A>>
+where
+    N: fmt::Display,
+    A: Allocator<implicit::Melody<N, Id, A>>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let program = self.program;
+        let spellings = Spellings::new(self.names, program.defs.keys(), &program.spans);
+
+        let header = Header {
+            imports: &program.imports,
+            metadata: &program.metadata,
+            tempo: program.tempo.as_ref(),
+            time: program.time,
+        };
+
+        let defs = spellings.order.iter().map(|name| Definition {
+            name: spellings.get(name),
+            params: program
+                .params
+                .get(name)
+                .map(|params| params.iter().map(|param| spellings.get(param)).collect())
+                .unwrap_or_default(),
+            is_public: program.public.contains(name),
+            bars: program.bars.get(name).map(|bars| bars.count),
+            doc: program.docs.get(name).map(String::as_str),
+            body: from_implicit(A::as_ref(&program.defs[name]), &spellings),
+        });
+
+        write_program(f, header, defs)
+    }
+}
+
+impl<N, Id, A> fmt::Display for Source<'_, melody::Program<N, Id, A>>
+where
+    N: fmt::Display,
+    A: Allocator<melody::Melody<N, Id, A>>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let program = self.program;
+        let spellings = Spellings::new(self.names, program.defs.keys(), &program.spans);
+
+        // Checked programs only number their parameters, so definitions are
+        // given as many parameters as they use or are called with.
+        let mut arities = HashMap::new();
+        let bodies: Vec<_> = spellings
+            .order
+            .iter()
+            .map(|name| {
+                let mut params = 0;
+                let body = A::as_ref(&program.defs[name]);
+                let body = from_melody(body, &spellings, &mut params, &mut arities);
+
+                let arity = arities.entry(*name).or_insert(0);
+                *arity = params.max(*arity);
+                body
+            })
+            .collect();
+
+        let header = Header::<Id> {
+            imports: &[],
+            metadata: &program.metadata,
+            tempo: program.tempo.as_ref(),
+            time: program.time,
+        };
+
+        let defs = spellings
+            .order
+            .iter()
+            .zip(bodies)
+            .map(|(name, body)| Definition {
+                name: spellings.get(name),
+                params: (0..arities[name])
+                    .map(|index| spellings.parameter(index))
+                    .collect(),
+                is_public: program.public.contains(name),
+                bars: None,
+                doc: program.docs.get(name).map(String::as_str),
+                body,
+            });
+
+        write_program(f, header, defs)
+    }
+}
+
+/// How the names of a program are written.
+struct Spellings<'a> {
+    names: &'a Names,
+    /// The definitions, in the order they are written in.
+    order: Vec<Name>,
+    /// The spellings of the definitions which would otherwise be ambiguous
+    /// with those before them.
+    primed: HashMap<Name, String>,
+    taken: HashSet<String>,
+}
+
+impl<'a> Spellings<'a> {
+    fn new<'b, Id>(
+        names: &'a Names,
+        defs: impl Iterator<Item = &'b Name>,
+        spans: &HashMap<Name, Span<Id>>,
+    ) -> Self {
+        let mut order: Vec<_> = defs.copied().collect();
+        order.sort_by_key(|name| (spans.get(name).map(|span| span.start), names.get(name)));
+
+        let mut taken: HashSet<String> =
+            order.iter().map(|name| writable(names.get(name))).collect();
+        let mut seen = HashSet::new();
+        let mut primed = HashMap::new();
+
+        for name in order.iter() {
+            let spelling = writable(names.get(name));
+            if !seen.insert(spelling.clone()) {
+                let unique = unique(&spelling, &taken);
+                taken.insert(unique.clone());
+                primed.insert(*name, unique);
+            }
+        }
+
+        Self {
+            names,
+            order,
+            primed,
+            taken,
+        }
+    }
+
+    fn get(&self, name: &Name) -> String {
+        match self.primed.get(name) {
+            Some(spelling) => spelling.clone(),
+            None => writable(self.names.get(name)),
+        }
+    }
+
+    /// Get the name of the parameter with the given index in a checked
+    /// program.
+    fn parameter(&self, index: usize) -> String {
+        unique(&format!("x{}", index + 1), &self.taken)
+    }
+}
+
+/// Make a spelling which can be written as a name, such as those of the
+/// instances of parameterized definitions like `xy(1, 1/2)`, which become
+/// `xy_1_1_2`.
+fn writable(spelling: &str) -> String {
+    let mut writable = String::with_capacity(spelling.len());
+
+    for c in spelling.chars() {
+        if c.is_alphanumeric() || c == '\'' {
+            writable.push(c);
+        } else if !writable.is_empty() && !writable.ends_with('_') {
+            writable.push('_');
+        }
+    }
+
+    writable.trim_end_matches('_').into()
+}
+
+/// Add primes to `spelling` until it is none of the `taken` spellings.
+fn unique(spelling: &str, taken: &HashSet<String>) -> String {
+    let mut unique = spelling.to_string();
+    while taken.contains(&unique) {
+        unique.push('\'');
+    }
+
+    unique
+}
+
+struct Header<'a, Id> {
+    imports: &'a [Import<Id>],
+    metadata: &'a Metadata,
+    tempo: Option<&'a Tempo>,
+    time: Option<TimeSignature>,
+}
+
+struct Definition<'a> {
+    name: String,
+    params: Vec<String>,
+    is_public: bool,
+    bars: Option<usize>,
+    doc: Option<&'a str>,
+    body: Written,
+}
+
+fn write_program<'a, Id>(
+    f: &mut fmt::Formatter<'_>,
+    header: Header<'_, Id>,
+    defs: impl Iterator<Item = Definition<'a>>,
+) -> fmt::Result {
+    let mut has_header = false;
+
+    for import in header.imports {
+        writeln!(f, "import \"{}\"", import.path)?;
+        has_header = true;
+    }
+
+    let Metadata {
+        title,
+        composer,
+        copyright,
+        description,
+    } = header.metadata;
+
+    let fields = [
+        ("title", title),
+        ("composer", composer),
+        ("copyright", copyright),
+        ("description", description),
+    ];
+
+    for (field, value) in fields {
+        if let Some(value) = value {
+            writeln!(f, "{field} \"{value}\"")?;
+            has_header = true;
+        }
+    }
+
+    if let Some(tempo) = header.tempo {
+        writeln!(f, "tempo {}", integer(&tempo.0))?;
+        has_header = true;
+    }
+
+    if let Some(time) = header.time {
+        writeln!(f, "time {time}")?;
+        has_header = true;
+    }
+
+    for (index, def) in defs.enumerate() {
+        if index == 0 && has_header {
+            writeln!(f)?;
+        }
+
+        if let Some(doc) = def.doc {
+            for line in doc.lines() {
+                writeln!(f, "--- {line}")?;
+            }
+        }
+
+        write!(f, "{}", def.name)?;
+
+        if !def.params.is_empty() {
+            write!(f, "({})", def.params.join(", "))?;
+        }
+
+        if def.is_public {
+            write!(f, "!")?;
+        }
+
+        if let Some(count) = def.bars {
+            let unit = if count == 1 { "bar" } else { "bars" };
+            write!(f, " : {count} {unit}")?;
+        }
+
+        write!(f, " = ")?;
+        def.body.write(f, Level::Top)?;
+        writeln!(f)?;
+    }
+
+    Ok(())
+}
+
+/// A melody as it is written, which both kinds of programs are printed as.
+enum Written {
+    /// A single token, like a note, a degree or a name.
+    Atom(String),
+    Call(String, Vec<Written>),
+    Choice(Vec<usize>, Vec<Written>),
+    /// An operator before a simple melody, like `~`.
+    Prefix(String, Box<Written>),
+    Scale(String, Box<Written>),
+    /// An operator after a melody, like `*2`.
+    Postfix(Box<Written>, Rank, String),
+    Sequence(Vec<Written>),
+    Stack(Vec<Written>),
+}
+
+/// The postfix operators, in the order they are parsed after a melody.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum Rank {
+    Sharps,
+    Steps,
+    Transpose,
+    Offset,
+    Marking,
+    Repeat,
+    Slice,
+    Fit,
+}
+
+/// What is expected where a melody is written, from what binds the tightest
+/// to what binds the loosest.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Level {
+    Simple,
+    /// The melody a postfix operator of the given rank applies to.
+    Operand(Rank),
+    Scale,
+    /// An element of a stack or a branch of a choice.
+    Sequence,
+    Top,
+}
+
+impl Written {
+    fn postfix(inner: Self, rank: Rank, operator: String) -> Self {
+        Self::Postfix(Box::new(inner), rank, operator)
+    }
+
+    fn prefix(operator: String, inner: Self) -> Self {
+        Self::Prefix(operator, Box::new(inner))
+    }
+
+    fn sharp(inner: Self, by: isize) -> Self {
+        // Sharps come before every other postfix operator, so a transposition
+        // saves parentheses around those.
+        if by == 1 && !matches!(inner, Self::Postfix(_, rank, _) if rank < Rank::Transpose) {
+            Self::postfix(inner, Rank::Sharps, "#".into())
+        } else {
+            Self::postfix(inner, Rank::Transpose, format!("^{by}"))
+        }
+    }
+
+    fn offset(inner: Self, by: isize) -> Self {
+        Self::postfix(inner, Rank::Offset, format!("{by:+}"))
+    }
+
+    fn steps(inner: Self, by: isize) -> Self {
+        Self::postfix(inner, Rank::Steps, format!("^^{by}"))
+    }
+
+    fn dynamic(inner: Self, velocity: &Velocity) -> Self {
+        let marking = match velocity.marking() {
+            Some(marking) => marking.to_string(),
+            None => integer(&(&velocity.0 * BigRational::from_integer(100.into()))),
+        };
+
+        Self::postfix(inner, Rank::Marking, format!("@{marking}"))
+    }
+
+    fn repeat(inner: Self, count: &Option<usize>) -> Self {
+        let count = match count {
+            Some(count) => count.to_string(),
+            None => "oo".into(),
+        };
+
+        Self::postfix(inner, Rank::Repeat, format!("*{count}"))
+    }
+
+    fn slice(inner: Self, from: &Time, to: &Time) -> Self {
+        let from = if from == &Time::zero() {
+            String::new()
+        } else {
+            ratio(&from.0)
+        };
+
+        Self::postfix(inner, Rank::Slice, format!("[{from}..{}]", ratio(&to.0)))
+    }
+
+    fn fit(inner: Self, length: &Time) -> Self {
+        Self::postfix(inner, Rank::Fit, format!(" : {}", ratio(&length.0)))
+    }
+
+    /// Write a voice as a channel marking, or an instrument operator if it has
+    /// no channel.
+    fn voice(inner: Self, voice: &Voice) -> Self {
+        let inner = match voice.instrument {
+            Some(instrument) => Self::prefix(format!("instrument({}) ", instrument + 1), inner),
+            None => inner,
+        };
+
+        match voice.channel {
+            Some(channel) => Self::postfix(inner, Rank::Marking, format!("@ch{}", channel + 1)),
+            None => inner,
+        }
+    }
+
+    fn tempo(inner: Self, from: &Option<Tempo>, to: &Tempo) -> Self {
+        let to = integer(&to.0);
+        let tempo = match from {
+            Some(from) if integer(&from.0) == to => to,
+            Some(from) => format!("{}..{to}", integer(&from.0)),
+            None => format!("..{to}"),
+        };
+
+        Self::prefix(format!("tempo({tempo}) "), inner)
+    }
+
+    fn scale(factor: &Factor, inner: Self) -> Self {
+        Self::Scale(ratio(&factor.0), Box::new(inner))
+    }
+
+    /// Whether this melody has to be parenthesised where the given level is
+    /// expected.
+    fn needs_parens(&self, level: Level) -> bool {
+        match self {
+            Self::Atom(_) | Self::Call(..) | Self::Choice(..) | Self::Prefix(..) => false,
+            Self::Scale(..) => level == Level::Simple,
+            Self::Postfix(_, rank, _) => match level {
+                Level::Simple => true,
+                Level::Operand(outer) => *rank >= outer,
+                _ => false,
+            },
+            Self::Sequence(_) => !matches!(level, Level::Sequence | Level::Top),
+            Self::Stack(_) => level != Level::Top,
+        }
+    }
+
+    fn write(&self, f: &mut impl Write, level: Level) -> fmt::Result {
+        if self.needs_parens(level) {
+            write!(f, "(")?;
+            self.write(f, Level::Top)?;
+            return write!(f, ")");
+        }
+
+        match self {
+            Self::Atom(atom) => write!(f, "{atom}"),
+
+            Self::Call(name, args) => {
+                write!(f, "{name}(")?;
+                Self::write_all(f, args, ", ", Level::Scale)?;
+                write!(f, ")")
+            }
+
+            Self::Choice(weights, branches) => {
+                write!(f, "{{")?;
+
+                for (index, (weight, branch)) in weights.iter().zip(branches).enumerate() {
+                    if index > 0 {
+                        write!(f, " | ")?;
+                    }
+
+                    let mut written = String::new();
+                    branch.write(&mut written, Level::Sequence)?;
+
+                    // A branch starting with a number would otherwise have
+                    // that number taken as its weight if a colon follows.
+                    if *weight != 1 || written.starts_with(|c: char| c.is_ascii_digit()) {
+                        write!(f, "{weight}: ")?;
+                    }
+
+                    write!(f, "{written}")?;
+                }
+
+                write!(f, "}}")
+            }
+
+            Self::Prefix(operator, inner) => {
+                write!(f, "{operator}")?;
+                inner.write(f, Level::Simple)
+            }
+
+            Self::Scale(factor, inner) => {
+                write!(f, "{factor} ")?;
+                inner.write(f, Level::Simple)
+            }
+
+            Self::Postfix(inner, rank, operator) => {
+                inner.write(f, Level::Operand(*rank))?;
+                write!(f, "{operator}")
+            }
+
+            Self::Sequence(melodies) => Self::write_all(f, melodies, ", ", Level::Scale),
+            Self::Stack(melodies) => Self::write_all(f, melodies, " | ", Level::Sequence),
+        }
+    }
+
+    fn write_all(
+        f: &mut impl Write,
+        melodies: &[Self],
+        separator: &str,
+        level: Level,
+    ) -> fmt::Result {
+        for (index, melody) in melodies.iter().enumerate() {
+            if index > 0 {
+                write!(f, "{separator}")?;
+            }
+
+            melody.write(f, level)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn from_implicit<N, Id, A>(melody: &implicit::Melody<N, Id, A>, spellings: &Spellings) -> Written
+where
+    N: fmt::Display,
+    A: Allocator<implicit::Melody<N, Id, A>>,
+{
+    use implicit::Melody;
+
+    let inner = |inner: &A::Holder| from_implicit(A::as_ref(inner), spellings);
+    let all = |melodies: &A::Several| {
+        A::as_slice(melodies)
+            .iter()
+            .map(|melody| from_implicit(melody, spellings))
+            .collect()
+    };
+
+    match melody {
+        Melody::Pause(_) => Written::Atom("<>".into()),
+        Melody::Note(_, note) => Written::Atom(note.to_string()),
+        Melody::Degree(_, degree) => Written::Atom(degree.to_string()),
+        Melody::Name(_, name) | Melody::Parameter(_, name) => Written::Atom(spellings.get(name)),
+        Melody::Call(_, name, args) => Written::Call(spellings.get(name), all(args)),
+        Melody::Scale(_, factor, melody) => Written::scale(factor, inner(melody)),
+        Melody::Sharp(_, by, melody) => Written::sharp(inner(melody), *by),
+        Melody::Offset(_, by, melody) => Written::offset(inner(melody), *by),
+        Melody::Dynamic(_, velocity, melody) => Written::dynamic(inner(melody), velocity),
+        Melody::Repeat(_, count, melody) => Written::repeat(inner(melody), count),
+        Melody::Reverse(_, melody) => Written::prefix("~".into(), inner(melody)),
+        Melody::Invert(_, axis, melody) => Written::prefix(format!("inv({axis}) "), inner(melody)),
+        Melody::Key(_, key, melody) => Written::prefix(
+            format!("in {} {} ", key.tonic, key.mode.name()),
+            inner(melody),
+        ),
+        Melody::Steps(_, by, melody) => Written::steps(inner(melody), *by),
+        Melody::Tempo(_, from, to, melody) => Written::tempo(inner(melody), from, to),
+        Melody::Voice(_, voice, melody) => Written::voice(inner(melody), voice),
+        Melody::Slice(_, from, to, melody) => Written::slice(inner(melody), from, to),
+        Melody::Fit(_, length, melody) => Written::fit(inner(melody), length),
+        Melody::Sequence(melodies) => Written::Sequence(all(melodies)),
+        Melody::Stack(melodies) => Written::Stack(all(melodies)),
+        Melody::Choice(_, weights, melodies) => Written::Choice(weights.clone(), all(melodies)),
+    }
+}
+
+/// Get how a checked melody is written. `params` is raised to the number of
+/// parameters it uses, and `arities` to the number of arguments each
+/// definition is called with.
+fn from_melody<N, Id, A>(
+    melody: &melody::Melody<N, Id, A>,
+    spellings: &Spellings,
+    params: &mut usize,
+    arities: &mut HashMap<Name, usize>,
+) -> Written
+where
+    N: fmt::Display,
+    A: Allocator<melody::Melody<N, Id, A>>,
+{
+    let mut inner = |inner: &A::Holder| from_melody(A::as_ref(inner), spellings, params, arities);
+
+    match &melody.node {
+        Node::Pause => Written::Atom("<>".into()),
+        Node::Note(note) => Written::Atom(note.to_string()),
+        Node::Degree(degree) => Written::Atom(degree.to_string()),
+        Node::Name(name) | Node::Recur(name) => Written::Atom(spellings.get(name)),
+        Node::Parameter(index) => {
+            *params = (*params).max(index + 1);
+            Written::Atom(spellings.parameter(*index))
+        }
+
+        Node::Call(name, args) => {
+            let args: Vec<_> = A::as_slice(args)
+                .iter()
+                .map(|arg| from_melody(arg, spellings, params, arities))
+                .collect();
+
+            let arity = arities.entry(*name).or_insert(0);
+            *arity = args.len().max(*arity);
+            Written::Call(spellings.get(name), args)
+        }
+
+        Node::Scale(factor, melody) => Written::scale(factor, inner(melody)),
+        Node::Sharp(by, melody) => Written::sharp(inner(melody), *by),
+        Node::Offset(by, melody) => Written::offset(inner(melody), *by),
+        Node::Dynamic(velocity, melody) => Written::dynamic(inner(melody), velocity),
+        Node::Repeat(count, melody) => Written::repeat(inner(melody), count),
+        Node::Reverse(melody) => Written::prefix("~".into(), inner(melody)),
+        Node::Invert(axis, melody) => Written::prefix(format!("inv({axis}) "), inner(melody)),
+        Node::Key(key, melody) => Written::prefix(
+            format!("in {} {} ", key.tonic, key.mode.name()),
+            inner(melody),
+        ),
+        Node::Steps(by, melody) => Written::steps(inner(melody), *by),
+        Node::Tempo(from, to, melody) => Written::tempo(inner(melody), from, to),
+        Node::Voice(voice, melody) => Written::voice(inner(melody), voice),
+        Node::Slice(from, to, melody) => Written::slice(inner(melody), from, to),
+
+        Node::Sequence(melodies) | Node::Stack(melodies) | Node::Choice(_, melodies) => {
+            let melodies = A::as_slice(melodies)
+                .iter()
+                .map(|melody| from_melody(melody, spellings, params, arities))
+                .collect();
+
+            match &melody.node {
+                Node::Sequence(_) => Written::Sequence(melodies),
+                Node::Stack(_) => Written::Stack(melodies),
+                Node::Choice(weights, _) => Written::Choice(weights.clone(), melodies),
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+/// Write a ratio like a factor, as `3` or `3/2`.
+fn ratio(ratio: &BigRational) -> String {
+    if ratio.is_integer() {
+        ratio.numer().to_string()
+    } else {
+        format!("{}/{}", ratio.numer(), ratio.denom())
+    }
+}
+
+/// Write a ratio which can only be written as a whole number, rounded.
+fn integer(ratio: &BigRational) -> String {
+    ratio.round().numer().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::implicit::Program;
+    use crate::parse::Parser;
+    use crate::{compile, Heap, Names};
+
+    fn parse(names: &mut Names, source: &str) -> Program<char, &'static str, Heap> {
+        Parser::parse(&mut Heap, names, "", source).unwrap()
+    }
+
+    /// Check that `source` prints exactly as it is written, such that parsing
+    /// what is printed gives the same program, spans and all.
+    fn check_canonical(source: &str) {
+        let mut names = Names::new();
+        let program = parse(&mut names, source);
+        let printed = program.display(&names).to_string();
+
+        assert_eq!(source, printed);
+        assert!(program == parse(&mut names, &printed));
+    }
+
+    /// Check that `source` prints as `expected`, which prints as itself.
+    fn check_printed(expected: &str, source: &str) {
+        let mut names = Names::new();
+        let printed = parse(&mut names, source).display(&names).to_string();
+        assert_eq!(expected, printed);
+        check_canonical(expected);
+    }
+
+    #[test]
+    fn precedence() {
+        check_canonical("it = ab, (cd | ef), gh | pq\n");
+        check_canonical("it = 2 (ab, cd), 1/2 ~ab*2, {ab | 2: cd, ef | 3: 4}\n");
+        check_canonical("it = (ab*2)#, ab#^^1^2-1@mf*oo[1/2..3] : 2, (ab : 2)*2\n");
+        check_canonical("it = (ab@70)@ch2, ab@ch2*2, xy(ab, (cd, ef)), 2 (ab#)\n");
+    }
+
+    #[test]
+    fn operators() {
+        check_canonical(
+            "it = in a minor 1, inv(c) ab, tempo(90) ab, tempo(..60) ab, tempo(80..96) ab\n",
+        );
+        check_canonical("it = instrument(25) ab@ch10, ab[..2], ab^-3, <>, 3/2 <>\n");
+    }
+
+    #[test]
+    fn minimal_parens() {
+        check_printed("it = ab, cd | ef, gh\n", "it = ((ab), (cd)) | (((ef, gh)))");
+        check_printed("it = (ab, cd)*2, ~ab@mf\n", "it = ((ab, cd))*2, (~(ab))@mf");
+    }
+
+    #[test]
+    fn declarations() {
+        check_canonical(concat!(
+            "import \"lib.mm\"\n",
+            "title \"A tune\"\n",
+            "composer \"Someone\"\n",
+            "tempo 90\n",
+            "time 3/4\n",
+            "\n",
+            "--- The tune\n",
+            "--- in two lines\n",
+            "it! : 2 bars = xy(ab), xy(cd)\n",
+            "xy(pq) = pq, pq\n",
+        ));
+    }
+
+    #[test]
+    fn declarations_compared() {
+        let source = "title \"A tune\"\n\n--- The tune\nit! = a, c\n";
+        let mut names = Names::new();
+        let it = names.make("it");
+
+        let mut parsed = parse(&mut names, source);
+        assert!(parsed == parse(&mut names, source));
+        parsed.metadata.title = Some("Another tune".into());
+        assert!(parsed != parse(&mut names, source));
+        parsed = parse(&mut names, source);
+        parsed.docs.insert(it, "Another tune".into());
+        assert!(parsed != parse(&mut names, source));
+
+        let compiled = || compile::<char, _, _>(&mut Heap, &mut Names::new(), "", source).unwrap();
+        let mut program = compiled();
+        assert!(program == compiled());
+        program.metadata.title = Some("Another tune".into());
+        assert!(program != compiled());
+        program = compiled();
+        program.docs.insert(it, "Another tune".into());
+        assert!(program != compiled());
+    }
+
+    #[test]
+    fn primes() {
+        check_printed(
+            "it = pq\npq = ab\nxy = pq'\npq' = cd\n",
+            "it = pq where pq = ab\nxy = pq where pq = cd",
+        );
+    }
+
+    #[test]
+    fn compiled() {
+        let source = "it! = 2 (a#*2, xy(c)) | {e | g}\nxy(pq) = pq, pq@mf\n";
+        let mut names = Names::new();
+        let program = compile::<char, _, _>(&mut Heap, &mut names, "", source).unwrap();
+        let printed = program.display(&names).to_string();
+        assert_eq!(
+            "it! = 2 (a#*2, xy_1(c)) | {e | g}\nxy_1(x1) = x1, x1@mf\n",
+            printed
+        );
+
+        // Instances are named after their parameters' lengths again when
+        // compiled, so only the source is the same when printed again.
+        assert!(compile::<char, _, _>(&mut Heap, &mut names, "", &printed).is_ok());
+        check_canonical(&printed);
+    }
+}
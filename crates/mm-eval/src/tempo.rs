@@ -0,0 +1,60 @@
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{ToPrimitive, Zero};
+
+/// The tempo a melody is played at when no tempo is given, in beats per minute.
+const DEFAULT_BPM: usize = 120;
+
+/// A tempo in beats per minute, where a beat is a note of length `1`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Tempo(pub BigRational);
+
+impl Tempo {
+    /// Create a tempo with the given beats per minute, if it is positive.
+    pub fn from_bpm(bpm: BigInt) -> Option<Self> {
+        (bpm > BigInt::zero()).then(|| Self(BigRational::from_integer(bpm)))
+    }
+
+    /// Get the tempo some fraction of the way from this tempo to another.
+    pub fn towards(&self, to: &Self, fraction: &BigRational) -> Self {
+        Self(&self.0 + (&to.0 - &self.0) * fraction)
+    }
+
+    /// Get the number of microseconds every beat lasts at this tempo, as used
+    /// by MIDI tempo events.
+    pub fn to_micros_per_beat(&self) -> u32 {
+        let micros = (BigRational::from_integer(BigInt::from(60_000_000)) / &self.0).round();
+        micros.to_u32().unwrap_or(u32::MAX).max(1)
+    }
+}
+
+impl Default for Tempo {
+    fn default() -> Self {
+        Self(BigRational::from_integer(BigInt::from(DEFAULT_BPM)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::BigInt;
+    use num_rational::BigRational;
+
+    use super::Tempo;
+
+    fn bpm(bpm: i32) -> Tempo {
+        Tempo::from_bpm(BigInt::from(bpm)).unwrap()
+    }
+
+    #[test]
+    fn micros_per_beat() {
+        assert_eq!(500_000, Tempo::default().to_micros_per_beat());
+        assert_eq!(625_000, bpm(96).to_micros_per_beat());
+        assert_eq!(None, Tempo::from_bpm(BigInt::from(0)));
+    }
+
+    #[test]
+    fn towards() {
+        let half = BigRational::new(BigInt::from(1), BigInt::from(2));
+        assert_eq!(bpm(100), bpm(80).towards(&bpm(120), &half));
+    }
+}
@@ -69,6 +69,43 @@ impl Time {
     }
 }
 
+/// A time signature, giving the number of beats in a bar and the kind of note
+/// which makes up a beat, such as `4` for quarter notes. A note of length `1`
+/// is a quarter note.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct TimeSignature {
+    pub beats: u8,
+    pub unit: u8,
+}
+
+impl TimeSignature {
+    /// Create a time signature, if the beats are positive and the unit is a
+    /// power of two.
+    pub fn new(beats: u8, unit: u8) -> Option<Self> {
+        (beats > 0 && unit.is_power_of_two()).then_some(Self { beats, unit })
+    }
+
+    pub fn beat_length(&self) -> BigRational {
+        BigRational::new(BigInt::from(4), BigInt::from(self.unit))
+    }
+
+    pub fn bar_length(&self) -> BigRational {
+        self.beat_length() * BigInt::from(self.beats)
+    }
+}
+
+impl Default for TimeSignature {
+    fn default() -> Self {
+        Self { beats: 4, unit: 4 }
+    }
+}
+
+impl fmt::Display for TimeSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.beats, self.unit)
+    }
+}
+
 impl Add<&'_ Time> for &'_ Length {
     type Output = Time;
 
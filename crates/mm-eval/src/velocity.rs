@@ -0,0 +1,130 @@
+use std::ops::Mul;
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::ToPrimitive;
+
+/// The velocity level a note has when no dynamics are applied to it.
+const DEFAULT_LEVEL: u8 = 100;
+
+/// The smallest velocity level a note can have, as MIDI stops notes played at
+/// level `0` instead.
+const MIN_LEVEL: u8 = 1;
+
+/// The largest velocity level a note can have.
+const MAX_LEVEL: u8 = 127;
+
+/// The dynamic markings, along with the velocity level each stands for.
+const MARKINGS: [(&str, u8); 8] = [
+    ("ppp", 16),
+    ("pp", 33),
+    ("p", 49),
+    ("mp", 64),
+    ("mf", 80),
+    ("f", 96),
+    ("ff", 112),
+    ("fff", 127),
+];
+
+/// A note velocity relative to the default velocity. Nested dynamics compose
+/// by multiplying their velocities together.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Velocity(pub BigRational);
+
+impl Velocity {
+    pub fn one() -> Self {
+        Self(BigRational::from_integer(BigInt::from(1)))
+    }
+
+    /// Create a velocity which brings a note at the default level to the given
+    /// level, or `None` if the level is not between `1` and `127` inclusive.
+    pub fn from_level(level: BigInt) -> Option<Self> {
+        let level = level
+            .to_u8()
+            .filter(|level| (MIN_LEVEL..=MAX_LEVEL).contains(level))?;
+        Some(Self::at(level))
+    }
+
+    /// Get the velocity corresponding to a dynamic marking like `pp` or `mf`.
+    pub fn from_marking(marking: &str) -> Option<Self> {
+        let (_, level) = MARKINGS.iter().find(|(name, _)| *name == marking)?;
+        Some(Self::at(*level))
+    }
+
+    /// Get the dynamic marking which stands for exactly this velocity, if
+    /// there is one.
+    pub fn marking(&self) -> Option<&'static str> {
+        MARKINGS
+            .iter()
+            .find(|(_, level)| *self == Self::at(*level))
+            .map(|(name, _)| *name)
+    }
+
+    /// Get the absolute velocity level of a note at this velocity, between `1`
+    /// and `127` inclusive.
+    pub fn to_level(&self) -> u8 {
+        let level = (&self.0 * BigInt::from(DEFAULT_LEVEL)).round();
+        level
+            .to_u8()
+            .unwrap_or(MAX_LEVEL)
+            .clamp(MIN_LEVEL, MAX_LEVEL)
+    }
+
+    fn at(level: u8) -> Self {
+        Self(BigRational::new(
+            BigInt::from(level),
+            BigInt::from(DEFAULT_LEVEL),
+        ))
+    }
+}
+
+impl Mul for &'_ Velocity {
+    type Output = Velocity;
+
+    fn mul(self, rhs: Self) -> Velocity {
+        Velocity(&self.0 * &rhs.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::BigInt;
+
+    use super::Velocity;
+
+    #[test]
+    fn default_level() {
+        assert_eq!(100, Velocity::one().to_level());
+    }
+
+    #[test]
+    fn nested_markings() {
+        let pp = Velocity::from_marking("pp").unwrap();
+        let ff = Velocity::from_marking("ff").unwrap();
+
+        assert_eq!(33, pp.to_level());
+        assert_eq!(37, (&pp * &ff).to_level());
+        assert_eq!(Some("pp"), pp.marking());
+        assert_eq!(None, (&pp * &ff).marking());
+    }
+
+    #[test]
+    fn saturating() {
+        let loud = Velocity::from_level(BigInt::from(120)).unwrap();
+        assert_eq!(127, (&loud * &loud).to_level());
+
+        let quiet = Velocity::from_level(BigInt::from(1)).unwrap();
+        assert_eq!(1, (&quiet * &quiet).to_level());
+    }
+
+    #[test]
+    fn levels() {
+        let level = |level: i32| Velocity::from_level(BigInt::from(level));
+
+        assert_eq!(None, level(0));
+        assert_eq!(Some(1), level(1).map(|velocity| velocity.to_level()));
+        assert_eq!(Some(127), level(127).map(|velocity| velocity.to_level()));
+        assert_eq!(None, level(128));
+        assert_eq!(None, level(300));
+    }
+}
@@ -0,0 +1,66 @@
+/// The number of MIDI channels a voice can be played on.
+pub const CHANNELS: u8 = 16;
+
+/// The number of General MIDI instruments a voice can be played with.
+pub const INSTRUMENTS: u8 = 128;
+
+/// The channel and instrument a melody is played with. Both count from zero,
+/// even though they are written counting from one, and either may be left for
+/// the surrounding melody or the output to decide.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Voice {
+    pub channel: Option<u8>,
+    pub instrument: Option<u8>,
+}
+
+impl Voice {
+    /// Create a voice on the channel written as `number`, if there is one.
+    pub fn channel(number: u8) -> Option<Self> {
+        (1..=CHANNELS).contains(&number).then(|| Self {
+            channel: Some(number - 1),
+            instrument: None,
+        })
+    }
+
+    /// Create a voice with the General MIDI instrument written as `number`, if
+    /// there is one.
+    pub fn instrument(number: u8) -> Option<Self> {
+        (1..=INSTRUMENTS).contains(&number).then(|| Self {
+            channel: None,
+            instrument: Some(number - 1),
+        })
+    }
+
+    /// Get this voice within an outer voice, keeping whatever this voice
+    /// leaves undecided from the outer voice.
+    pub fn within(self, outer: Self) -> Self {
+        Self {
+            channel: self.channel.or(outer.channel),
+            instrument: self.instrument.or(outer.instrument),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Voice;
+
+    #[test]
+    fn within() {
+        let channel = Voice::channel(2).unwrap();
+        let instrument = Voice::instrument(33).unwrap();
+        let inner = Voice::channel(10).unwrap();
+
+        let voice = inner.within(instrument.within(channel));
+        assert_eq!(Some(9), voice.channel);
+        assert_eq!(Some(32), voice.instrument);
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(None, Voice::channel(0));
+        assert_eq!(None, Voice::channel(17));
+        assert_eq!(None, Voice::instrument(129));
+        assert!(Voice::instrument(128).is_some());
+    }
+}
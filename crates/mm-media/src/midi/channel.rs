@@ -2,41 +2,116 @@ use std::cmp::Ordering;
 use std::collections::BinaryHeap;
 
 use midly::num::{u28, u4, u7};
-use midly::{MidiMessage, TrackEvent, TrackEventKind};
-use mm_eval::span::Span;
-use mm_eval::{Length, Time};
+use midly::{MidiMessage, PitchBend, TrackEvent, TrackEventKind};
+use mm_eval::eval::Played;
+use mm_eval::Time;
 use num_rational::BigRational;
 use num_traits::{FromPrimitive, ToPrimitive};
 
+use super::tuning::Tuning;
 use super::Pitch;
 
-/// Write the notes from the given iterator to the given track on a specific
-/// channel.
+/// Write the notes of a voice from the given iterator to the given track,
+/// tuned with the given tuning and played with the given General MIDI program,
+/// if any.
+///
+/// Notes are played on `channel` as long as they need the same pitch bend as
+/// the notes sounding there. Otherwise, they are played on a channel taken from
+/// `spare`, or on the channel which is free the soonest if there are none left.
 ///
 /// `ticks_per_beat` determines how many ticks a note of length `1` should last.
-pub fn write_channel<Id>(
-    notes: impl Iterator<Item = (Pitch, Span<Id>, Time, Length)>,
+pub fn write_voice<Id>(
+    notes: impl Iterator<Item = Played<Pitch, Id>>,
+    tuning: &Tuning,
+    program: Option<u7>,
     ticks_per_beat: usize,
     channel: u4,
+    spare: &mut impl Iterator<Item = u4>,
     track: &mut Vec<TrackEvent>,
 ) {
+    let mut notes: Vec<_> = notes.collect();
+    notes.sort_by(|a, b| a.start.0.cmp(&b.start.0));
+
+    let mut lanes = vec![Lane {
+        channel,
+        bend: 0,
+        until: Time::zero(),
+    }];
+
     let mut events = BinaryHeap::new();
 
-    for (note, _, start, length) in notes {
+    for played in notes {
+        let Some(tone) = tuning.tone(played.note) else {
+            continue;
+        };
+
+        let end = &played.start + &played.length;
+        let lane = lanes
+            .iter()
+            .position(|lane| lane.bend == tone.bend)
+            .or_else(|| lanes.iter().position(|lane| lane.until.0 <= played.start.0))
+            .or_else(|| {
+                let channel = spare.next()?;
+                lanes.push(Lane {
+                    channel,
+                    bend: 0,
+                    until: Time::zero(),
+                });
+
+                Some(lanes.len() - 1)
+            })
+            .unwrap_or_else(|| {
+                let soonest = lanes
+                    .iter()
+                    .enumerate()
+                    .min_by(|a, b| a.1.until.0.cmp(&b.1.until.0));
+                soonest.map(|(index, _)| index).unwrap_or_default()
+            });
+
+        let lane = &mut lanes[lane];
+        if lane.bend != tone.bend {
+            lane.bend = tone.bend;
+            events.push(PitchEvent {
+                at: played.start.clone(),
+                channel: lane.channel,
+                kind: PitchEventKind::Bend(tone.bend),
+            });
+        }
+
+        if end.0 > lane.until.0 {
+            lane.until = end.clone();
+        }
+
+        let vel = u7::new(played.velocity.to_level());
+
         let off = PitchEvent {
-            at: &start + &length,
-            kind: PitchEventKind::Off(note),
+            at: end,
+            channel: lane.channel,
+            kind: PitchEventKind::Off(tone.key, vel),
         };
 
         let on = PitchEvent {
-            at: start,
-            kind: PitchEventKind::On(note),
+            at: played.start,
+            channel: lane.channel,
+            kind: PitchEventKind::On(tone.key, vel),
         };
 
         events.push(on);
         events.push(off);
     }
 
+    if let Some(program) = program {
+        for lane in &lanes {
+            track.push(TrackEvent {
+                delta: u28::new(0),
+                kind: TrackEventKind::Midi {
+                    channel: lane.channel,
+                    message: MidiMessage::ProgramChange { program },
+                },
+            });
+        }
+    }
+
     let mut at = 0;
     while let Some(event) = events.pop() {
         let now = (event.at.0 * BigRational::from_usize(ticks_per_beat).unwrap())
@@ -47,36 +122,41 @@ pub fn write_channel<Id>(
         let delta = u28::new(u32::try_from(delta).unwrap());
         at = now;
 
-        let kind = match event.kind {
-            PitchEventKind::On(note) => TrackEventKind::Midi {
-                channel,
-                message: MidiMessage::NoteOn {
-                    key: note.to_midi_key_saturating(),
-                    vel: u7::new(100),
-                },
+        let message = match event.kind {
+            PitchEventKind::On(key, vel) => MidiMessage::NoteOn { key, vel },
+            PitchEventKind::Off(key, vel) => MidiMessage::NoteOff { key, vel },
+            PitchEventKind::Bend(bend) => MidiMessage::PitchBend {
+                bend: PitchBend::from_int(bend),
             },
+        };
 
-            PitchEventKind::Off(note) => TrackEventKind::Midi {
-                channel,
-                message: MidiMessage::NoteOff {
-                    key: note.to_midi_key_saturating(),
-                    vel: u7::new(100),
-                },
-            },
+        let kind = TrackEventKind::Midi {
+            channel: event.channel,
+            message,
         };
 
         track.push(TrackEvent { delta, kind });
     }
 }
 
+/// A channel which the notes of a voice are played on, along with the pitch
+/// bend it is set to and the time until which notes sound on it.
+struct Lane {
+    channel: u4,
+    bend: i16,
+    until: Time,
+}
+
 struct PitchEvent {
     at: Time,
+    channel: u4,
     kind: PitchEventKind,
 }
 
 enum PitchEventKind {
-    On(Pitch),
-    Off(Pitch),
+    On(u7, u7),
+    Off(u7, u7),
+    Bend(i16),
 }
 
 impl Eq for PitchEvent {}
@@ -119,10 +199,94 @@ impl PartialOrd for PitchEventKind {
 
 impl Ord for PitchEventKind {
     fn cmp(&self, other: &Self) -> Ordering {
-        match (self, other) {
-            (Self::Off(_), Self::On(_)) => Ordering::Less,
-            (Self::On(_), Self::Off(_)) => Ordering::Greater,
-            _ => Ordering::Equal,
+        self.rank().cmp(&other.rank())
+    }
+}
+
+impl PitchEventKind {
+    /// Notes stop before the pitch bend changes, which happens before the next
+    /// notes start.
+    fn rank(&self) -> u8 {
+        match self {
+            Self::Off(..) => 0,
+            Self::Bend(..) => 1,
+            Self::On(..) => 2,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use midly::num::u4;
+    use midly::{MidiMessage, PitchBend, TrackEventKind};
+    use mm_eval::eval::Played;
+    use mm_eval::note::Note;
+    use mm_eval::span::Span;
+    use mm_eval::{Length, Time, Velocity, Voice};
+    use num_rational::BigRational;
+    use num_traits::FromPrimitive;
+
+    use super::write_voice;
+    use crate::midi::{Pitch, Tuning};
+
+    fn played(name: &str, start: u8) -> Played<Pitch, ()> {
+        Played {
+            note: Pitch::parse(name).unwrap(),
+            span: Span {
+                source: (),
+                start: 0,
+                end: 0,
+            },
+            start: Time(BigRational::from_u8(start).unwrap()),
+            length: Length::Bounded(BigRational::from_u8(2).unwrap()),
+            velocity: Velocity::one(),
+            voice: Voice::default(),
         }
     }
+
+    #[test]
+    fn bent_notes_on_spare_channels() {
+        // With 24 equal steps on consecutive keys, C4 and D4 lie a quarter tone
+        // above a MIDI key, while C#4 lies right on one.
+        let tuning = Tuning::equal(24)
+            .unwrap()
+            .with_keyboard("1\n0\n127\n60\n69\n440.0\n1\n0\n")
+            .unwrap();
+        let notes = [played("C4", 0), played("C#4", 0), played("D4", 2)];
+        let mut spare = [5, 6].map(u4::new).into_iter();
+        let mut track = Vec::new();
+
+        write_voice(
+            notes.into_iter(),
+            &tuning,
+            None,
+            1,
+            u4::new(0),
+            &mut spare,
+            &mut track,
+        );
+
+        let on = |wanted: u8| -> Vec<_> {
+            track
+                .iter()
+                .filter_map(|event| match event.kind {
+                    TrackEventKind::Midi { channel, message } if channel == wanted => Some(message),
+                    _ => None,
+                })
+                .filter_map(|message| match message {
+                    MidiMessage::NoteOn { key, .. } => Some(Ok(u8::from(key))),
+                    MidiMessage::PitchBend { bend } => Some(Err(bend)),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        // The overlapping notes need different bends, so C#4 takes a spare
+        // channel, while D4 needs the same bend as C4.
+        let expected = vec![Err(PitchBend::from_int(2048)), Ok(64), Ok(65)];
+
+        assert_eq!(expected, on(0));
+        assert_eq!(vec![Ok(65)], on(5));
+        assert_eq!(Some(u4::new(6)), spare.next());
+    }
 }
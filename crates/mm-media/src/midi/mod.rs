@@ -1,34 +1,79 @@
-pub use pitch::{Interval, Pitch};
+pub use pitch::{Interval, Pitch, Spelled, Spelling};
+pub use tuning::{Tone, Tuning};
 
 mod channel;
 mod pitch;
+mod tempo;
+pub mod tuning;
 
-use mm_eval::span::Span;
-use mm_eval::{Length, Time};
+use mm_eval::eval::Event;
+use mm_eval::{Metadata, TimeSignature, Voice};
 
 use std::io;
 use std::path::Path;
 
-use midly::num::{u15, u24, u28, u4};
+use midly::num::{u15, u28, u4, u7};
 use midly::{Format, Header, MetaMessage, Smf, Timing, TrackEvent, TrackEventKind};
 
-use self::channel::write_channel;
+use self::channel::write_voice;
+use self::tempo::write_tempo;
 
 const TICKS_PER_BEAT: usize = 128;
 
-/// Write the given notes to a MIDI file at the given path.
+/// The channel General MIDI reserves for percussion, counting from zero.
+const PERCUSSION: u8 = 9;
+
+/// Write the given notes and changes of tempo to a MIDI file at the given
+/// path. The metadata, changes of tempo and the time signature are written to
+/// a track of their own, ahead of one track for every voice the notes are
+/// played with.
+///
+/// Notes are tuned with the given tuning, where notes which fall between the
+/// keys of a MIDI keyboard are bent into place. Notes which need different
+/// bends at the same time are spread over the channels no voice asks for.
 pub fn write<Id>(
-    notes: impl Iterator<Item = (Pitch, Span<Id>, Time, Length)>,
+    events: impl Iterator<Item = Event<Pitch, Id>>,
+    time: TimeSignature,
+    tuning: &Tuning,
+    metadata: &Metadata,
     to: impl AsRef<Path>,
 ) -> Result<(), io::Error> {
-    let mut track = vec![TrackEvent {
-        delta: u28::new(0),
-        kind: TrackEventKind::Meta(MetaMessage::Tempo(u24::new(500_000))),
-    }];
+    let mut voices: Vec<(Voice, Vec<_>)> = Vec::new();
+    let mut tempos = Vec::new();
+
+    for event in events {
+        match event {
+            Event::Note(played) => {
+                match voices.iter_mut().find(|(voice, _)| *voice == played.voice) {
+                    Some((_, notes)) => notes.push(played),
+                    None => voices.push((played.voice, vec![played])),
+                }
+            }
+
+            Event::Tempo {
+                start,
+                length,
+                from,
+                to,
+            } => tempos.push((start, length, from, to)),
+
+            // Nothing is played for overflows, which are left for the caller
+            // to report.
+            Event::Overflow(_) => {}
+        }
+    }
 
-    write_channel(notes, TICKS_PER_BEAT, u4::new(0), &mut track);
+    let mut conductor: Vec<_> = metadata_events(metadata)
+        .into_iter()
+        .chain([time_signature(time)])
+        .map(|message| TrackEvent {
+            delta: u28::new(0),
+            kind: TrackEventKind::Meta(message),
+        })
+        .collect();
 
-    track.push(TrackEvent {
+    write_tempo(tempos.into_iter(), TICKS_PER_BEAT, &mut conductor);
+    conductor.push(TrackEvent {
         delta: u28::new(0),
         kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
     });
@@ -39,7 +84,141 @@ pub fn write<Id>(
     );
 
     let mut smf = Smf::new(header);
-    smf.tracks.push(track);
+    smf.tracks.push(conductor);
+
+    let channels = channels(voices.iter().map(|(voice, _)| *voice));
+    let mut spare = (0..16)
+        .filter(|channel| *channel != PERCUSSION && !channels.contains(channel))
+        .map(u4::new);
+
+    // The keys of the percussion channel pick instruments rather than pitches,
+    // so they are never tuned.
+    let untuned = Tuning::default();
+
+    for ((voice, notes), channel) in voices.into_iter().zip(channels.iter().copied()) {
+        let tuning = if channel == PERCUSSION {
+            &untuned
+        } else {
+            tuning
+        };
+        let program = voice.instrument.map(u7::new);
+        let mut track = Vec::new();
+
+        write_voice(
+            notes.into_iter(),
+            tuning,
+            program,
+            TICKS_PER_BEAT,
+            u4::new(channel),
+            &mut spare,
+            &mut track,
+        );
+
+        track.push(TrackEvent {
+            delta: u28::new(0),
+            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+        });
+
+        smf.tracks.push(track);
+    }
 
     smf.save(to)
 }
+
+/// Decide the channel of every voice. Voices without a channel of their own
+/// get the first channel which no voice asks for, skipping the percussion
+/// channel, or the first channel once there are none left.
+fn channels(voices: impl Iterator<Item = Voice> + Clone) -> Vec<u8> {
+    let taken: Vec<_> = voices.clone().filter_map(|voice| voice.channel).collect();
+    let mut free = (0..16).filter(|channel| *channel != PERCUSSION && !taken.contains(channel));
+
+    voices
+        .map(|voice| {
+            voice
+                .channel
+                .unwrap_or_else(|| free.next().unwrap_or_default())
+        })
+        .collect()
+}
+
+/// Make the meta events which describe a piece. The copyright notice comes
+/// first, as MIDI asks for, and the title names the whole sequence.
+fn metadata_events(metadata: &Metadata) -> Vec<MetaMessage<'_>> {
+    let mut events = Vec::new();
+
+    if let Some(copyright) = &metadata.copyright {
+        events.push(MetaMessage::Copyright(copyright.as_bytes()));
+    }
+
+    if let Some(title) = &metadata.title {
+        events.push(MetaMessage::TrackName(title.as_bytes()));
+    }
+
+    if let Some(composer) = &metadata.composer {
+        events.push(MetaMessage::Text(composer.as_bytes()));
+    }
+
+    if let Some(description) = &metadata.description {
+        events.push(MetaMessage::Text(description.as_bytes()));
+    }
+
+    events
+}
+
+/// Make the meta event for a time signature, with a metronome click on every
+/// beat.
+fn time_signature(time: TimeSignature) -> MetaMessage<'static> {
+    // The unit is given as a power of two, and the metronome in MIDI clocks,
+    // of which there are 24 per quarter note.
+    let unit = time.unit.trailing_zeros() as u8;
+    let clocks = (96 / u32::from(time.unit)).clamp(1, 255) as u8;
+    MetaMessage::TimeSignature(time.beats, unit, clocks, 8)
+}
+
+#[cfg(test)]
+mod tests {
+    use midly::MetaMessage;
+    use mm_eval::{Metadata, TimeSignature, Voice};
+
+    use super::{channels, metadata_events, time_signature};
+
+    #[test]
+    fn time_signatures() {
+        let time = |beats, unit| time_signature(TimeSignature::new(beats, unit).unwrap());
+
+        assert_eq!(MetaMessage::TimeSignature(4, 2, 24, 8), time(4, 4));
+        assert_eq!(MetaMessage::TimeSignature(6, 3, 12, 8), time(6, 8));
+        assert_eq!(MetaMessage::TimeSignature(2, 1, 48, 8), time(2, 2));
+    }
+
+    #[test]
+    fn metadata() {
+        let metadata = Metadata {
+            title: Some("Etude".into()),
+            copyright: Some("(c) 2024".into()),
+            description: Some("For piano".into()),
+            ..Metadata::default()
+        };
+
+        let expected = vec![
+            MetaMessage::Copyright(b"(c) 2024"),
+            MetaMessage::TrackName(b"Etude"),
+            MetaMessage::Text(b"For piano"),
+        ];
+
+        assert_eq!(expected, metadata_events(&metadata));
+        assert!(metadata_events(&Metadata::default()).is_empty());
+    }
+
+    #[test]
+    fn channels_for_voices() {
+        let voices = [
+            Voice::instrument(1).unwrap(),
+            Voice::channel(1).unwrap(),
+            Voice::default(),
+            Voice::channel(10).unwrap(),
+        ];
+
+        assert_eq!(vec![1, 0, 2, 9], channels(voices.into_iter()));
+    }
+}
@@ -4,6 +4,13 @@ use std::ops::Add;
 use midly::num::u7;
 use mm_eval::note::Note;
 
+/// How many cents a semitone and an octave span.
+const SEMITONE: isize = 100;
+const OCTAVE: isize = 12 * SEMITONE;
+
+/// A pitch, given as the number of cents it lies above A4 in twelve-tone equal
+/// temperament. A pitch is played on the key of the semitone at or below it,
+/// raised by the cents it lies above that key.
 #[derive(Clone, Debug, Copy, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct Pitch(isize);
 
@@ -11,53 +18,107 @@ impl Pitch {
     pub const A4: Self = Self(0);
 
     pub const fn to_midi_key(self) -> Option<u7> {
-        match self.0 + 69 {
+        match self.to_key() {
             value @ 0..=127 => Some(u7::new(value as u8)),
             _ => None,
         }
     }
 
     pub const fn to_midi_key_saturating(self) -> u7 {
-        match self.0 + 69 {
+        match self.to_key() {
             value @ 0..=127 => u7::new(value as u8),
             128.. => u7::max_value(),
             _ => u7::new(0),
         }
     }
 
-    /// Get the offset from this pitch to the given pitch.
+    /// Get the MIDI key number of this pitch, which may lie outside of the
+    /// range of keys MIDI allows.
+    pub const fn to_key(self) -> isize {
+        self.0.div_euclid(SEMITONE).saturating_add(69)
+    }
+
+    /// Get how many cents this pitch lies above its key.
+    pub const fn above_key(self) -> isize {
+        self.0.rem_euclid(SEMITONE)
+    }
+
+    /// Get the offset from this pitch to the given pitch in cents.
     pub const fn offset(&self, to: &Self) -> isize {
-        self.0 - to.0
+        self.0.saturating_sub(to.0)
     }
 
-    /// Get the closest A below this pitch. If this pitch is itself
+    /// Get the closest A at or below this pitch.
     pub const fn a_below(&self) -> Self {
-        let off = if self.0 < 0 { 1 } else { 0 };
-        Self(((self.0 + off) / 12 - off) * 12)
+        Self(self.0.div_euclid(OCTAVE) * OCTAVE)
     }
 }
 
 impl Note for Pitch {
+    /// Parse a note name like `C`, `Eb4`, `F#3` or `C4'50`. A note name consists
+    /// of a letter from `A` to `G`, followed by any number of sharps (`#`),
+    /// double sharps (`x`) and flats (`b`) or a single natural (`n`), optionally
+    /// an octave number, and optionally an apostrophe with a number of cents to
+    /// raise the note by. Notes without an octave number are in octave 4.
     fn parse(name: &str) -> Option<Self> {
-        Some(match name {
-            "A" => Pitch(0),
-            "B" => Pitch(2),
-            "C" => Pitch(-9),
-            "D" => Pitch(-7),
-            "E" => Pitch(-5),
-            "F" => Pitch(-4),
-            "G" => Pitch(-2),
+        let (name, cents) = match name.split_once('\'') {
+            Some((name, cents)) if cents.bytes().all(|c| c.is_ascii_digit()) => {
+                (name, cents.parse::<isize>().ok()?)
+            }
+
+            Some(_) => return None,
+            None => (name, 0),
+        };
+
+        let mut chars = name.chars();
+        let base = match chars.next()? {
+            'A' => 0,
+            'B' => 2,
+            'C' => -9,
+            'D' => -7,
+            'E' => -5,
+            'F' => -4,
+            'G' => -2,
 
             _ => return None,
-        })
+        };
+
+        let rest = chars.as_str();
+        let digits = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let (accidentals, octave) = rest.split_at(digits);
+
+        let accidental = match accidentals {
+            "n" => 0,
+            _ => accidentals.chars().try_fold(0, |sum, c| match c {
+                '#' => Some(sum + 1),
+                'x' => Some(sum + 2),
+                'b' => Some(sum - 1),
+                _ => None,
+            })?,
+        };
+
+        let octave = match octave {
+            "" => 4,
+            _ => octave.parse::<isize>().ok()?,
+        };
+
+        let octave = octave.checked_sub(4)?.checked_mul(OCTAVE)?;
+        let semitones = (base + accidental as isize).checked_mul(SEMITONE)?;
+        Some(Self(octave.checked_add(semitones)?.checked_add(cents)?))
+    }
+
+    fn add_sharp(&self, by: isize) -> Option<Self> {
+        self.0.checked_add(by.checked_mul(SEMITONE)?).map(Self)
     }
 
-    fn add_sharp(&self, by: usize) -> Self {
-        Self(self.0 + by as isize)
+    fn add_octave(&self, by: isize) -> Option<Self> {
+        self.0.checked_add(by.checked_mul(OCTAVE)?).map(Self)
     }
 
-    fn add_octave(&self, by: isize) -> Self {
-        Self(self.0 + 12 * by)
+    fn invert(&self, axis: &Self) -> Option<Self> {
+        axis.0.checked_mul(2)?.checked_sub(self.0).map(Self)
     }
 }
 
@@ -65,7 +126,7 @@ impl Add<Interval> for Pitch {
     type Output = Self;
 
     fn add(self, rhs: Interval) -> Self {
-        Self(self.0 + rhs.0)
+        Self(self.0.saturating_add(rhs.0))
     }
 }
 
@@ -79,38 +140,207 @@ impl Add<Pitch> for Interval {
 
 impl fmt::Display for Pitch {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let in_octave = (self.0 + 9).rem_euclid(12) - 9;
-
-        let name = match in_octave {
-            -9 => "C",
-            -8 => "C#",
-            -7 => "D",
-            -6 => "D#",
-            -5 => "E",
-            -4 => "F",
-            -3 => "F#",
-            -2 => "G",
-            -1 => "G#",
-            0 => "A",
-            1 => "A#",
-            2 => "B",
+        self.spelled(Spelling::Sharps).fmt(f)
+    }
+}
 
-            _ => unreachable!(),
-        };
+/// How to spell pitches which lie between two natural notes.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Spelling {
+    #[default]
+    Sharps,
+    Flats,
+}
 
-        let octave = {
-            let v = self.0 + 9;
-            let off = if v < 0 { -1 } else { 0 };
-            v / 12 + off + 4
+/// A pitch which is displayed with a specific [`Spelling`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Spelled(Pitch, Spelling);
+
+impl Pitch {
+    /// Display this pitch using the given spelling for accidentals.
+    pub const fn spelled(self, spelling: Spelling) -> Spelled {
+        Spelled(self, spelling)
+    }
+}
+
+impl fmt::Display for Spelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self(pitch, spelling) = *self;
+
+        // Semitones above the C in the same octave.
+        let above_c = pitch.0.div_euclid(SEMITONE) + 9;
+        let in_octave = above_c.rem_euclid(12);
+        let octave = above_c.div_euclid(12) + 4;
+
+        let name = match (in_octave, spelling) {
+            (0, _) => "C",
+            (1, Spelling::Sharps) => "C#",
+            (1, Spelling::Flats) => "Db",
+            (2, _) => "D",
+            (3, Spelling::Sharps) => "D#",
+            (3, Spelling::Flats) => "Eb",
+            (4, _) => "E",
+            (5, _) => "F",
+            (6, Spelling::Sharps) => "F#",
+            (6, Spelling::Flats) => "Gb",
+            (7, _) => "G",
+            (8, Spelling::Sharps) => "G#",
+            (8, Spelling::Flats) => "Ab",
+            (9, _) => "A",
+            (10, Spelling::Sharps) => "A#",
+            (10, Spelling::Flats) => "Bb",
+            (11, _) => "B",
+
+            _ => unreachable!(),
         };
 
-        write!(f, "{name}{octave}")
+        match pitch.above_key() {
+            0 => write!(f, "{name}{octave}"),
+            cents => write!(f, "{name}{octave}'{cents}"),
+        }
     }
 }
 
 pub struct Interval(isize);
 
 impl Interval {
-    pub const SEMITONE: Self = Self(1);
-    pub const WHOLETONE: Self = Self(2);
+    pub const SEMITONE: Self = Self(SEMITONE);
+    pub const WHOLETONE: Self = Self(2 * SEMITONE);
+}
+
+#[cfg(test)]
+mod tests {
+    use mm_eval::chord::Chord;
+    use mm_eval::key::{Key, Mode};
+    use mm_eval::note::Note;
+
+    use super::{Pitch, Spelling};
+
+    fn parse(name: &str) -> Pitch {
+        Pitch::parse(name).unwrap()
+    }
+
+    #[test]
+    fn accidentals() {
+        assert_eq!(parse("A#"), parse("Bb"));
+        assert_eq!(parse("B"), parse("Cb5"));
+        assert_eq!(parse("D"), parse("Cx"));
+        assert_eq!(parse("D"), parse("Ebb"));
+        assert_eq!(parse("B"), parse("Bn"));
+    }
+
+    #[test]
+    fn octaves() {
+        assert_eq!(Pitch::A4, parse("A4"));
+        assert_eq!(Pitch::A4, parse("A"));
+        assert_eq!(Some(60), parse("C4").to_midi_key().map(u8::from));
+        assert_eq!(
+            Some(0),
+            parse("C0")
+                .add_octave(-1)
+                .unwrap()
+                .to_midi_key()
+                .map(u8::from)
+        );
+    }
+
+    #[test]
+    fn overflow() {
+        let high = parse(&format!("A{}", isize::MAX / 1200));
+        assert_eq!(None, high.to_midi_key());
+        assert_eq!(None, high.add_octave(5));
+        assert_eq!(None, high.add_sharp(isize::MAX / 100));
+        assert_eq!(None, high.invert(&parse("A0")));
+        assert_eq!(None, parse("A").add_octave(isize::MAX));
+        assert_eq!(None, Pitch::parse("C999999999999999999"));
+    }
+
+    #[test]
+    fn inversion() {
+        assert_eq!(parse("C"), parse("E").invert(&parse("D")).unwrap());
+        assert_eq!(parse("A2"), parse("Eb4").invert(&parse("F#3")).unwrap());
+        assert_eq!(parse("D"), parse("D").invert(&parse("D")).unwrap());
+    }
+
+    #[test]
+    fn degrees() {
+        let key = Key {
+            tonic: parse("D"),
+            mode: Mode::Dorian,
+        };
+
+        assert_eq!(parse("D4"), key.degree(1).unwrap());
+        assert_eq!(parse("F4"), key.degree(3).unwrap());
+        assert_eq!(parse("C5"), key.degree(7).unwrap());
+        assert_eq!(parse("E5"), key.degree(9).unwrap());
+        assert_eq!(parse("C4"), key.degree(0).unwrap());
+    }
+
+    #[test]
+    fn chords() {
+        let notes = |symbol| -> Vec<_> {
+            let chord = Chord::<Pitch>::parse(symbol).unwrap();
+            chord.notes().unwrap()
+        };
+
+        let expected: Vec<_> = ["G4", "B4", "D5", "F5", "Ab5"].map(parse).into();
+        assert_eq!(expected, notes("G7b9"));
+
+        let expected: Vec<_> = ["F#4", "A4", "C#5", "E5"].map(parse).into();
+        assert_eq!(expected, notes("F#m7"));
+    }
+
+    #[test]
+    fn octaves_before_chords() {
+        // Chords made of only a number are notes in that octave instead.
+        assert_eq!(parse("C4").add_octave(2).unwrap(), parse("C6"));
+        assert_eq!(parse("C4").add_octave(9).unwrap(), parse("C13"));
+        assert_eq!(None, Pitch::parse("Cadd6"));
+        assert_eq!(None, Pitch::parse("Cdom9"));
+    }
+
+    #[test]
+    fn cents() {
+        assert_eq!(parse("C4'50"), parse("C'50"));
+        assert_eq!(parse("C4'50"), parse("B3'150"));
+        assert_eq!(parse("C#4"), parse("C4'100"));
+        assert_eq!(parse("A4"), parse("A'0"));
+
+        let pitch = parse("Eb3'25");
+        assert_eq!(parse("Eb3").to_key(), pitch.to_key());
+        assert_eq!(25, pitch.above_key());
+        assert_eq!(parse("Eb4'25"), pitch.add_octave(1).unwrap());
+        assert_eq!(parse("E3'25"), pitch.add_sharp(1).unwrap());
+        assert_eq!(parse("F3'75"), pitch.invert(&parse("E3'50")).unwrap());
+
+        assert_eq!(None, Pitch::parse("C'"));
+        assert_eq!(None, Pitch::parse("C'x"));
+        assert_eq!(None, Pitch::parse("C'-5"));
+    }
+
+    #[test]
+    fn not_notes() {
+        assert_eq!(None, Pitch::parse("H"));
+        assert_eq!(None, Pitch::parse("a"));
+        assert_eq!(None, Pitch::parse("Bad"));
+        assert_eq!(None, Pitch::parse("Bnb"));
+        assert_eq!(None, Pitch::parse("C4b"));
+    }
+
+    #[test]
+    fn spelling() {
+        for name in ["C#4", "Eb3", "B0", "Bb0", "A4", "Db4'50", "Cb1'1"] {
+            let pitch = parse(name);
+            let sharps = pitch.spelled(Spelling::Sharps).to_string();
+            let flats = pitch.spelled(Spelling::Flats).to_string();
+
+            assert_eq!(pitch, parse(&sharps));
+            assert_eq!(pitch, parse(&flats));
+        }
+
+        assert_eq!("C#4", parse("Db").to_string());
+        assert_eq!("C4'50", parse("B3'150").to_string());
+        assert_eq!("Db4", parse("C#").spelled(Spelling::Flats).to_string());
+        assert_eq!("B2", parse("Cb3").to_string());
+    }
 }
@@ -0,0 +1,118 @@
+use midly::num::{u24, u28};
+use midly::{MetaMessage, TrackEvent, TrackEventKind};
+use mm_eval::{Length, Tempo, Time};
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, ToPrimitive};
+
+/// How many steps per beat gradual changes of tempo are divided into.
+const RAMP_STEPS_PER_BEAT: usize = 8;
+
+/// Write the given changes of tempo to the given track as tempo meta events.
+/// Gradual changes are approximated by a number of smaller changes.
+///
+/// `ticks_per_beat` determines how many ticks a note of length `1` should last.
+pub fn write_tempo(
+    changes: impl Iterator<Item = (Time, Length, Tempo, Tempo)>,
+    ticks_per_beat: usize,
+    track: &mut Vec<TrackEvent>,
+) {
+    let to_ticks = |at: &BigRational| {
+        (at * BigRational::from_usize(ticks_per_beat).unwrap())
+            .to_usize()
+            .expect("start times are reasonably small")
+    };
+
+    let mut events = Vec::new();
+
+    for (start, length, from, to) in changes {
+        let Length::Bounded(length) = length else {
+            events.push((to_ticks(&start.0), to));
+            continue;
+        };
+
+        if from == to {
+            events.push((to_ticks(&start.0), to));
+            continue;
+        }
+
+        let steps = (&length * ratio(RAMP_STEPS_PER_BEAT, 1))
+            .ceil()
+            .to_usize()
+            .unwrap_or(1)
+            .max(1);
+
+        // Every step is played at the tempo halfway through it.
+        for step in 0..steps {
+            let at = &start.0 + &length * ratio(step, steps);
+            let fraction = ratio(2 * step + 1, 2 * steps);
+            events.push((to_ticks(&at), from.towards(&to, &fraction)));
+        }
+    }
+
+    // Changes at the same time keep their order, such that the last one wins.
+    events.sort_by_key(|(at, _)| *at);
+
+    let mut at = 0;
+    for (now, tempo) in events {
+        let delta = u28::new(u32::try_from(now - at).unwrap());
+        at = now;
+
+        let micros = tempo.to_micros_per_beat().min(u24::max_value().as_int());
+        track.push(TrackEvent {
+            delta,
+            kind: TrackEventKind::Meta(MetaMessage::Tempo(u24::new(micros))),
+        });
+    }
+}
+
+fn ratio(numerator: usize, denominator: usize) -> BigRational {
+    BigRational::from_usize(numerator).unwrap() / BigRational::from_usize(denominator).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use midly::num::u24;
+    use midly::{MetaMessage, TrackEventKind};
+    use mm_eval::{Length, Tempo, Time};
+    use num_rational::BigRational;
+    use num_traits::FromPrimitive;
+
+    use super::write_tempo;
+
+    fn bpm(bpm: usize) -> Tempo {
+        Tempo(BigRational::from_usize(bpm).unwrap())
+    }
+
+    #[test]
+    fn gradual() {
+        let changes = [
+            (Time::zero(), Length::zero(), bpm(120), bpm(120)),
+            (Time::zero(), Length::one(), bpm(60), bpm(120)),
+            (
+                Time(BigRational::from_usize(1).unwrap()),
+                Length::zero(),
+                bpm(120),
+                bpm(120),
+            ),
+        ];
+
+        let mut track = Vec::new();
+        write_tempo(changes.into_iter(), 128, &mut track);
+
+        let events: Vec<_> = track
+            .iter()
+            .map(|event| match event.kind {
+                TrackEventKind::Meta(MetaMessage::Tempo(micros)) => (event.delta.as_int(), micros),
+                _ => panic!("expected only tempo events"),
+            })
+            .collect();
+
+        // Eight steps from 60 to 120 beats per minute, each at the tempo
+        // halfway through it.
+        assert_eq!(10, events.len());
+        assert_eq!((0, u24::new(500_000)), events[0]);
+        assert_eq!((0, u24::new(941_176)), events[1]);
+        assert_eq!((16, u24::new(842_105)), events[2]);
+        assert_eq!((16, u24::new(500_000)), events[9]);
+    }
+}
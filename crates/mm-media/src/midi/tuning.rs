@@ -0,0 +1,550 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use midly::num::u7;
+
+use super::Pitch;
+
+/// The frequency of A4 in twelve-tone equal temperament, in Hz.
+const CONCERT_A: f64 = 440.0;
+
+/// How far a pitch bend bends a note at most, in cents, which is what General
+/// MIDI synthesizers use unless told otherwise.
+const BEND_RANGE: f64 = 200.0;
+
+/// A tuning, which decides the frequency of every key that notes are written
+/// with. A tuning consists of a scale, which repeats at some period, and a
+/// mapping of keys to degrees of that scale, following the Scala `.scl` and
+/// `.kbm` file formats.
+///
+/// Note names, accidentals and transpositions pick keys, counting twelve keys
+/// to the octave just like a piano keyboard, and the tuning gives those keys
+/// their pitch. Notes raised by some cents, like `C4'50`, are played that many
+/// cents above their key, which reaches the degrees of scales with more than
+/// twelve degrees that no key plays, such as `C4'63` for the step above C4 in
+/// 19-tone equal temperament.
+///
+/// Unless a keyboard mapping says otherwise, every octave of twelve keys spans
+/// one period of the scale, and each key plays the degree closest to where it
+/// lies in equal temperament. An octave up is then one period up in any tuning,
+/// and in 19-tone equal temperament D is three steps above C and E six.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tuning {
+    /// The size of every degree of the scale above its first degree in cents,
+    /// where the last degree is the period at which the scale repeats.
+    degrees: Vec<f64>,
+    keyboard: Keyboard,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Keyboard {
+    /// The keys which are tuned. Other keys are not played at all.
+    keys: RangeInclusive<isize>,
+    /// The scale degree of every key in a pattern which repeats from the
+    /// middle key onwards, or `None` for keys which are not played. An empty
+    /// pattern spreads every twelve keys over one period.
+    pattern: Vec<Option<isize>>,
+    /// The key which plays the first degree of the scale.
+    middle: isize,
+    /// The key whose frequency is given.
+    reference: isize,
+    frequency: f64,
+    /// How many degrees a repetition of the pattern spans.
+    period: isize,
+}
+
+/// The key and pitch bend which realise a pitch in MIDI.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Tone {
+    pub key: u7,
+    /// The pitch bend, from `-8192` to `8191`, which assumes that synthesizers
+    /// bend by at most two semitones.
+    pub bend: i16,
+}
+
+/// Why a scale or keyboard mapping could not be read.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// A line, counting from one, which does not hold what it should.
+    Invalid { line: usize, expected: &'static str },
+    /// A file which ends before it holds everything it should.
+    Incomplete { expected: &'static str },
+}
+
+impl Tuning {
+    /// Create a tuning which divides the octave into `divisions` equal steps,
+    /// where A4 is 440 Hz.
+    pub fn equal(divisions: usize) -> Option<Self> {
+        if divisions == 0 {
+            return None;
+        }
+
+        let step = 1200.0 / divisions as f64;
+        let degrees = (1..=divisions).map(|degree| degree as f64 * step).collect();
+
+        Some(Self::with_degrees(degrees))
+    }
+
+    /// Read a tuning from the contents of a Scala `.scl` file, which is played
+    /// with every octave of keys spanning one period from middle C, and A4 at
+    /// 440 Hz.
+    pub fn parse(scale: &str) -> Result<Self, Error> {
+        let mut lines = Lines::new(scale);
+
+        lines.next("a description")?;
+        let (line, count) = lines.next("the number of notes")?;
+        let count = first_word(count)
+            .parse::<usize>()
+            .ok()
+            .filter(|count| *count > 0)
+            .ok_or(Error::Invalid {
+                line,
+                expected: "a positive number of notes",
+            })?;
+
+        let mut degrees = Vec::with_capacity(count);
+        for _ in 0..count {
+            let (line, degree) = lines.next("a note of the scale")?;
+            let degree = cents(first_word(degree)).ok_or(Error::Invalid {
+                line,
+                expected: "a note in cents like '701.955' or as a ratio like '3/2'",
+            })?;
+
+            degrees.push(degree);
+        }
+
+        Ok(Self::with_degrees(degrees))
+    }
+
+    /// Map keys to the degrees of this tuning as given by the contents of a
+    /// Scala `.kbm` file.
+    pub fn with_keyboard(self, keyboard: &str) -> Result<Self, Error> {
+        let mut lines = Lines::new(keyboard);
+
+        let mut number = |expected| -> Result<isize, Error> {
+            let (line, number) = lines.next(expected)?;
+            first_word(number)
+                .parse()
+                .map_err(|_| Error::Invalid { line, expected })
+        };
+
+        let size = number("the size of the pattern")?;
+        let first = number("the first key to tune")?;
+        let last = number("the last key to tune")?;
+        let middle = number("the key of the first degree")?;
+        let reference = number("the key to give a frequency to")?;
+
+        let (line, frequency) = lines.next("the frequency of the reference key")?;
+        let frequency = first_word(frequency)
+            .parse::<f64>()
+            .ok()
+            .filter(|frequency| *frequency > 0.0)
+            .ok_or(Error::Invalid {
+                line,
+                expected: "a positive frequency like '440.0'",
+            })?;
+
+        let (line, period) = lines.next("the degree at which the pattern repeats")?;
+        let period = first_word(period)
+            .parse::<isize>()
+            .ok()
+            .filter(|period| *period >= 0)
+            .ok_or(Error::Invalid {
+                line,
+                expected: "the degree at which the pattern repeats",
+            })?;
+
+        // Keys at the end of the pattern which are left out are not played.
+        let mut pattern = Vec::new();
+        for _ in 0..size {
+            let degree = match lines.next("a degree or 'x'") {
+                Ok((line, degree)) => match first_word(degree) {
+                    "x" => None,
+                    degree => Some(degree.parse().map_err(|_| Error::Invalid {
+                        line,
+                        expected: "a degree or 'x'",
+                    })?),
+                },
+
+                Err(_) => None,
+            };
+
+            pattern.push(degree);
+        }
+
+        let period = match (period, pattern.is_empty()) {
+            (0, _) | (_, true) => self.degrees.len() as isize,
+            (period, false) => period,
+        };
+
+        let keyboard = Keyboard {
+            keys: first..=last,
+            pattern,
+            middle,
+            reference,
+            frequency,
+            period,
+        };
+
+        if keyboard.degree(reference).is_none() {
+            return Err(Error::Invalid {
+                line: lines.line_of(4),
+                expected: "a key to give a frequency to which plays a degree",
+            });
+        }
+
+        Ok(Self { keyboard, ..self })
+    }
+
+    /// Get how many cents the given pitch lies above A4 at 440 Hz, or `None` if
+    /// this tuning does not play its key.
+    pub fn cents(&self, pitch: Pitch) -> Option<f64> {
+        let key = pitch.to_key();
+        if !self.keyboard.keys.contains(&key) {
+            return None;
+        }
+
+        let degree = self.keyboard.degree(key)?;
+        let reference = self
+            .keyboard
+            .degree(self.keyboard.reference)
+            .expect("the reference key plays a degree");
+
+        let frequency = 1200.0 * (self.keyboard.frequency / CONCERT_A).log2();
+        let above = pitch.above_key() as f64;
+        Some(frequency + self.degree(degree) - self.degree(reference) + above)
+    }
+
+    /// Get the key and pitch bend which play the given pitch in this tuning,
+    /// or `None` if this tuning does not play it.
+    pub fn tone(&self, pitch: Pitch) -> Option<Tone> {
+        let cents = self.cents(pitch)?;
+        let key = (cents / 100.0).round();
+        let bend = (cents - 100.0 * key) / BEND_RANGE * 8192.0;
+
+        let key = (key as isize).saturating_add(69).clamp(0, 127) as u8;
+        let bend = bend.round().clamp(-8192.0, 8191.0) as i16;
+
+        Some(Tone {
+            key: u7::new(key),
+            bend,
+        })
+    }
+
+    fn with_degrees(degrees: Vec<f64>) -> Self {
+        let period = degrees.len() as isize;
+        Self {
+            degrees,
+            keyboard: Keyboard {
+                period,
+                ..Keyboard::default()
+            },
+        }
+    }
+
+    /// Get the size of the given degree of the scale in cents.
+    fn degree(&self, degree: isize) -> f64 {
+        let count = self.degrees.len() as isize;
+        let period = self.degrees[self.degrees.len() - 1];
+
+        let repeats = degree.div_euclid(count) as f64;
+        let within = match degree.rem_euclid(count) {
+            0 => 0.0,
+            within => self.degrees[within as usize - 1],
+        };
+
+        repeats * period + within
+    }
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Self::equal(12).expect("twelve is a positive number of divisions")
+    }
+}
+
+impl Keyboard {
+    /// Get the scale degree the given key plays, if any. Keys so far out that
+    /// their degree does not fit in an `isize` play nothing.
+    fn degree(&self, key: isize) -> Option<isize> {
+        let offset = key.checked_sub(self.middle)?;
+        if self.pattern.is_empty() {
+            // Every twelve keys span one period, where keys which lie halfway
+            // between two degrees play the higher one.
+            let octaves = offset.div_euclid(12);
+            let within = offset
+                .rem_euclid(12)
+                .checked_mul(self.period.checked_mul(2)?)?
+                .checked_add(12)?
+                / 24;
+
+            return octaves.checked_mul(self.period)?.checked_add(within);
+        }
+
+        let size = self.pattern.len() as isize;
+        let degree = self.pattern[offset.rem_euclid(size) as usize]?;
+        offset
+            .div_euclid(size)
+            .checked_mul(self.period)?
+            .checked_add(degree)
+    }
+}
+
+impl Default for Keyboard {
+    fn default() -> Self {
+        Self {
+            keys: isize::MIN..=isize::MAX,
+            pattern: Vec::new(),
+            middle: 60,
+            reference: 69,
+            frequency: CONCERT_A,
+            period: 12,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid { line, expected } => write!(f, "expected {expected} on line {line}"),
+            Self::Incomplete { expected } => write!(f, "expected {expected} before the end"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The lines of a Scala file which are not comments, along with their line
+/// numbers.
+struct Lines<'a> {
+    lines: Vec<(usize, &'a str)>,
+    at: usize,
+}
+
+impl<'a> Lines<'a> {
+    fn new(source: &'a str) -> Self {
+        let lines = source
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.starts_with('!'))
+            .map(|(index, line)| (index + 1, line.trim()))
+            .collect();
+
+        Self { lines, at: 0 }
+    }
+
+    fn next(&mut self, expected: &'static str) -> Result<(usize, &'a str), Error> {
+        let line = self.lines.get(self.at).copied();
+        self.at += 1;
+        line.ok_or(Error::Incomplete { expected })
+    }
+
+    /// Get the line number of the line at the given index among the lines
+    /// which are not comments.
+    fn line_of(&self, index: usize) -> usize {
+        self.lines[index].0
+    }
+}
+
+fn first_word(line: &str) -> &str {
+    line.split_whitespace().next().unwrap_or_default()
+}
+
+/// Read the size of a note in a scale, given in cents if it has a decimal point
+/// and as a ratio or a whole number otherwise.
+fn cents(note: &str) -> Option<f64> {
+    if note.contains('.') {
+        return note.parse().ok();
+    }
+
+    let (numerator, denominator) = note.split_once('/').unwrap_or((note, "1"));
+    let numerator: u64 = numerator.parse().ok()?;
+    let denominator: u64 = denominator.parse().ok()?;
+
+    if numerator == 0 || denominator == 0 {
+        return None;
+    }
+
+    Some(1200.0 * (numerator as f64 / denominator as f64).log2())
+}
+
+#[cfg(test)]
+mod tests {
+    use midly::num::u7;
+    use mm_eval::note::Note;
+
+    use super::{Error, Tone, Tuning};
+    use crate::midi::Pitch;
+
+    const JUST: &str = "! just.scl
+!
+Five-limit just intonation
+ 12
+!
+ 16/15
+ 9/8
+ 6/5
+ 5/4
+ 4/3
+ 45/32
+ 3/2
+ 8/5
+ 5/3
+ 9/5
+ 15/8
+ 2/1
+";
+
+    fn parse(name: &str) -> Pitch {
+        Pitch::parse(name).unwrap()
+    }
+
+    fn close(expected: f64, actual: Option<f64>) -> bool {
+        actual.is_some_and(|actual| (expected - actual).abs() < 1e-6)
+    }
+
+    #[test]
+    fn twelve_tone() {
+        let tuning = Tuning::default();
+
+        assert!(close(0.0, tuning.cents(parse("A4"))));
+        assert!(close(-900.0, tuning.cents(parse("C4"))));
+
+        let tone = tuning.tone(parse("C4")).unwrap();
+        assert_eq!(60, u8::from(tone.key));
+        assert_eq!(0, tone.bend);
+    }
+
+    #[test]
+    fn nineteen_tone() {
+        let tuning = Tuning::equal(19).unwrap();
+        let step = 1200.0 / 19.0;
+
+        // A4 is fourteen steps above middle C, and keeps its frequency.
+        assert!(close(0.0, tuning.cents(parse("A4"))));
+        assert!(close(-14.0 * step, tuning.cents(parse("C4"))));
+        assert!(close(16.0 * step, tuning.cents(parse("G5"))));
+
+        // Eleven steps make a fifth, and an octave up is a period up.
+        let c = tuning.cents(parse("C4")).unwrap();
+        assert!(close(c + 694.736_842, tuning.cents(parse("G4"))));
+        assert!(close(
+            c + 1200.0,
+            tuning.cents(parse("C4").add_octave(1).unwrap())
+        ));
+        assert!(close(c + 3.0 * step, tuning.cents(parse("D4"))));
+        assert!(close(c + 10.0 * step, tuning.cents(parse("F#4"))));
+    }
+
+    #[test]
+    fn cents() {
+        let tuning = Tuning::equal(19).unwrap();
+        let step = 1200.0 / 19.0;
+
+        // The degree between C4 and C#4 is reached by raising C4.
+        let c = tuning.cents(parse("C4")).unwrap();
+        assert!(close(c + 63.0, tuning.cents(parse("C4'63"))));
+        assert!((c + step - tuning.cents(parse("C4'63")).unwrap()).abs() < 0.2);
+
+        // Quarter tones are played with a pitch bend of a quarter tone.
+        assert_eq!(
+            Some(Tone {
+                key: u7::new(70),
+                bend: -2048,
+            }),
+            Tuning::default().tone(parse("A4'50"))
+        );
+    }
+
+    #[test]
+    fn just_intonation() {
+        let tuning = Tuning::parse(JUST).unwrap();
+
+        // A major sixth above middle C is a 5/3, and A4 stays at 440 Hz.
+        let c = tuning.cents(parse("C4")).unwrap();
+        assert!(close(0.0, tuning.cents(parse("A4"))));
+        assert!(close(c + 701.955_001, tuning.cents(parse("G4"))));
+        assert!(close(c + 1200.0, tuning.cents(parse("C5"))));
+
+        // Middle C ends up about sixteen cents above equal temperament.
+        let c = tuning.tone(parse("C4")).unwrap();
+        assert_eq!(60, u8::from(c.key));
+        assert_eq!(641, c.bend);
+    }
+
+    #[test]
+    fn keyboards() {
+        // A pattern of twelve keys, where the black keys are not played and C4
+        // is tuned to 256 Hz.
+        let keyboard = "12\n0\n127\n60\n60\n256.0\n7\n0\nx\n1\nx\n2\n3\nx\n4\nx\n5\nx\n6\n";
+        let tuning = Tuning::parse("major\n7\n9/8\n5/4\n4/3\n3/2\n5/3\n15/8\n2/1\n")
+            .unwrap()
+            .with_keyboard(keyboard)
+            .unwrap();
+
+        let c = 1200.0 * (256.0f64 / 440.0).log2();
+        assert!(close(c, tuning.cents(parse("C4"))));
+        assert!(close(c + 386.313_714, tuning.cents(parse("E4"))));
+        assert!(close(c + 1200.0, tuning.cents(parse("C5"))));
+        assert_eq!(None, tuning.cents(parse("C#4")));
+    }
+
+    #[test]
+    fn tones() {
+        let tuning = Tuning::equal(19).unwrap();
+
+        // D4 lies eleven steps below A4, about five cents above where it is in
+        // twelve-tone equal temperament.
+        assert_eq!(
+            Some(Tone {
+                key: u7::new(62),
+                bend: 216,
+            }),
+            tuning.tone(parse("D4"))
+        );
+    }
+
+    #[test]
+    fn far_out() {
+        let highest = parse(&format!("A{}", isize::MAX / 1200));
+        let lowest = parse("A").add_octave(isize::MIN / 1200).unwrap();
+
+        // Keys beyond the range of MIDI play the highest or lowest key.
+        let tone = Tuning::default().tone(highest).unwrap();
+        assert_eq!(127, u8::from(tone.key));
+        let tone = Tuning::default().tone(lowest).unwrap();
+        assert_eq!(0, u8::from(tone.key));
+
+        // Degrees which do not fit are not played at all.
+        let keyboard = format!(
+            "1\n{}\n{}\n60\n69\n440.0\n{}\n0\n",
+            isize::MIN,
+            isize::MAX,
+            1isize << 58
+        );
+        let tuning = Tuning::default().with_keyboard(&keyboard).unwrap();
+        assert_eq!(None, tuning.tone(highest));
+        assert_eq!(None, tuning.tone(lowest));
+        assert!(tuning.tone(parse("C4")).is_some());
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(
+            Err(Error::Incomplete {
+                expected: "the number of notes"
+            }),
+            Tuning::parse("! only a comment\nnothing else")
+        );
+
+        assert!(matches!(
+            Tuning::parse("bad\n2\n100.0\nthree halves\n"),
+            Err(Error::Invalid { line: 4, .. })
+        ));
+
+        assert!(matches!(
+            Tuning::default().with_keyboard("12\n0\n127\n60\n61\n440\n12\n0\nx\n"),
+            Err(Error::Invalid { line: 5, .. })
+        ));
+    }
+}
@@ -1,10 +1,10 @@
 use std::collections::HashSet;
 
-use mm_eval::span::Span;
-use mm_eval::{Length, Time};
+use mm_eval::eval::Played;
+use mm_eval::{Length, TimeSignature};
 use num_traits::ToPrimitive;
 
-use crate::midi::{Interval, Pitch};
+use crate::midi::{Interval, Pitch, Tuning};
 
 #[derive(Debug)]
 pub struct Canvas {
@@ -19,10 +19,16 @@ pub struct Canvas {
 
     pub unit_width: f64,
     pub pitch_height: f64,
+
+    pub beat_width: f64,
+    pub beats_per_bar: usize,
 }
 
 impl Canvas {
-    fn new() -> Self {
+    fn new(time: TimeSignature) -> Self {
+        let unit_width = 80.0;
+        let beat_width = time.beat_length().to_f64().expect("beat lengths are small") * unit_width;
+
         Self {
             rectangles: Vec::new(),
             pitches: Vec::new(),
@@ -33,8 +39,11 @@ impl Canvas {
             min_y: 0.0,
             max_y: 0.0,
 
-            unit_width: 80.0,
+            unit_width,
             pitch_height: 10.0,
+
+            beat_width,
+            beats_per_bar: usize::from(time.beats),
         }
     }
 
@@ -86,24 +95,35 @@ pub struct Label {
     pub y: f64,
 }
 
-pub fn draw<Id>(notes: impl Iterator<Item = (Pitch, Span<Id>, Time, Length)>) -> Canvas {
-    let mut canvas = Canvas::new();
+/// Draw the given notes, placing every note by how high the given tuning
+/// plays it.
+pub fn draw<Id>(
+    notes: impl Iterator<Item = Played<Pitch, Id>>,
+    time: TimeSignature,
+    tuning: &Tuning,
+) -> Canvas {
+    let mut canvas = Canvas::new(time);
 
     let mut pitches = HashSet::new();
 
-    for (pitch, _, time, length) in notes {
+    for played in notes {
+        let pitch = played.note;
+        let Some(cents) = tuning.cents(pitch) else {
+            continue;
+        };
+
         pitches.insert(pitch);
 
-        let x = time
+        let x = played
+            .start
             .0
             .to_f64()
             .expect("time values are not unreasonably big")
             * canvas.unit_width;
 
-        let y = Pitch::A4.offset(&pitch);
-        let y = canvas.a4 + y as f64 * canvas.pitch_height;
+        let y = canvas.a4 - cents / 100.0 * canvas.pitch_height;
 
-        let width = match length {
+        let width = match played.length {
             Length::Bounded(length) => {
                 length
                     .to_f64()
@@ -126,10 +146,10 @@ pub fn draw<Id>(notes: impl Iterator<Item = (Pitch, Span<Id>, Time, Length)>) ->
     let highest = *pitches.iter().max().unwrap_or(&Pitch::A4);
 
     for pitch in cmaj_between(deepest, highest) {
-        let y = Pitch::A4.offset(&pitch);
-        let y = canvas.a4 + y as f64 * canvas.pitch_height;
-
-        canvas.pitches.push(Label { pitch, y });
+        if let Some(cents) = tuning.cents(pitch) {
+            let y = canvas.a4 - cents / 100.0 * canvas.pitch_height;
+            canvas.pitches.push(Label { pitch, y });
+        }
     }
 
     canvas.update_bounds();
@@ -1,21 +1,25 @@
 use std::fs::{self};
 use std::path::Path;
 
-use mm_eval::span::Span;
-use mm_eval::{Length, Time};
+use mm_eval::eval::Played;
+use mm_eval::{Metadata, TimeSignature};
 
-use crate::midi::Pitch;
+use crate::midi::{Pitch, Tuning};
 
 mod draw;
 mod render;
 
 pub fn write<Id>(
-    notes: impl Iterator<Item = (Pitch, Span<Id>, Time, Length)>,
+    notes: impl Iterator<Item = Played<Pitch, Id>>,
+    time: TimeSignature,
+    tuning: &Tuning,
+    metadata: &Metadata,
+    doc: Option<&str>,
     to: impl AsRef<Path>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let canvas = draw::draw(notes);
+    let canvas = draw::draw(notes, time, tuning);
     let mut svg = String::new();
-    render::render(canvas, &mut svg)?;
+    render::render(canvas, metadata, doc, &mut svg)?;
 
     fs::write(to, svg)?;
     Ok(())
@@ -3,6 +3,7 @@ use std::fmt;
 use hypermelon::build::raw;
 use hypermelon::prelude::Elem;
 use hypermelon::{attrs, build};
+use mm_eval::Metadata;
 
 use super::draw::Canvas;
 
@@ -25,22 +26,82 @@ const STYLE: &str = r#"
     }
 "#;
 
-pub fn render(canvas: Canvas, w: impl fmt::Write) -> fmt::Result {
+/// Render the canvas, headed by the title and composer from the metadata and
+/// the doc comment of the melody, if any.
+pub fn render(
+    canvas: Canvas,
+    metadata: &Metadata,
+    doc: Option<&str>,
+    w: impl fmt::Write,
+) -> fmt::Result {
     let width = canvas.max_x;
     let height = canvas.max_y - canvas.min_y;
 
     let label_size = canvas.pitch_height;
     let label_width = label_size * 2.0;
 
+    let heading = match (&metadata.title, &metadata.composer) {
+        (Some(title), Some(composer)) => Some(format!("{title} by {composer}")),
+        (Some(title), None) => Some(title.clone()),
+        (None, Some(composer)) => Some(format!("by {composer}")),
+        (None, None) => None,
+    };
+
+    // The heading and the doc comment are shown above the notes, which are
+    // moved down to make room.
+    let mut lines = Vec::new();
+    let mut header_height = 0.0;
+
+    let heading_line = heading
+        .iter()
+        .map(|heading| (heading.as_str(), label_size * 1.2));
+    let doc_lines = doc
+        .into_iter()
+        .flat_map(str::lines)
+        .map(|line| (line, label_size));
+
+    for (line, size) in heading_line.chain(doc_lines) {
+        header_height += size * 1.25;
+        lines.push((line, size, header_height));
+    }
+
+    if !lines.is_empty() {
+        header_height += label_size * 0.5;
+    }
+
     let svg = build::elem("svg").with(attrs!(
         ("xmlns", "http://www.w3.org/2000/svg"),
         ("width", width + label_width),
-        ("height", height)
+        ("height", height + header_height)
     ));
 
     let style = build::elem("style").append(STYLE);
 
-    let cols = (width / canvas.unit_width) as usize;
+    let title = heading
+        .iter()
+        .map(|heading| build::elem("title").append(raw(heading)));
+
+    let description: Vec<_> = [&metadata.description, &metadata.copyright]
+        .into_iter()
+        .flatten()
+        .map(String::as_str)
+        .collect();
+
+    let description =
+        (!description.is_empty()).then(|| build::elem("desc").append(raw(description.join("\n"))));
+
+    let header = lines.into_iter().map(|(line, size, y)| {
+        build::elem("text")
+            .with(attrs!(
+                ("x", label_width),
+                ("y", y),
+                ("font-size", size),
+                ("class", "header")
+            ))
+            .append(raw(line))
+    });
+
+    let beats = (width / canvas.beat_width).ceil() as usize;
     let rows = ((height + canvas.min_y) / canvas.pitch_height) as usize;
 
     let labels = canvas.pitches.into_iter().map(|label| {
@@ -57,9 +118,15 @@ pub fn render(canvas: Canvas, w: impl fmt::Write) -> fmt::Result {
         .with(("class", "labels"))
         .append(build::from_iter(labels));
 
-    let verticals = (0..=4 * cols).map(|i| {
-        let class = if i % 4 == 0 { "major" } else { "minor" };
-        let x = canvas.unit_width * i as f64 / 4.0 + label_width;
+    // A major line starts every bar, and a minor line each of its other beats.
+    let verticals = (0..=beats).map(|i| {
+        let class = if i % canvas.beats_per_bar == 0 {
+            "major"
+        } else {
+            "minor"
+        };
+
+        let x = canvas.beat_width * i as f64 + label_width;
 
         build::single("line").with(attrs!(
             ("x1", x),
@@ -99,11 +166,18 @@ pub fn render(canvas: Canvas, w: impl fmt::Write) -> fmt::Result {
             },
         )));
 
-    let all = svg
-        .append(style)
+    let body = build::elem("g")
+        .with(("transform", format!("translate(0, {header_height})")))
         .append(labels)
         .append(grid)
         .append(rectangles);
 
+    let all = svg
+        .append(build::from_iter(title))
+        .append(build::from_iter(description.into_iter()))
+        .append(style)
+        .append(build::from_iter(header))
+        .append(body);
+
     hypermelon::render(all, w)
 }
@@ -1,9 +1,9 @@
 use std::collections::HashMap;
 use std::io;
-use std::path::Path;
 
 use ariadne::{Cache, Label, Report, ReportKind, Source};
-use mm_eval::{check, parse, Error, Names};
+use mm_eval::check::Warning;
+use mm_eval::{check, link, parse, Error, Length, Names};
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct SourceId(usize);
@@ -27,7 +27,11 @@ impl Sources {
         id
     }
 
-    pub fn cache(&self) -> SourceCache {
+    pub fn get(&self, id: SourceId) -> &str {
+        &self.sources[id.0].1
+    }
+
+    pub fn cache(&self) -> SourceCache<'_> {
         SourceCache::new(self)
     }
 }
@@ -38,18 +42,70 @@ pub struct SourceCache<'src> {
 }
 
 impl<'src> SourceCache<'src> {
-    pub fn iter(&self) -> impl Iterator<Item = (SourceId, &'src Path, &'src str)> {
-        self.sources
-            .sources
-            .iter()
-            .enumerate()
-            .map(|(index, (path, source))| (SourceId(index), Path::new(path), source.as_str()))
-    }
-
     pub fn report(&self, w: impl io::Write, names: &Names, e: Error<SourceId>) -> io::Result<()> {
         make_report(names, e).write(self, w)
     }
 
+    pub fn warn(&self, w: impl io::Write, warning: Warning<SourceId>) -> io::Result<()> {
+        make_warning(warning).write(self, w)
+    }
+
+    /// Warn about a transposition which overflowed while playing a melody.
+    pub fn overflow(&self, w: impl io::Write, at: mm_eval::span::Span<SourceId>) -> io::Result<()> {
+        Report::build(ReportKind::Warning, at.source, at.start)
+            .with_message("Transposition is too large to play")
+            .with_label(Label::new(Span(at)).with_message("nothing in here is played"))
+            .finish()
+            .write(self, w)
+    }
+
+    /// Report that no exported melody of the given source has the name asked
+    /// for, suggesting the closest one.
+    pub fn unknown_entry(
+        &self,
+        w: impl io::Write,
+        source: SourceId,
+        entry: &str,
+        public: &[(&str, mm_eval::span::Span<SourceId>)],
+    ) -> io::Result<()> {
+        let spellings: Vec<_> = public.iter().map(|(spelling, _)| *spelling).collect();
+        let closest = closest(entry, &spellings);
+
+        let mut report = Report::<Span>::build(ReportKind::Error, source, 0)
+            .with_message(format!("No exported melody named '{entry}'"))
+            .with_labels(public.iter().map(|(spelling, at)| {
+                let label = Label::new(Span(*at));
+                if Some(*spelling) == closest {
+                    label.with_message("did you mean this one?")
+                } else {
+                    label
+                }
+            }))
+            .with_note(format!("Exported melodies are {}", list(&spellings)));
+
+        if let Some(closest) = closest {
+            report = report.with_help(format!("Use `--entry {closest}`"));
+        }
+
+        report.finish().write(self, w)
+    }
+
+    /// Report that the given source exports several melodies, none of which
+    /// was asked for.
+    pub fn multiple_entries(
+        &self,
+        w: impl io::Write,
+        source: SourceId,
+        public: &[(&str, mm_eval::span::Span<SourceId>)],
+    ) -> io::Result<()> {
+        Report::<Span>::build(ReportKind::Error, source, 0)
+            .with_message("Multiple exported melodies")
+            .with_labels(public.iter().map(|(_, at)| Label::new(Span(*at))))
+            .with_help("Pick one with `--entry NAME`, or write every one with `--all`")
+            .finish()
+            .write(self, w)
+    }
+
     fn new(sources: &'src Sources) -> Self {
         let map: HashMap<_, _> = sources
             .sources
@@ -109,8 +165,30 @@ impl ariadne::Span for Span {
     }
 }
 
-fn make_report(names: &Names, e: Error<SourceId>) -> Report<Span> {
+fn make_report(names: &Names, e: Error<SourceId>) -> Report<'_, Span> {
     match e {
+        Error::Parse(parse::Error::ExpectedCount(at)) => {
+            Report::build(ReportKind::Error, at.source, at.start)
+                .with_message("Expected a number of repetitions or 'oo'")
+                .with_label(Label::new(Span(at)))
+                .finish()
+        }
+
+        Error::Parse(parse::Error::ExpectedDegree(at)) => {
+            Report::build(ReportKind::Error, at.source, at.start)
+                .with_message("Scale degrees must be whole numbers from 1 and up")
+                .with_label(Label::new(Span(at)))
+                .finish()
+        }
+
+        Error::Parse(parse::Error::ExpectedDynamic(at)) => {
+            Report::build(ReportKind::Error, at.source, at.start)
+                .with_message("Expected a dynamic marking")
+                .with_label(Label::new(Span(at)))
+                .with_note("Use a velocity from `@1` to `@127` or a marking from `ppp` to `fff`")
+                .finish()
+        }
+
         Error::Parse(parse::Error::ExpectedEqual(at)) => {
             Report::build(ReportKind::Error, at.source, at.start)
                 .with_message("Expected equals sign '='")
@@ -139,6 +217,102 @@ fn make_report(names: &Names, e: Error<SourceId>) -> Report<Span> {
                 .finish()
         }
 
+        Error::Parse(parse::Error::ExpectedInterval(at)) => {
+            Report::build(ReportKind::Error, at.source, at.start)
+                .with_message("Expected a number of semitones or an interval like 'm3'")
+                .with_label(Label::new(Span(at)))
+                .finish()
+        }
+
+        Error::Parse(parse::Error::ExpectedMode(at)) => {
+            Report::build(ReportKind::Error, at.source, at.start)
+                .with_message("Expected a mode like 'major' or 'dorian'")
+                .with_label(Label::new(Span(at)))
+                .finish()
+        }
+
+        Error::Parse(parse::Error::ExpectedPath(at)) => {
+            Report::build(ReportKind::Error, at.source, at.start)
+                .with_message("Expected a path to import")
+                .with_label(Label::new(Span(at)))
+                .finish()
+        }
+
+        Error::Parse(parse::Error::ExpectedTempo(at)) => {
+            Report::build(ReportKind::Error, at.source, at.start)
+                .with_message("Expected a tempo in beats per minute")
+                .with_label(Label::new(Span(at)))
+                .with_note("Use a tempo like `tempo(96)`, or `tempo(80..96)` for a gradual change")
+                .finish()
+        }
+
+        Error::Parse(parse::Error::ExpectedInstrument(at)) => {
+            Report::build(ReportKind::Error, at.source, at.start)
+                .with_message("Expected a General MIDI instrument from 1 to 128")
+                .with_label(Label::new(Span(at)))
+                .with_note("Use an instrument like `instrument(33)` for an acoustic bass")
+                .finish()
+        }
+
+        Error::Parse(parse::Error::ExpectedChannel(at)) => {
+            Report::build(ReportKind::Error, at.source, at.start)
+                .with_message("Expected a channel from 'ch1' to 'ch16'")
+                .with_label(Label::new(Span(at)))
+                .finish()
+        }
+
+        Error::Parse(parse::Error::ExpectedWeight(at)) => {
+            Report::build(ReportKind::Error, at.source, at.start)
+                .with_message("Weights of choices must be whole numbers from 1 and up")
+                .with_label(Label::new(Span(at)))
+                .finish()
+        }
+
+        Error::Parse(parse::Error::ExpectedSlice(at)) => {
+            Report::build(ReportKind::Error, at.source, at.start)
+                .with_message("Expected a slice like '[4..12]' or '[..8]'")
+                .with_label(Label::new(Span(at)))
+                .with_note("The end of a slice must come after its start")
+                .finish()
+        }
+
+        Error::Parse(parse::Error::ExpectedLength(at)) => {
+            Report::build(ReportKind::Error, at.source, at.start)
+                .with_message("Expected a length to fit to like '3' or '3/2'")
+                .with_label(Label::new(Span(at)))
+                .with_note("The length must be greater than zero")
+                .finish()
+        }
+
+        Error::Parse(parse::Error::ExpectedTimeSignature(at)) => {
+            Report::build(ReportKind::Error, at.source, at.start)
+                .with_message("Expected a time signature like '3/4' or '6/8'")
+                .with_label(Label::new(Span(at)))
+                .with_note("The bottom number must be a power of two")
+                .finish()
+        }
+
+        Error::Parse(parse::Error::ExpectedBars(at)) => {
+            Report::build(ReportKind::Error, at.source, at.start)
+                .with_message("Expected a number of bars like '4 bars'")
+                .with_label(Label::new(Span(at)))
+                .finish()
+        }
+
+        Error::Parse(parse::Error::UnexpectedCharacter(at)) => {
+            Report::build(ReportKind::Error, at.source, at.start)
+                .with_message("Unexpected character")
+                .with_label(Label::new(Span(at)))
+                .finish()
+        }
+
+        Error::Parse(parse::Error::UnexpectedToken(at)) => {
+            Report::build(ReportKind::Error, at.source, at.start)
+                .with_message("Unexpected token")
+                .with_label(Label::new(Span(at)).with_message("skipped up to the next definition"))
+                .finish()
+        }
+
         Error::Parse(parse::Error::DivisionByZero(at)) => {
             Report::build(ReportKind::Error, at.source, at.start)
                 .with_message("Length factor cannot be divided by zero")
@@ -146,6 +320,21 @@ fn make_report(names: &Names, e: Error<SourceId>) -> Report<Span> {
                 .finish()
         }
 
+        Error::Parse(parse::Error::Overflow(at)) => {
+            Report::build(ReportKind::Error, at.source, at.start)
+                .with_message("Number is too large")
+                .with_label(Label::new(Span(at)))
+                .finish()
+        }
+
+        Error::Parse(parse::Error::UnknownMetadata(at)) => {
+            Report::build(ReportKind::Error, at.source, at.start)
+                .with_message("Unknown metadata field")
+                .with_label(Label::new(Span(at)))
+                .with_note("Use `title`, `composer`, `copyright` or `description`")
+                .finish()
+        }
+
         Error::Parse(parse::Error::Redefinition { previous, new }) => {
             Report::build(ReportKind::Error, new.source, new.start)
                 .with_message("Name cannot be redefined")
@@ -154,6 +343,14 @@ fn make_report(names: &Names, e: Error<SourceId>) -> Report<Span> {
                 .finish()
         }
 
+        Error::Parse(parse::Error::ShadowedParameter { parameter, new }) => {
+            Report::build(ReportKind::Error, new.source, new.start)
+                .with_message("Local definition cannot shadow a parameter")
+                .with_label(Label::new(Span(new)))
+                .with_label(Label::new(Span(parameter)).with_message("parameter defined here"))
+                .finish()
+        }
+
         Error::Parse(parse::Error::UnclosedParen { opener, at }) => {
             Report::build(ReportKind::Error, opener.source, opener.start)
                 .with_message("Unclosed parenthesis")
@@ -164,6 +361,24 @@ fn make_report(names: &Names, e: Error<SourceId>) -> Report<Span> {
                 .finish()
         }
 
+        Error::Parse(parse::Error::UnclosedBrace { opener, at }) => {
+            Report::build(ReportKind::Error, opener.source, opener.start)
+                .with_message("Unclosed brace")
+                .with_label(Label::new(Span(opener)).with_message("this opening brace"))
+                .with_label(Label::new(Span(at)).with_message("expected a closing brace '}' here"))
+                .finish()
+        }
+
+        Error::Parse(parse::Error::UnclosedBracket { opener, at }) => {
+            Report::build(ReportKind::Error, opener.source, opener.start)
+                .with_message("Unclosed bracket")
+                .with_label(Label::new(Span(opener)).with_message("this opening bracket"))
+                .with_label(
+                    Label::new(Span(at)).with_message("expected a closing bracket ']' here"),
+                )
+                .finish()
+        }
+
         Error::Check(check::Error::NoPublicNames(at)) => {
             Report::build(ReportKind::Error, at.source, at.start)
                 .with_message("No exported melody")
@@ -171,6 +386,13 @@ fn make_report(names: &Names, e: Error<SourceId>) -> Report<Span> {
                 .finish()
         }
 
+        Error::Link(link::Error::UnknownImport(at)) => {
+            Report::build(ReportKind::Error, at.source, at.start)
+                .with_message("Could not find the imported file")
+                .with_label(Label::new(Span(at)))
+                .finish()
+        }
+
         Error::Check(check::Error::UnknownName(at, name)) => {
             Report::build(ReportKind::Error, at.source, at.start)
                 .with_message(format!("Unknown name '{}'", names.get(&name)))
@@ -178,6 +400,43 @@ fn make_report(names: &Names, e: Error<SourceId>) -> Report<Span> {
                 .finish()
         }
 
+        Error::Check(check::Error::UnknownChordQuality(at)) => {
+            Report::build(ReportKind::Error, at.source, at.start)
+                .with_message("Unknown chord quality")
+                .with_label(Label::new(Span(at)))
+                .with_note(
+                    "A quality is `m`, `min`, `maj`, `M`, `mmaj`, `mM`, `dim`, `aug`, `ø` or \
+                     `dom`, then `6`, `7`, `9`, `11` or `13`, then `sus2`, `sus4` or `sus`, then \
+                     any of `add2`, `add4`, `add6`, `add9`, `add11`, `add13` and alterations \
+                     like `b5`, `#9` or `b13`, as in `Cmaj7`, `Dm7b5` or `G7sus4`",
+                )
+                .finish()
+        }
+
+        Error::Check(check::Error::ArgumentCount {
+            at,
+            expected,
+            actual,
+        }) => Report::build(ReportKind::Error, at.source, at.start)
+            .with_message(format!("Expected {expected} arguments, got {actual}"))
+            .with_label(Label::new(Span(at)))
+            .finish(),
+
+        Error::Check(check::Error::RecursiveArguments(at)) => {
+            Report::build(ReportKind::Error, at.source, at.start)
+                .with_message("Recursive call changes its arguments")
+                .with_label(Label::new(Span(at)))
+                .with_note(
+                    "A recursive call must pass along the parameters of its definition unchanged \
+                     and in the same order",
+                )
+                .with_help(
+                    "To swap arguments, recurse through a second definition which uses them the \
+                     other way around",
+                )
+                .finish()
+        }
+
         Error::Check(check::Error::UnboundedNotLast(at)) => {
             Report::build(ReportKind::Error, at.source, at.start)
                 .with_message("Unbounded melody must be last in a sequence")
@@ -185,6 +444,34 @@ fn make_report(names: &Names, e: Error<SourceId>) -> Report<Span> {
                 .finish()
         }
 
+        Error::Check(check::Error::UnboundedReverse(at)) => {
+            Report::build(ReportKind::Error, at.source, at.start)
+                .with_message("Unbounded melody cannot be reversed")
+                .with_label(Label::new(Span(at)))
+                .finish()
+        }
+
+        Error::Check(check::Error::UnboundedFit(at)) => {
+            Report::build(ReportKind::Error, at.source, at.start)
+                .with_message("Unbounded melody cannot be fit to a length")
+                .with_label(Label::new(Span(at)))
+                .finish()
+        }
+
+        Error::Check(check::Error::EmptyFit(at)) => {
+            Report::build(ReportKind::Error, at.source, at.start)
+                .with_message("Melody without length cannot be fit to a length")
+                .with_label(Label::new(Span(at)))
+                .finish()
+        }
+
+        Error::Check(check::Error::UnboundedTempoChange(at)) => {
+            Report::build(ReportKind::Error, at.source, at.start)
+                .with_message("Tempo cannot change gradually over an unbounded melody")
+                .with_label(Label::new(Span(at)))
+                .finish()
+        }
+
         Error::Check(check::Error::UnfoundedRecursion(at)) => {
             Report::build(ReportKind::Error, at.source, at.start)
                 .with_message("Unfounded recursion")
@@ -193,3 +480,74 @@ fn make_report(names: &Names, e: Error<SourceId>) -> Report<Span> {
         }
     }
 }
+
+fn make_warning(warning: Warning<SourceId>) -> Report<'static, Span> {
+    match warning {
+        Warning::BarCount {
+            at,
+            bars,
+            expected,
+            actual,
+        } => {
+            let plural = if bars == 1 { "" } else { "s" };
+            Report::build(ReportKind::Warning, at.source, at.start)
+                .with_message(format!(
+                    "Expected {bars} bar{plural} (length {}) but this lasts {}",
+                    show_length(&expected),
+                    show_length(&actual),
+                ))
+                .with_label(Label::new(Span(at)))
+                .finish()
+        }
+    }
+}
+
+/// Quote and list the given names, like `'a', 'b' and 'c'`.
+fn list(names: &[&str]) -> String {
+    let quoted: Vec<_> = names.iter().map(|name| format!("'{name}'")).collect();
+    match quoted.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} and {last}", rest.join(", ")),
+        None => String::new(),
+    }
+}
+
+/// Get the name among `names` which is closest to `name`, if it is close
+/// enough to have been meant.
+fn closest<'a>(name: &str, names: &[&'a str]) -> Option<&'a str> {
+    let limit = (name.chars().count() / 2).max(1);
+
+    names
+        .iter()
+        .map(|candidate| (distance(name, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Get the number of characters which have to be inserted, removed or
+/// replaced to turn `a` into `b`.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, x) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+
+        for (j, y) in b.iter().enumerate() {
+            let replaced = diagonal + usize::from(x != *y);
+            diagonal = row[j + 1];
+            row[j + 1] = replaced.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+fn show_length(length: &Length) -> String {
+    match length {
+        Length::Bounded(length) => length.to_string(),
+        Length::Unbounded => String::from("forever"),
+    }
+}
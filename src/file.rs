@@ -1,15 +1,91 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::{fs, io};
 
-use crate::error::Sources;
+use mm_eval::parse::Parser;
+use mm_eval::{implicit, Arena, Error, Names};
+use mm_media::midi::Pitch;
 
-pub fn get_sources(paths: impl IntoIterator<Item = PathBuf>) -> io::Result<Sources> {
-    let mut sources = Sources::new();
+use crate::error::{SourceId, Sources};
 
-    for path in paths {
-        let content = fs::read_to_string(&path)?;
-        sources.add(path.to_string_lossy().into_owned(), content);
+pub type Program<'a> = implicit::Program<Pitch, SourceId, &'a Arena<'a, Pitch, SourceId>>;
+
+/// A set of parsed sources, along with every source they import.
+pub struct Modules<'a> {
+    pub sources: Sources,
+    pub entries: Vec<(SourceId, PathBuf)>,
+    pub programs: HashMap<SourceId, Program<'a>>,
+    pub errors: Vec<Error<SourceId>>,
+
+    loaded: HashMap<PathBuf, SourceId>,
+    imports: HashMap<(SourceId, String), SourceId>,
+}
+
+impl<'a> Modules<'a> {
+    /// Load and parse the given files and every file they import. Import paths
+    /// are relative to the file they occur in. Files which cannot be read are
+    /// an error if they are given directly, while imports of them are left
+    /// unresolved.
+    pub fn load(
+        alloc: &mut &'a Arena<'a, Pitch, SourceId>,
+        names: &mut Names,
+        paths: impl IntoIterator<Item = PathBuf>,
+    ) -> io::Result<Self> {
+        let mut modules = Self {
+            sources: Sources::new(),
+            entries: Vec::new(),
+            programs: HashMap::new(),
+            errors: Vec::new(),
+            loaded: HashMap::new(),
+            imports: HashMap::new(),
+        };
+
+        for path in paths {
+            let id = modules.load_file(alloc, names, &path)?;
+            modules.entries.push((id, path));
+        }
+
+        Ok(modules)
     }
 
-    Ok(sources)
+    /// Get the source imported by `path` in the source `from`.
+    pub fn resolve(&self, from: &SourceId, path: &str) -> Option<SourceId> {
+        self.imports.get(&(*from, path.into())).copied()
+    }
+
+    fn load_file(
+        &mut self,
+        alloc: &mut &'a Arena<'a, Pitch, SourceId>,
+        names: &mut Names,
+        path: &Path,
+    ) -> io::Result<SourceId> {
+        let key = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        if let Some(id) = self.loaded.get(&key) {
+            return Ok(*id);
+        }
+
+        let content = fs::read_to_string(path)?;
+        let id = self
+            .sources
+            .add(path.to_string_lossy().into_owned(), content);
+        self.loaded.insert(key, id);
+
+        let program = match Parser::parse(alloc, names, id, self.sources.get(id)) {
+            Ok(program) => program,
+            Err(es) => {
+                self.errors.extend(es.into_iter().map(Into::into));
+                return Ok(id);
+            }
+        };
+
+        let base = path.parent().unwrap_or(Path::new(""));
+        for import in program.imports.iter() {
+            if let Ok(other) = self.load_file(alloc, names, &base.join(&import.path)) {
+                self.imports.insert((id, import.path.clone()), other);
+            }
+        }
+
+        self.programs.insert(id, program);
+        Ok(id)
+    }
 }
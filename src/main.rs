@@ -1,14 +1,17 @@
 mod error;
 mod file;
 
+use std::fs;
 use std::io::{stderr, stdin};
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 
-use error::SourceId;
-use mm_eval::eval::Evaluator;
-use mm_eval::{Arena, Names};
-use mm_media::midi::Pitch;
+use error::{SourceId, Sources};
+use file::Modules;
+use mm_eval::eval::{Evaluator, Event};
+use mm_eval::span::Span;
+use mm_eval::{Arena, Metadata, Names, TimeSignature};
+use mm_media::midi::{Pitch, Tuning};
 use mm_media::{midi, svg};
 use notify_debouncer_mini::notify::RecursiveMode;
 use notify_debouncer_mini::{new_debouncer, DebounceEventResult};
@@ -17,14 +20,20 @@ const MAX_DEPTH: usize = 20;
 const MAX_NOTES: usize = 1000;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let (args, paths) = Args::new(std::env::args().skip(1));
+    let mut args = std::env::args().skip(1).peekable();
+    if args.next_if(|arg| arg == "fmt").is_some() {
+        return fmt(args);
+    }
+
+    let (args, paths) = Args::new(args)?;
+    let tuning = args.tuning()?;
 
     if args.watch {
         let mut debouncer = new_debouncer(
             Duration::from_millis(500),
             None,
             move |ev: DebounceEventResult| match ev {
-                Ok(e) => compile(&args, e.into_iter().map(|e| e.path)).unwrap(),
+                Ok(e) => compile(&args, &tuning, e.into_iter().map(|e| e.path)).unwrap(),
                 Err(e) => println!("watch error {e:?}"),
             },
         )?;
@@ -38,23 +47,39 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         stdin().read_line(&mut String::new())?;
         Ok(())
     } else {
-        compile(&args, paths)
+        compile(&args, &tuning, paths)
     }
 }
 
 fn compile(
     args: &Args,
+    tuning: &Tuning,
     paths: impl IntoIterator<Item = PathBuf>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let mut alloc = &Arena::new();
+    let mut names = Names::new();
 
-    let sources = file::get_sources(paths)?;
-    let sources = sources.cache();
+    let modules = Modules::load(&mut alloc, &mut names, paths)?;
+    let sources = modules.sources.cache();
 
-    let mut names = Names::new();
+    if !modules.errors.is_empty() {
+        let mut writer = stderr().lock();
 
-    for (id, path, source) in sources.iter() {
-        let mut program = match mm_eval::compile(&mut alloc, &mut names, id, source) {
+        for e in modules.errors {
+            sources.report(&mut writer, &names, e).unwrap();
+        }
+
+        return Ok(());
+    }
+
+    for (id, path) in modules.entries.iter() {
+        let mut program = match mm_eval::compile_linked(
+            &mut alloc,
+            &mut names,
+            id,
+            &modules.programs,
+            |from, path| modules.resolve(from, path),
+        ) {
             Ok(program) => program,
             Err(es) => {
                 let mut writer = stderr().lock();
@@ -67,37 +92,187 @@ fn compile(
             }
         };
 
-        if program.public.len() != 1 {
-            eprintln!("Multiple possible entrypoints. Skipping.");
-            continue;
+        if !program.warnings.is_empty() {
+            let mut writer = stderr().lock();
+
+            for warning in std::mem::take(&mut program.warnings) {
+                sources.warn(&mut writer, warning).unwrap();
+            }
         }
 
-        let entry = program.public.pop().unwrap();
-        let eval = Evaluator::new(&program.defs, entry).with_max_depth(MAX_DEPTH);
+        let public: Vec<_> = program
+            .public
+            .iter()
+            .map(|name| (*name, names.get(name), program.spans[name]))
+            .collect();
 
-        if args.make_midi {
-            write(Kind::Midi, path, &eval)?;
-        }
+        let spans: Vec<_> = public
+            .iter()
+            .map(|(_, spelling, at)| (*spelling, *at))
+            .collect();
+
+        // Every exported melody is written to a file named after it, unless
+        // only one of them is written.
+        let entries: Vec<_> = match (&args.entry, args.all) {
+            (_, true) => public
+                .iter()
+                .map(|(name, spelling, _)| (*name, Some(*spelling)))
+                .collect(),
+
+            (Some(entry), false) => {
+                match public.iter().find(|(_, spelling, _)| spelling == entry) {
+                    Some((name, _, _)) => vec![(*name, None)],
+                    None => {
+                        sources
+                            .unknown_entry(stderr().lock(), *id, entry, &spans)
+                            .unwrap();
+                        continue;
+                    }
+                }
+            }
+
+            (None, false) if public.len() == 1 => vec![(public[0].0, None)],
+            (None, false) => {
+                sources
+                    .multiple_entries(stderr().lock(), *id, &spans)
+                    .unwrap();
+                continue;
+            }
+        };
 
-        if args.make_svg {
-            write(Kind::Svg, path, &eval)?;
+        for (entry, suffix) in entries {
+            let mut eval = Evaluator::new(&program.defs, entry)
+                .with_max_depth(MAX_DEPTH)
+                .with_seed(args.seed);
+            if let Some(tempo) = program.tempo.clone() {
+                eval = eval.with_tempo(tempo);
+            }
+
+            let time = program.time.unwrap_or_default();
+            let doc = program.docs.get(&entry).map(String::as_str);
+
+            // Every output plays the same notes, so they overflow in the same
+            // places.
+            let mut overflows = Vec::new();
+
+            for (kind, make) in [(Kind::Midi, args.make_midi), (Kind::Svg, args.make_svg)] {
+                if make {
+                    let out = output(path, suffix, kind);
+                    overflows = write(kind, &out, &eval, time, tuning, &program.metadata, doc)?;
+                }
+            }
+
+            let mut writer = stderr().lock();
+            for at in overflows {
+                sources.overflow(&mut writer, at).unwrap();
+            }
         }
     }
 
     Ok(())
 }
 
+/// Get the file to write an output of the given kind for the source at
+/// `path` to, like `piece.mid`, or `piece.intro.mid` for the entry `intro`.
+fn output(path: &Path, entry: Option<&str>, kind: Kind) -> PathBuf {
+    match entry {
+        Some(entry) => path.with_extension(format!("{entry}.{}", kind.extension())),
+        None => path.with_extension(kind.extension()),
+    }
+}
+
+/// Write the melody to a file of the given kind, and get where it overflows.
 fn write<'a>(
     kind: Kind,
-    path: &Path,
+    out: &Path,
     eval: &Evaluator<Pitch, SourceId, &'a Arena<'a, Pitch, SourceId>>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let out = path.with_extension(kind.extension());
-    let notes = eval.iter().take(MAX_NOTES);
+    time: TimeSignature,
+    tuning: &Tuning,
+    metadata: &Metadata,
+    doc: Option<&str>,
+) -> Result<Vec<Span<SourceId>>, Box<dyn std::error::Error>> {
+    let mut overflows = Vec::new();
+    let events = eval.events().inspect(|event| {
+        // Repeated melodies overflow in the same place every time.
+        match event {
+            Event::Overflow(at) if !overflows.contains(at) => overflows.push(*at),
+            _ => {}
+        }
+    });
 
     match kind {
-        Kind::Midi => midi::write(notes, &out)?,
-        Kind::Svg => svg::write(notes, &out)?,
+        Kind::Midi => {
+            // Only notes count towards the limit, such that changes of tempo
+            // between them are kept.
+            let mut notes = 0;
+            let events = events.take_while(|event| {
+                notes += usize::from(matches!(event, Event::Note(_)));
+                notes <= MAX_NOTES
+            });
+
+            midi::write(events, time, tuning, metadata, out)?
+        }
+        Kind::Svg => svg::write(
+            events
+                .filter_map(|event| match event {
+                    Event::Note(played) => Some(played),
+                    _ => None,
+                })
+                .take(MAX_NOTES),
+            time,
+            tuning,
+            metadata,
+            doc,
+            out,
+        )?,
+    }
+
+    Ok(overflows)
+}
+
+/// Format the given files in place, or only list the ones which are not
+/// formatted with `--check`.
+fn fmt(args: impl IntoIterator<Item = String>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut check = false;
+    let mut paths = Vec::new();
+
+    for arg in args {
+        match arg.as_str() {
+            "--check" => check = true,
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    let mut sources = Sources::new();
+    let mut unformatted = 0;
+
+    for path in paths {
+        let content = fs::read_to_string(&path)?;
+        let id = sources.add(path.to_string_lossy().into_owned(), content);
+        let source = sources.get(id);
+
+        match mm_eval::fmt::format::<Pitch, _>(id, source) {
+            Ok(formatted) if formatted == source => {}
+            Ok(_) if check => {
+                println!("{}", path.display());
+                unformatted += 1;
+            }
+            Ok(formatted) => fs::write(&path, formatted)?,
+            Err(es) => {
+                let sources = sources.cache();
+                let mut writer = stderr().lock();
+
+                for e in es {
+                    sources.report(&mut writer, &Names::new(), e.into())?;
+                }
+
+                return Err(format!("{} could not be parsed", path.display()).into());
+            }
+        }
+    }
+
+    if unformatted > 0 {
+        return Err(format!("{unformatted} files are not formatted").into());
     }
 
     Ok(())
@@ -107,25 +282,77 @@ struct Args {
     make_midi: bool,
     make_svg: bool,
     watch: bool,
+    seed: u64,
+    scale: Option<PathBuf>,
+    keyboard: Option<PathBuf>,
+    edo: Option<usize>,
+    /// The name of the exported melody to write, if there are several.
+    entry: Option<String>,
+    /// Whether to write every exported melody to a file of its own.
+    all: bool,
 }
 
 impl Args {
-    pub fn new(args: impl IntoIterator<Item = String>) -> (Self, Vec<PathBuf>) {
+    pub fn new(args: impl IntoIterator<Item = String>) -> Result<(Self, Vec<PathBuf>), String> {
         let mut make_midi = false;
         let mut make_svg = false;
         let mut watch = false;
+        let mut seed = 0;
+        let mut scale = None;
+        let mut keyboard = None;
+        let mut edo = None;
+        let mut entry = None;
+        let mut all = false;
 
         let mut paths = Vec::new();
 
-        for arg in args {
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
             match arg.as_str() {
                 "-m" | "--midi" => make_midi = true,
                 "-s" | "--svg" => make_svg = true,
                 "-w" | "--watch" => watch = true,
+                "-a" | "--all" => all = true,
+                "--entry" => {
+                    entry = Some(
+                        args.next()
+                            .ok_or("Expected the name of an exported melody after '--entry'")?,
+                    )
+                }
+                "--seed" => {
+                    seed = args
+                        .next()
+                        .and_then(|seed| seed.parse().ok())
+                        .ok_or("Expected a whole number after '--seed'")?
+                }
+                "--tuning" => {
+                    let path = args
+                        .next()
+                        .ok_or("Expected a Scala file after '--tuning'")?;
+                    scale = Some(PathBuf::from(path));
+                }
+                "--keyboard" => {
+                    let path = args
+                        .next()
+                        .ok_or("Expected a Scala keyboard mapping after '--keyboard'")?;
+                    keyboard = Some(PathBuf::from(path));
+                }
+                "--edo" => {
+                    edo = args
+                        .next()
+                        .and_then(|edo| edo.parse().ok())
+                        .filter(|edo| *edo > 0)
+                        .map(Some)
+                        .ok_or("Expected a positive number of divisions after '--edo'")?
+                }
                 _ => paths.push(PathBuf::from(arg)),
             }
         }
 
+        if entry.is_some() && all {
+            return Err("Expected either '--entry' or '--all'".into());
+        }
+
         if !(make_midi || make_svg) {
             make_midi = true;
         }
@@ -134,9 +361,39 @@ impl Args {
             make_midi,
             make_svg,
             watch,
+            seed,
+            scale,
+            keyboard,
+            edo,
+            entry,
+            all,
         };
 
-        (args, paths)
+        Ok((args, paths))
+    }
+
+    /// Load the tuning asked for, which is twelve-tone equal temperament if
+    /// there is none.
+    fn tuning(&self) -> Result<Tuning, String> {
+        let read = |path: &Path| {
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))
+        };
+
+        let tuning = match (&self.scale, self.edo) {
+            (Some(_), Some(_)) => return Err("Expected either '--tuning' or '--edo'".into()),
+            (Some(scale), None) => {
+                Tuning::parse(&read(scale)?).map_err(|e| format!("{}: {e}", scale.display()))?
+            }
+            (None, Some(edo)) => Tuning::equal(edo).expect("the number of divisions is positive"),
+            (None, None) => Tuning::default(),
+        };
+
+        match &self.keyboard {
+            Some(keyboard) => tuning
+                .with_keyboard(&read(keyboard)?)
+                .map_err(|e| format!("{}: {e}", keyboard.display())),
+            None => Ok(tuning),
+        }
     }
 }
 